@@ -18,6 +18,8 @@
 //! memory location where the data bytes are stored. This allows code to directly
 //! dereference data addresses without any overhead.
 
+use sayo_ast::Diagnostic;
+
 /// Magic bytes: "SAYO"
 pub const MAGIC: [u8; 4] = [0x53, 0x41, 0x59, 0x4F];
 
@@ -68,6 +70,16 @@ pub enum BinaryError {
     TextSectionTooLarge(u32),
 }
 
+impl BinaryError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        match self {
+            Self::NoMainLabel => Diagnostic::error("E0307", self.to_string())
+                .with_help("add a `main:` label where execution should start"),
+            Self::TextSectionTooLarge(_) => Diagnostic::error("E0308", self.to_string()),
+        }
+    }
+}
+
 /// Generate the binary header
 pub fn generate_header(main_addr: u16, text_size: u16) -> Vec<u8> {
     let mut header = Vec::with_capacity(HEADER_SIZE as usize);
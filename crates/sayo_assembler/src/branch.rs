@@ -0,0 +1,140 @@
+/// Branch relaxation
+///
+/// `JMP`, `SJMP` and `AJMP` to a label start out as `SJMP` and are widened to `AJMP`
+/// and then `JMP` only when the target is out of reach, in the same layout loop as
+/// pseudo-instructions (forms only ever widen, so it terminates). Jumps to constants
+/// are kept as written. Conditional jumps whose target operand is a register are
+/// assembled as the register-target `JF*` form regardless of relaxation.
+use crate::address::Address;
+use crate::layout::Layout;
+use sayo_ast::{Instruction, Item, Mnemonic, Operand, Program};
+use std::collections::HashMap;
+
+/// Choose branch forms for `program` into `lowered` (keyed by item index); jumps are
+/// only relaxed with `relax_jumps`. Without a layout, jumps to labels take the shortest form.
+/// Returns whether any instruction changed length.
+pub(crate) fn relax(
+    program: &Program,
+    layout: Option<&Layout>,
+    relax_jumps: bool,
+    lowered: &mut HashMap<usize, Instruction>,
+) -> bool {
+    let mut changed = false;
+    for (idx, item) in program.items.iter().enumerate() {
+        let Item::Instruction(instr) = &item.node else {
+            continue;
+        };
+
+        let form = if let Some(form) = register_target(instr) {
+            form
+        } else if relax_jumps && instr.mnemonic.is_jump() {
+            let Some(form) = jump_form(instr, layout, idx, lowered.get(&idx)) else {
+                // A jump to a constant: keep the form written
+                changed |= lowered.remove(&idx).is_some_and(|real| real.mnemonic.metadata().length != instr.mnemonic.metadata().length);
+                continue;
+            };
+            form
+        } else {
+            continue;
+        };
+
+        let length = lowered.get(&idx).map_or(instr.mnemonic.metadata().length, |real| real.mnemonic.metadata().length);
+        changed |= form.metadata().length != length;
+        lowered.insert(idx, Instruction { mnemonic: form, ..instr.clone() });
+    }
+    changed
+}
+
+/// `JF*` form of a conditional jump written with a register as its target
+fn register_target(instr: &Instruction) -> Option<Mnemonic> {
+    let form = instr.mnemonic.register_target_form()?;
+    instr.operands.last().filter(|op| op.node.is_register()).map(|_| form)
+}
+
+/// Shortest jump form, no shorter than the current one, that reaches the target;
+/// `None` if the target is a constant rather than an address
+fn jump_form(instr: &Instruction, layout: Option<&Layout>, idx: usize, current: Option<&Instruction>) -> Option<Mnemonic> {
+    let operand = &instr.operands.first()?.node;
+    let min_rank = current.and_then(|real| Mnemonic::JUMP_FORMS.iter().position(|form| *form == real.mnemonic)).unwrap_or(0);
+    let Some(layout) = layout else {
+        return matches!(operand, Operand::Label(_) | Operand::Expr(_)).then_some(Mnemonic::JUMP_FORMS[min_rank]);
+    };
+
+    let scope = layout.item_scopes.get(idx).and_then(|s| s.as_ref());
+    let resolve = |name: &str| layout.symbols.resolve_value_at(name, scope, idx);
+    let target = match operand {
+        Operand::Label(name) if !layout.symbols.is_constant(name) => resolve(name),
+        Operand::Expr(expr) if expr.symbols().iter().any(|name| !layout.symbols.is_constant(name)) => expr.eval(&resolve).ok(),
+        _ => return None,
+    };
+    // An undefined target is reported by the encoder; keep the widest form meanwhile
+    let Some(target) = target else {
+        return Some(Mnemonic::JMP);
+    };
+
+    let pc = layout.item_addresses[idx];
+    Mnemonic::JUMP_FORMS[min_rank..].iter().copied().find(|form| reaches(*form, pc, target))
+}
+
+/// Whether a jump at `pc` reaches `target` in the given form
+fn reaches(form: Mnemonic, pc: Address, target: i64) -> bool {
+    let next_pc = pc as i64 + form.metadata().length as i64;
+    match form {
+        Mnemonic::SJMP => (-128..=127).contains(&(target - next_pc)),
+        // PC = (PC & 0xff00) + i: only used where both addresses are in the first page
+        Mnemonic::AJMP => (0..=0xFF).contains(&target) && next_pc <= 0xFF,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layout::LayoutPass;
+    use sayo_ast::{Directive, Instruction, Item, Mnemonic, Operand, Program, Register, Span, Spanned};
+
+    fn item(node: Item) -> Spanned<Item> {
+        Spanned::new(node, Span::new(0, 0))
+    }
+
+    fn instruction(mnemonic: Mnemonic, operands: Vec<Operand>) -> Spanned<Item> {
+        let operands = operands.into_iter().map(|op| Spanned::new(op, Span::new(0, 0))).collect();
+        item(Item::Instruction(Instruction { mnemonic, operands, encoding: None }))
+    }
+
+    fn program(gap: i64) -> Program {
+        Program {
+            items: vec![
+                item(Item::Label("main".to_string())),
+                instruction(Mnemonic::JMP, vec![Operand::Label("end".to_string())]),
+                instruction(Mnemonic::JZ, vec![Operand::Register(Register::R0), Operand::Register(Register::R1)]),
+                item(Item::Directive(Directive::Zero(gap))),
+                item(Item::Label("end".to_string())),
+                instruction(Mnemonic::RET, vec![]),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_jumps_grow_only_when_out_of_reach() {
+        let near = LayoutPass::new().process(&program(100)).unwrap();
+        assert_eq!(near.lowered[&1].mnemonic, Mnemonic::SJMP);
+        assert_eq!(near.lowered[&2].mnemonic, Mnemonic::JFZ);
+
+        // Too far for SJMP, but the target is still in the first 256 bytes
+        let page = LayoutPass::new().process(&program(200)).unwrap();
+        assert_eq!(page.lowered[&1].mnemonic, Mnemonic::AJMP);
+
+        let far = LayoutPass::new().process(&program(300)).unwrap();
+        assert_eq!(far.lowered[&1].mnemonic, Mnemonic::JMP);
+        // JMP (3 bytes) + JFZ (3 bytes) + gap
+        assert_eq!(far.item_addresses[4], far.item_addresses[1] + 306);
+    }
+
+    #[test]
+    fn test_relaxation_disabled() {
+        let layout = LayoutPass::new().with_branch_relaxation(false).process(&program(100)).unwrap();
+        assert!(!layout.lowered.contains_key(&1));
+        // A register target is not a relaxation: JZ R0, R1 can only be encoded as JFZ
+        assert_eq!(layout.lowered[&2].mnemonic, Mnemonic::JFZ);
+    }
+}
@@ -1,7 +1,7 @@
 use crate::binary::{self, BinaryError};
 use crate::layout::Layout;
 use crate::Section;
-use sayo_ast::{Instruction, Item, Operand, Program};
+use sayo_ast::{Diagnostic, EvalError, Expr, HidNamespace, Instruction, Item, Operand, Program};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -20,11 +20,38 @@ pub enum EncodeError {
 
     #[error("PC-relative offset {0} out of range for 8-bit signed")]
     PcRelOffsetOutOfRange(i64),
+
+    #[error("Cannot evaluate expression '{expr}': {source}")]
+    Expression { expr: String, source: EvalError },
+
+    #[error("Value {value} out of range for .{directive}")]
+    DataValueOutOfRange { value: i64, directive: &'static str },
+
+    #[error("{instruction} expects a {expected}, but {name} is a {actual}")]
+    HidNamespaceMismatch { instruction: String, name: String, expected: HidNamespace, actual: HidNamespace },
     
     #[error("Binary format error: {0}")]
     BinaryFormat(#[from] BinaryError),
 }
 
+impl EncodeError {
+    /// Diagnostic without a location; the encoder attaches the span of the offending item
+    pub fn diagnostic(&self) -> Diagnostic {
+        let code = match self {
+            Self::UndefinedLabel(_) => "E0301",
+            Self::ImmediateOutOfRange(..) => "E0302",
+            Self::OperandCountMismatch { .. } => "E0303",
+            Self::InvalidOperandType(_) => "E0304",
+            Self::PcRelOffsetOutOfRange(_) => "E0305",
+            Self::Expression { .. } => "E0306",
+            Self::HidNamespaceMismatch { .. } => "E0310",
+            Self::DataValueOutOfRange { .. } => "E0311",
+            Self::BinaryFormat(error) => return error.diagnostic(),
+        };
+        Diagnostic::error(code, self.to_string())
+    }
+}
+
 /// Encoder generates machine code from AST + layout
 pub struct Encoder<'a> {
     layout: &'a Layout,
@@ -36,10 +63,10 @@ impl<'a> Encoder<'a> {
     }
 
     /// Encode the entire program into machine code with header
-    pub fn encode(&mut self, program: &Program) -> Result<Vec<u8>, EncodeError> {
+    pub fn encode(&mut self, program: &Program) -> Result<Vec<u8>, Box<Diagnostic>> {
         // First, find the main label
         let main_addr = self.layout.symbols.resolve("main")
-            .ok_or(BinaryError::NoMainLabel)?;
+            .ok_or_else(|| BinaryError::NoMainLabel.diagnostic())?;
         
         // Calculate text section size
         let text_size = self.layout.section_sizes
@@ -48,7 +75,7 @@ impl<'a> Encoder<'a> {
             .unwrap_or(0);
         
         if text_size > 65535 {
-            return Err(BinaryError::TextSectionTooLarge(text_size).into());
+            return Err(BinaryError::TextSectionTooLarge(text_size).diagnostic().into());
         }
         
         // Generate header
@@ -66,7 +93,7 @@ impl<'a> Encoder<'a> {
     }
     
     /// Encode only the text section (without header, for raw output)
-    pub fn encode_raw(&mut self, program: &Program) -> Result<Vec<u8>, EncodeError> {
+    pub fn encode_raw(&mut self, program: &Program) -> Result<Vec<u8>, Box<Diagnostic>> {
         let mut output = Vec::new();
         
         // Encode text section
@@ -82,7 +109,8 @@ impl<'a> Encoder<'a> {
                 let data_start = self.layout.data_section_start();
                 
                 if item_addr >= data_start || (data_start == text_start && self.is_data_directive(dir)) {
-                    let bytes = self.encode_data_directive(dir)?;
+                    let bytes = self.encode_data_directive(dir, idx)
+                        .map_err(|e| e.diagnostic().with_span(item.span))?;
                     output.extend_from_slice(&bytes);
                 }
             }
@@ -92,7 +120,7 @@ impl<'a> Encoder<'a> {
     }
     
     /// Encode items in a specific section
-    fn encode_section(&mut self, program: &Program, section: Section) -> Result<Vec<u8>, EncodeError> {
+    fn encode_section(&mut self, program: &Program, section: Section) -> Result<Vec<u8>, Box<Diagnostic>> {
         let mut output = Vec::new();
         let section_start = match section {
             Section::Text => self.layout.text_section_start(),
@@ -113,7 +141,9 @@ impl<'a> Encoder<'a> {
 
             match &item.node {
                 Item::Instruction(instr) => {
-                    let bytes = self.encode_instruction(instr, item_addr, idx)?;
+                    let instr = self.layout.instruction(idx, instr);
+                    let bytes = self.encode_instruction(instr, item_addr, idx)
+                        .map_err(|e| e.diagnostic().with_span(item.span))?;
                     output.extend_from_slice(&bytes);
                 }
                 Item::Label(_) => {
@@ -130,11 +160,11 @@ impl<'a> Encoder<'a> {
     }
     
     /// Encode the data section with linear layout (no length prefixes)
-    fn encode_data_section(&mut self, program: &Program) -> Result<Vec<u8>, EncodeError> {
+    fn encode_data_section(&mut self, program: &Program) -> Result<Vec<u8>, Box<Diagnostic>> {
         let mut output = Vec::new();
         let mut in_data_section = false;
         
-        for item in &program.items {
+        for (idx, item) in program.items.iter().enumerate() {
             match &item.node {
                 Item::Directive(dir) => {
                     use sayo_ast::Directive;
@@ -155,7 +185,8 @@ impl<'a> Encoder<'a> {
                     }
                     
                     if in_data_section && self.is_data_directive(dir) {
-                        let bytes = self.encode_data_directive(dir)?;
+                        let bytes = self.encode_data_directive(dir, idx)
+                            .map_err(|e| e.diagnostic().with_span(item.span))?;
                         output.extend_from_slice(&bytes);
                     }
                 }
@@ -183,32 +214,33 @@ impl<'a> Encoder<'a> {
         )
     }
     
-    fn encode_data_directive(&self, dir: &sayo_ast::Directive) -> Result<Vec<u8>, EncodeError> {
-        use sayo_ast::{Directive, DataValue};
+    fn encode_data_directive(&self, dir: &sayo_ast::Directive, item_idx: usize) -> Result<Vec<u8>, EncodeError> {
+        use sayo_ast::Directive;
         let mut output = Vec::new();
         
         match dir {
             Directive::Byte(values) => {
                 for v in values {
-                    let val = self.resolve_data_value(v, 1)?;
+                    let val = self.resolve_data_value(v, item_idx, "byte", 8)?;
                     output.push(val as u8);
                 }
             }
             Directive::Word(values) | Directive::Short(values) => {
+                let directive = if matches!(dir, Directive::Word(_)) { "word" } else { "short" };
                 for v in values {
-                    let val = self.resolve_data_value(v, 2)?;
+                    let val = self.resolve_data_value(v, item_idx, directive, 16)?;
                     output.extend_from_slice(&(val as u16).to_le_bytes());
                 }
             }
             Directive::Long(values) => {
                 for v in values {
-                    let val = self.resolve_data_value(v, 4)?;
+                    let val = self.resolve_data_value(v, item_idx, "long", 32)?;
                     output.extend_from_slice(&(val as u32).to_le_bytes());
                 }
             }
             Directive::Quad(values) => {
                 for v in values {
-                    let val = self.resolve_data_value(v, 8)?;
+                    let val = self.resolve_data_value(v, item_idx, "quad", 64)?;
                     output.extend_from_slice(&(val as u64).to_le_bytes());
                 }
             }
@@ -231,16 +263,22 @@ impl<'a> Encoder<'a> {
         Ok(output)
     }
     
-    fn resolve_data_value(&self, value: &sayo_ast::DataValue, _size: usize) -> Result<i64, EncodeError> {
+    /// Resolve a data value for a `bits`-wide directive; signed and unsigned values both
+    /// fit (`.byte -1` and `.byte 255` are the same byte)
+    fn resolve_data_value(&self, value: &sayo_ast::DataValue, item_idx: usize, directive: &'static str, bits: u32) -> Result<i64, EncodeError> {
         use sayo_ast::DataValue;
-        match value {
+        let value = match value {
             DataValue::Immediate(n) => Ok(*n),
             DataValue::Label(name) => {
-                self.layout.symbols.resolve(name)
-                    .map(|addr| addr as i64)
+                self.resolve_symbol(name, item_idx)
                     .ok_or_else(|| EncodeError::UndefinedLabel(name.clone()))
             }
+            DataValue::Expr(expr) => self.eval_expr(expr, item_idx),
+        }?;
+        if bits < 64 && !(-(1i64 << (bits - 1))..(1i64 << bits)).contains(&value) {
+            return Err(EncodeError::DataValueOutOfRange { value, directive });
         }
+        Ok(value)
     }
 
     /// Encode a single instruction
@@ -264,6 +302,18 @@ impl<'a> Encoder<'a> {
             });
         }
 
+        // A built-in name must belong to the instruction's table (no mouse buttons for PRESS_GK)
+        if let (Some(Operand::Hid(code)), Some(expected)) = (instr.operands.first().map(|op| &op.node), instr.mnemonic.hid_namespace()) {
+            if code.namespace != expected {
+                return Err(EncodeError::HidNamespaceMismatch {
+                    instruction: format!("{:?}", instr.mnemonic),
+                    name: code.name().to_string(),
+                    expected,
+                    actual: code.namespace,
+                });
+            }
+        }
+
         // Encode each operand based on its expected type
         for (i, (operand_spanned, operand_def)) in
             instr.operands.iter().zip(&metadata.operands).enumerate()
@@ -292,7 +342,7 @@ impl<'a> Encoder<'a> {
                     
                     // Check if this is a PC-relative jump instruction
                     use sayo_ast::instr::Mnemonic;
-                    if matches!(instr.mnemonic, Mnemonic::SJMP) && matches!(operand, Operand::Label(_)) {
+                    if matches!(instr.mnemonic, Mnemonic::SJMP) && self.is_address_operand(operand) {
                         // PC-relative offset for SJMP
                         let next_pc = instr_addr + metadata.length as u32;
                         let offset = value - next_pc as i64;
@@ -324,10 +374,16 @@ impl<'a> Encoder<'a> {
                 }
                 OperandType::U32 => {
                     let value = self.resolve_operand_value(operand, instr_addr, item_idx)?;
+                    if value < 0 || value > u32::MAX as i64 {
+                        return Err(EncodeError::ImmediateOutOfRange(value, 32));
+                    }
                     bytes.extend_from_slice(&(value as u32).to_le_bytes());
                 }
                 OperandType::I32 => {
                     let value = self.resolve_operand_value(operand, instr_addr, item_idx)?;
+                    if value < i32::MIN as i64 || value > i32::MAX as i64 {
+                        return Err(EncodeError::ImmediateOutOfRange(value, 32));
+                    }
                     bytes.extend_from_slice(&(value as i32 as u32).to_le_bytes());
                 }
                 OperandType::Rgb888 => {
@@ -389,28 +445,88 @@ impl<'a> Encoder<'a> {
         Ok(bytes)
     }
 
-    /// Resolve an operand to its numeric value (for immediates or labels)
+    /// Resolve an operand to its numeric value (for immediates, labels or expressions)
     fn resolve_operand_value(&self, operand: &Operand, _instr_addr: u32, item_idx: usize) -> Result<i64, EncodeError> {
         match operand {
             Operand::Immediate(value) => {
                 Ok(*value)
             }
             Operand::Label(name) => {
-                // Use the scope of the current item, not the final global scope
-                let item_scope = self.layout.item_scopes.get(item_idx).and_then(|s| s.as_ref());
-                let addr = self
-                    .layout
-                    .symbols
-                    .resolve_with_scope(name, item_scope)
-                    .ok_or_else(|| {
-                        EncodeError::UndefinedLabel(name.clone())
-                    })?;
-                Ok(addr as i64)
+                self.resolve_symbol(name, item_idx)
+                    .ok_or_else(|| EncodeError::UndefinedLabel(name.clone()))
             }
+            Operand::Expr(expr) => self.eval_expr(expr, item_idx),
+            Operand::Hid(code) => Ok(code.code as i64),
+            Operand::Color(color) => Ok(color.rgb888() as i64),
             Operand::Register(_) => {
                 // This should not be called for register operands
                 Err(EncodeError::InvalidOperandType(0))
             }
         }
     }
+
+    /// Evaluate an expression using the final label addresses
+    fn eval_expr(&self, expr: &Expr, item_idx: usize) -> Result<i64, EncodeError> {
+        expr.eval(&|name: &str| self.resolve_symbol(name, item_idx))
+            .map_err(|source| match source {
+                EvalError::UndefinedSymbol(name) => EncodeError::UndefinedLabel(name),
+                source => EncodeError::Expression { expr: expr.to_string(), source },
+            })
+    }
+
+    fn resolve_symbol(&self, name: &str, item_idx: usize) -> Option<i64> {
+        // Use the scope of the current item, not the final global scope
+        let item_scope = self.layout.item_scopes.get(item_idx).and_then(|s| s.as_ref());
+        self.layout.symbols.resolve_value_at(name, item_scope, item_idx)
+    }
+
+    /// Whether an operand refers to a code/data address (as opposed to a constant)
+    fn is_address_operand(&self, operand: &Operand) -> bool {
+        let symbols = &self.layout.symbols;
+        match operand {
+            Operand::Label(name) => !symbols.is_constant(name),
+            Operand::Expr(expr) => expr.symbols().iter().any(|name| !symbols.is_constant(name)),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Assembler, AssemblerOutput};
+    use sayo_ast::Diagnostic;
+
+    fn assemble(text: &str) -> Result<AssemblerOutput, Box<Diagnostic>> {
+        Assembler::new(text.to_string(), sayo_parser::parse(text).unwrap()).assemble()
+    }
+
+    #[test]
+    fn test_hid_namespace_mismatch() {
+        let error = assemble("main:\n    PRESS_GK MEDIA_VOL_UP\n    RET\n").err().unwrap();
+        assert_eq!(error.code, "E0310");
+        assert_eq!(error.message, "PRESS_GK expects a keyboard key, but MEDIA_VOL_UP is a media key");
+        assert!(assemble("main:\n    PRESS_MU MEDIA_VOL_UP\n    RELEASE_MU MEDIA_VOL_UP\n    RET\n").is_ok());
+    }
+
+    #[test]
+    fn test_data_value_range() {
+        assert!(assemble("main:\n    RET\n.data\n.byte 255, -128\n.word 0xFFFF, -1\n.long 0xFFFFFFFF\n").is_ok());
+        let error = assemble("main:\n    RET\n.data\n.byte 2*200, -300\n").err().unwrap();
+        assert_eq!(error.code, "E0311");
+        assert_eq!(error.message, "Value 400 out of range for .byte");
+        let error = assemble("main:\n    RET\n.data\n.word -32769\n").err().unwrap();
+        assert_eq!(error.message, "Value -32769 out of range for .word");
+    }
+
+    #[test]
+    fn test_constant_reassignment() {
+        // `.set` and `=` take effect from their definition on
+        let reassigned = assemble("X = 1\nmain:\n    SLEEP X\n    .set X, X + 1\n    SLEEP X\n    RET\n").ok().unwrap();
+        let literal = assemble("main:\n    SLEEP 1\n    SLEEP 2\n    RET\n").ok().unwrap();
+        assert_eq!(reassigned.machine_code, literal.machine_code);
+
+        // `.equ` cannot be defined again
+        let error = assemble(".equ X, 1\nX = 2\nmain:\n    RET\n").err().unwrap();
+        assert_eq!(error.code, "E0203");
+    }
 }
@@ -1,7 +1,9 @@
 use crate::address::{Address, Section};
 use crate::binary::HEADER_SIZE;
+use crate::branch;
+use crate::pseudo;
 use crate::symbol::SymbolTable;
-use sayo_ast::{Directive, Item, Program};
+use sayo_ast::{numeric_label, ConstantError, Constants, Diagnostic, Directive, EvalError, Instruction, Item, Mnemonic, Program};
 use std::collections::HashMap;
 use thiserror::Error;
 
@@ -10,11 +12,39 @@ pub enum LayoutError {
     #[error("Local label '{0}' defined without a global label")]
     LocalLabelWithoutGlobal(String),
 
-    #[error("Unsupported directive: {0}")]
-    UnsupportedDirective(String),
-
     #[error("Invalid alignment value: {0}")]
     InvalidAlignment(u32),
+
+    #[error("Symbol '{0}' is already defined")]
+    SymbolRedefinition(String),
+
+    #[error("Cannot evaluate constant '{name}': {source}")]
+    Constant { name: String, source: EvalError },
+
+    #[error("{0} expects a register and an immediate")]
+    PseudoOperands(Mnemonic),
+
+    #[error("Cannot evaluate operand of {mnemonic}: {source}")]
+    PseudoOperand { mnemonic: Mnemonic, source: EvalError },
+
+    #[error("Immediate value {value} out of range for {mnemonic}")]
+    PseudoOutOfRange { mnemonic: Mnemonic, value: i64 },
+}
+
+impl LayoutError {
+    /// Diagnostic without a location; callers attach the span of the offending item
+    pub fn diagnostic(&self) -> Diagnostic {
+        let code = match self {
+            Self::LocalLabelWithoutGlobal(_) => "E0201",
+            Self::InvalidAlignment(_) => "E0202",
+            Self::SymbolRedefinition(_) => "E0203",
+            Self::Constant { .. } => "E0204",
+            Self::PseudoOperands(_) => "E0205",
+            Self::PseudoOperand { .. } => "E0206",
+            Self::PseudoOutOfRange { .. } => "E0207",
+        };
+        Diagnostic::error(code, self.to_string())
+    }
 }
 
 /// Layout information for the program
@@ -28,6 +58,8 @@ pub struct Layout {
     pub item_scopes: Vec<Option<String>>,
     /// Final size of each section
     pub section_sizes: HashMap<Section, Address>,
+    /// Real instructions chosen for pseudo-instructions and relaxed branches (by item index)
+    pub lowered: HashMap<usize, Instruction>,
 }
 
 impl Layout {
@@ -37,8 +69,15 @@ impl Layout {
             item_addresses,
             item_scopes,
             section_sizes: HashMap::new(),
+            lowered: HashMap::new(),
         }
     }
+
+    /// The instruction emitted for item `idx`: the lowered form of a pseudo-instruction
+    /// or branch, otherwise `instr` itself
+    pub fn instruction<'a>(&'a self, idx: usize, instr: &'a Instruction) -> &'a Instruction {
+        self.lowered.get(&idx).unwrap_or(instr)
+    }
     
     /// Get the address where the text section starts (after header)
     pub fn text_section_start(&self) -> Address {
@@ -61,6 +100,8 @@ pub struct LayoutPass {
     text_addr: Address,
     /// Data section address (starts after text section)
     data_addr: Address,
+    /// Pick the shortest of SJMP/AJMP/JMP for jumps to labels
+    relax_branches: bool,
 }
 
 impl LayoutPass {
@@ -74,9 +115,17 @@ impl LayoutPass {
             text_addr: HEADER_SIZE,
             // Data section address is determined after text section is complete
             data_addr: 0,
+            relax_branches: true,
         }
     }
 
+    /// Enable or disable branch relaxation (enabled by default); disable it for
+    /// cycle-exact code that relies on the jump forms written
+    pub fn with_branch_relaxation(mut self, enabled: bool) -> Self {
+        self.relax_branches = enabled;
+        self
+    }
+
     fn current_addr(&self) -> Address {
         match self.current_section {
             Section::Text => self.text_addr,
@@ -108,7 +157,25 @@ impl LayoutPass {
         Ok(())
     }
 
-    pub fn process(&mut self, program: &Program) -> Result<Layout, LayoutError> {
+    pub fn process(&mut self, program: &Program) -> Result<Layout, Box<Diagnostic>> {
+        // Pseudo-instructions and jumps start in their shortest form and only grow, so this terminates
+        let mut lowered = HashMap::new();
+        pseudo::relax(program, None, &mut lowered)?;
+        branch::relax(program, None, self.relax_branches, &mut lowered);
+        loop {
+            *self = Self::new().with_branch_relaxation(self.relax_branches);
+            let mut layout = self.place(program, &lowered)?;
+            let pseudo_changed = pseudo::relax(program, Some(&layout), &mut lowered)?;
+            let branch_changed = branch::relax(program, Some(&layout), self.relax_branches, &mut lowered);
+            if !pseudo_changed && !branch_changed {
+                layout.lowered = lowered;
+                return Ok(layout);
+            }
+        }
+    }
+
+    /// One layout pass with the given forms of the pseudo-instructions
+    fn place(&mut self, program: &Program, lowered: &HashMap<usize, Instruction>) -> Result<Layout, Box<Diagnostic>> {
         // Assign addresses to all items
         // Text section starts at HEADER_SIZE
         // Data section starts immediately after text section
@@ -121,11 +188,13 @@ impl LayoutPass {
 
             match &item.node {
                 Item::Label(name) => {
-                    if name.starts_with('.') {
+                    if let Some(number) = numeric_label(name) {
+                        self.symbols.define_numeric(number, idx, item_addr);
+                    } else if name.starts_with('.') {
                         // Local label
                         self.symbols
                             .define_local(name.clone(), item_addr)
-                            .map_err(|_| LayoutError::LocalLabelWithoutGlobal(name.clone()))?;
+                            .map_err(|_| LayoutError::LocalLabelWithoutGlobal(name.clone()).diagnostic().with_span(item.span))?;
                     } else {
                         // Global label
                         self.symbols.define_global(name.clone(), item_addr);
@@ -134,16 +203,21 @@ impl LayoutPass {
 
                 Item::Instruction(instr) => {
                     // Get instruction length from metadata
-                    let metadata = instr.mnemonic.metadata();
+                    let metadata = lowered.get(&idx).unwrap_or(instr).mnemonic.metadata();
                     self.advance_addr(metadata.length as Address);
                 }
 
                 Item::Directive(dir) => {
-                    self.process_directive(dir, idx)?;
+                    self.process_directive(dir)
+                        .map_err(|e| e.diagnostic().with_span(item.span))?;
                 }
             }
         }
 
+        // Constants may refer to labels defined later, so evaluate them last
+        self.resolve_constants(program)
+            .map_err(|(e, idx)| e.diagnostic().with_span(program.items[idx].span))?;
+
         // Calculate final section sizes
         // Text size is relative to HEADER_SIZE
         let text_size = self.text_addr - HEADER_SIZE;
@@ -165,7 +239,35 @@ impl LayoutPass {
         Ok(layout)
     }
 
-    fn process_directive(&mut self, dir: &Directive, _item_idx: usize) -> Result<(), LayoutError> {
+    /// Evaluate the constants (see `Constants`) with the label addresses
+    /// Errors come with the index of the constant's definition
+    fn resolve_constants(&mut self, program: &Program) -> Result<(), (LayoutError, usize)> {
+        // Constants and labels share one namespace
+        for (idx, item) in program.items.iter().enumerate() {
+            if let Item::Directive(Directive::Equ(name, ..)) = &item.node {
+                if self.symbols.globals().contains_key(name) {
+                    return Err((LayoutError::SymbolRedefinition(name.clone()), idx));
+                }
+            }
+        }
+
+        let symbols = &self.symbols;
+        let scopes = &self.item_scopes;
+        let constants = Constants::evaluate(program, |name, idx| {
+            symbols.resolve_address_at(name, scopes[idx].as_ref(), idx).map(|addr| addr as i64)
+        })
+        .map_err(|error| {
+            let idx = error.index();
+            match error {
+                ConstantError::Redefinition { name, .. } => (LayoutError::SymbolRedefinition(name), idx),
+                ConstantError::Eval { name, source, .. } => (LayoutError::Constant { name, source }, idx),
+            }
+        })?;
+        self.symbols.set_constants(constants);
+        Ok(())
+    }
+
+    fn process_directive(&mut self, dir: &Directive) -> Result<(), LayoutError> {
         match dir {
             Directive::Text => {
                 self.current_section = Section::Text;
@@ -230,8 +332,10 @@ impl LayoutPass {
             Directive::Org(addr) => {
                 self.set_addr(*addr as Address);
             }
-            // These directives don't affect layout
-            Directive::Globl(_)
+            // These directives don't affect layout; constants are evaluated once every
+            // label is placed
+            Directive::Equ(..)
+            | Directive::Globl(_)
             | Directive::Local(_)
             | Directive::Type(_, _)
             | Directive::Size(_, _)
@@ -240,6 +344,7 @@ impl LayoutPass {
             | Directive::Loc(_)
             | Directive::Addrsig
             | Directive::AddrsigSym(_)
+            | Directive::Unknown { .. }
             | Directive::Bss => {
                 // No address increment
             }
@@ -1,8 +1,10 @@
 mod address;
 mod binary;
+mod branch;
 mod encoder;
 mod layout;
 mod listing;
+mod pseudo;
 mod symbol;
 
 pub use address::{Address, Section};
@@ -12,7 +14,7 @@ pub use layout::{Layout, LayoutPass};
 pub use listing::Listing;
 pub use symbol::{Symbol, SymbolTable};
 
-use sayo_ast::Program;
+use sayo_ast::{Diagnostic, Program, SourceMap};
 use std::error::Error;
 
 /// Result type for assembler operations
@@ -20,19 +22,36 @@ pub type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
 /// Main assembler entry point
 pub struct Assembler {
-    source: String,
+    sources: SourceMap,
     program: Program,
+    relax_branches: bool,
 }
 
 impl Assembler {
     pub fn new(source: String, program: Program) -> Self {
-        Self { source, program }
+        Self::with_sources(SourceMap::with_root("", source), program)
+    }
+
+    /// Assemble a program parsed from several files (see `sayo_parser::parse_sources`)
+    pub fn with_sources(sources: SourceMap, program: Program) -> Self {
+        Self { sources, program, relax_branches: true }
+    }
+
+    /// Enable or disable branch relaxation (see `LayoutPass::with_branch_relaxation`)
+    pub fn with_branch_relaxation(mut self, enabled: bool) -> Self {
+        self.relax_branches = enabled;
+        self
+    }
+
+    /// Source files of the program (for rendering diagnostics)
+    pub fn sources(&self) -> &SourceMap {
+        &self.sources
     }
 
     /// Assemble the program and generate machine code + listing
-    pub fn assemble(&self) -> Result<AssemblerOutput> {
+    pub fn assemble(&self) -> std::result::Result<AssemblerOutput, Box<Diagnostic>> {
         // Step 1: Layout pass - assign addresses to all items
-        let mut layout_pass = LayoutPass::new();
+        let mut layout_pass = LayoutPass::new().with_branch_relaxation(self.relax_branches);
         let layout = layout_pass.process(&self.program)?;
 
         // Step 2: Encode pass - generate machine code
@@ -40,7 +59,8 @@ impl Assembler {
         let machine_code = encoder.encode(&self.program)?;
 
         // Step 3: Generate listing with show-encoding format
-        let listing = Listing::generate(&self.source, &self.program, &layout, &machine_code)?;
+        let listing = Listing::generate(&self.sources, &self.program, &layout, &machine_code)
+            .map_err(|e| Diagnostic::error("E0309", format!("Cannot generate listing: {}", e)))?;
 
         Ok(AssemblerOutput {
             machine_code,
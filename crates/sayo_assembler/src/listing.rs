@@ -1,5 +1,5 @@
 use crate::layout::Layout;
-use sayo_ast::{Item, Program};
+use sayo_ast::{FileId, Item, Mnemonic, Program, SourceMap};
 use std::collections::HashMap;
 use std::error::Error;
 
@@ -8,15 +8,30 @@ use std::error::Error;
 /// Output format per line:
 /// - For instructions after label or address change: "MOV8 R2, 2                                        @ 0x0010 -> [0x6f,0x60,0x40]"
 /// - For labels: "main:                                                 @ 0x0010"
+/// - PRESS_*/RELEASE_* codes with a built-in name are decoded: "... -> [0x11,0x04] KEY_A"
+/// - Pseudo-instructions and relaxed branches show the real instruction chosen:
+///   "LI R0, 5 ... -> [0x8c,0x04,0x05] MOV8SX", "JMP loop ... -> [0x03,0xf0] SJMP"
 /// - Other lines: just the source text without annotation
 /// - Left side is padded to 60 chars; if source is > 60 chars, annotation is appended directly
+/// - Included files are listed in place after their `.include` line, between
+///   `; >>> path` and `; <<< path` markers
 pub struct Listing;
 
+/// Annotation state carried across lines (and into included files)
+struct ListingState {
+    last_end_addr: Option<u32>,
+    show_after_label: bool,
+}
+
 /// Information about a source line for listing purposes
 struct LineInfo {
     address: u32,
     encoding: Option<Vec<u8>>,
     is_label: bool,
+    /// Built-in name of the HID code a PRESS_*/RELEASE_* instruction encodes
+    hid_name: Option<&'static str>,
+    /// Real instruction a pseudo-instruction or branch was lowered to, if not the one written
+    lowered: Option<Mnemonic>,
 }
 
 impl Listing {
@@ -24,16 +39,13 @@ impl Listing {
     /// 
     /// This implementation shows address/encoding only after labels or when address changes.
     pub fn generate(
-        source: &str,
+        sources: &SourceMap,
         program: &Program,
         layout: &Layout,
         machine_code: &[u8],
     ) -> Result<String, Box<dyn Error>> {
-        // Build line info using original source (since spans are based on original bytes)
-        let line_info = Self::build_line_info(source, program, layout, machine_code);
-        
-        // Replace tabs with 4 spaces for consistent alignment in output
-        let source_normalized = source.replace('\t', "    ");
+        // Build line info using original sources (since spans are based on original bytes)
+        let line_info = Self::build_line_info(sources, program, layout, machine_code);
         
         // Generate output line by line
         let mut output = String::new();
@@ -41,22 +53,48 @@ impl Listing {
         // Add header information
         Self::generate_header(&mut output, machine_code, layout)?;
         
-        let mut last_end_addr: Option<u32> = None;
-        let mut show_after_label = false;
+        let mut state = ListingState {
+            last_end_addr: None,
+            show_after_label: false,
+        };
+        Self::generate_file(&mut output, sources, FileId::ROOT, &line_info, &mut state);
+
+        Ok(output)
+    }
+
+    /// List one source file, descending into files it includes
+    fn generate_file(
+        output: &mut String,
+        sources: &SourceMap,
+        file: FileId,
+        line_info: &HashMap<(FileId, usize), LineInfo>,
+        state: &mut ListingState,
+    ) {
+        // Files included from this one, keyed by the line of their .include
+        let includes: Vec<(usize, FileId)> = sources
+            .files()
+            .filter_map(|(id, f)| {
+                let site = f.included_from?;
+                (site.file == file).then(|| (sources.line_col(file, site.start).0, id))
+            })
+            .collect();
+        
+        // Replace tabs with 4 spaces for consistent alignment in output
+        let source_normalized = sources.text(file).replace('\t', "    ");
         
         for (line_num, line_text) in source_normalized.lines().enumerate() {
             let line_num_1based = line_num + 1;
             
-            if let Some(info) = line_info.get(&line_num_1based) {
+            if let Some(info) = line_info.get(&(file, line_num_1based)) {
                 // Check if this is a label
                 if info.is_label {
-                    show_after_label = true;
+                    state.show_after_label = true;
                 }
                 
                 // Determine if we should show address/encoding
                 // Show if: it's a label, or has encoding (instructions/data change address), or address jumped
-                let addr_jumped = last_end_addr.map_or(true, |last| info.address != last);
-                let should_show = info.is_label || info.encoding.is_some() || show_after_label || addr_jumped;
+                let addr_jumped = state.last_end_addr.map_or(true, |last| info.address != last);
+                let should_show = info.is_label || info.encoding.is_some() || state.show_after_label || addr_jumped;
                 
                 if should_show {
                     // Format with annotation
@@ -64,7 +102,11 @@ impl Listing {
                     let annotation = if let Some(ref bytes) = info.encoding {
                         // With encoding
                         let encoding_str = Self::format_bytes(bytes);
-                        format!("; @ 0x{:04x} -> [{}]", info.address, encoding_str)
+                        let note = info.hid_name.map(str::to_string).or_else(|| info.lowered.map(|m| m.to_string()));
+                        match note {
+                            Some(note) => format!("; @ 0x{:04x} -> [{}] {}", info.address, encoding_str, note),
+                            None => format!("; @ 0x{:04x} -> [{}]", info.address, encoding_str),
+                        }
                     } else {
                         // Address only
                         format!("; @ 0x{:04x}", info.address)
@@ -81,25 +123,30 @@ impl Listing {
                     
                     // Update tracking
                     let inst_len = info.encoding.as_ref().map_or(0, |b| b.len()) as u32;
-                    last_end_addr = Some(info.address + inst_len);
+                    state.last_end_addr = Some(info.address + inst_len);
                     if !info.is_label {
-                        show_after_label = false;
+                        state.show_after_label = false;
                     }
                 } else {
                     // No annotation needed
                     output.push_str(line_text.trim_end());
                     output.push('\n');
                     let inst_len = info.encoding.as_ref().map_or(0, |b| b.len()) as u32;
-                    last_end_addr = Some(info.address + inst_len);
+                    state.last_end_addr = Some(info.address + inst_len);
                 }
             } else {
                 // Line not in program (comments, empty lines, etc.)
                 output.push_str(line_text.trim_end());
                 output.push('\n');
             }
+            
+            for &(_, included) in includes.iter().filter(|(line, _)| *line == line_num_1based) {
+                let path = sources.path(included).display().to_string();
+                output.push_str(&format!("; >>> {}\n", path));
+                Self::generate_file(output, sources, included, line_info, state);
+                output.push_str(&format!("; <<< {}\n", path));
+            }
         }
-
-        Ok(output)
     }
 
     /// Generate header information for the listing file
@@ -171,15 +218,18 @@ impl Listing {
         Ok(())
     }
 
-    /// Build a map from 1-based line numbers to line info
+    /// Build a map from (file, 1-based line number) to line info
     fn build_line_info(
-        source: &str,
+        sources: &SourceMap,
         program: &Program,
         layout: &Layout,
         machine_code: &[u8],
-    ) -> HashMap<usize, LineInfo> {
-        let mut line_info: HashMap<usize, LineInfo> = HashMap::new();
-        let line_starts = Self::compute_line_starts(source);
+    ) -> HashMap<(FileId, usize), LineInfo> {
+        let mut line_info: HashMap<(FileId, usize), LineInfo> = HashMap::new();
+        let line_starts: HashMap<FileId, Vec<usize>> = sources
+            .files()
+            .map(|(id, f)| (id, Self::compute_line_starts(&f.text)))
+            .collect();
         
         // Track position in machine code - start after header
         // The machine_code includes: header (11 bytes) + text section + data section
@@ -190,15 +240,29 @@ impl Listing {
             let span = &item.span;
             
             // Find line number for this item's start position
-            let line_num = Self::offset_to_line(&line_starts, span.start);
+            let Some(file_line_starts) = line_starts.get(&span.file) else {
+                continue;
+            };
+            let line_num = (span.file, Self::offset_to_line(file_line_starts, span.start));
             
-            // Skip if we already have info for this line (keep first item's info)
-            if line_info.contains_key(&line_num) {
+            // Keep the first item's info for a line; items expanded from a macro
+            // all sit on the invocation line (and .rept iterations on their body
+            // line), so append their contiguous bytes
+            if let Some(info) = line_info.get_mut(&line_num) {
+                let len = Self::emitted_len(layout, idx, &item.node);
+                if let Some(bytes) = info.encoding.as_mut() {
+                    let code_offset = item_addr as usize;
+                    if len > 0 && info.address as usize + bytes.len() == code_offset && code_offset + len <= machine_code.len() {
+                        bytes.extend_from_slice(&machine_code[code_offset..code_offset + len]);
+                    }
+                }
                 continue;
             }
 
             match &item.node {
                 Item::Instruction(instr) => {
+                    let lowered = layout.lowered.get(&idx).map(|real| real.mnemonic).filter(|real| *real != instr.mnemonic);
+                    let instr = layout.instruction(idx, instr);
                     let metadata = instr.mnemonic.metadata();
                     let inst_len = metadata.length as usize;
 
@@ -211,10 +275,16 @@ impl Listing {
                         vec![]
                     };
 
+                    let hid_name = instr.mnemonic.hid_namespace()
+                        .zip(inst_bytes.get(1))
+                        .and_then(|(namespace, &code)| namespace.name_of(code));
+
                     line_info.insert(line_num, LineInfo {
                         address: item_addr,
                         encoding: Some(inst_bytes),
                         is_label: false,
+                        hid_name,
+                        lowered,
                     });
                 }
                 Item::Label(_) => {
@@ -222,20 +292,12 @@ impl Listing {
                         address: item_addr,
                         encoding: None,
                         is_label: true,
+                        hid_name: None,
+                        lowered: None,
                     });
                 }
-                Item::Directive(dir) => {
-                    use sayo_ast::Directive;
-                    let bytes_emitted = match dir {
-                        Directive::Byte(values) => values.len(),
-                        Directive::Word(values) | Directive::Short(values) => values.len() * 2,
-                        Directive::Long(values) => values.len() * 4,
-                        Directive::Quad(values) => values.len() * 8,
-                        Directive::Ascii(s) => s.len(),
-                        Directive::Asciz(s) => s.len() + 1, // +1 for null terminator
-                        Directive::Zero(count) | Directive::Skip(count) => *count as usize,
-                        _ => 0,
-                    };
+                Item::Directive(_) => {
+                    let bytes_emitted = Self::emitted_len(layout, idx, &item.node);
 
                     if bytes_emitted > 0 {
                         // For data directives in text section, use item_addr
@@ -252,12 +314,16 @@ impl Listing {
                             address: item_addr,
                             encoding: if dir_bytes.is_empty() { None } else { Some(dir_bytes) },
                             is_label: false,
+                            hid_name: None,
+                            lowered: None,
                         });
                     } else {
                         line_info.insert(line_num, LineInfo {
                             address: item_addr,
                             encoding: None,
                             is_label: false,
+                            hid_name: None,
+                            lowered: None,
                         });
                     }
                 }
@@ -267,6 +333,25 @@ impl Listing {
         line_info
     }
 
+    /// Number of bytes item `idx` occupies in the machine code
+    fn emitted_len(layout: &Layout, idx: usize, item: &Item) -> usize {
+        use sayo_ast::Directive;
+        match item {
+            Item::Instruction(instr) => layout.instruction(idx, instr).mnemonic.metadata().length as usize,
+            Item::Label(_) => 0,
+            Item::Directive(dir) => match dir {
+                Directive::Byte(values) => values.len(),
+                Directive::Word(values) | Directive::Short(values) => values.len() * 2,
+                Directive::Long(values) => values.len() * 4,
+                Directive::Quad(values) => values.len() * 8,
+                Directive::Ascii(s) => s.len(),
+                Directive::Asciz(s) => s.len() + 1, // +1 for null terminator
+                Directive::Zero(count) | Directive::Skip(count) => *count as usize,
+                _ => 0,
+            },
+        }
+    }
+
     /// Compute byte offset of each line start
     fn compute_line_starts(source: &str) -> Vec<usize> {
         let mut starts = vec![0];
@@ -0,0 +1,131 @@
+/// Lowering of pseudo-instructions (`LI`, `ADDI`, ...) to real instructions
+///
+/// The real form depends on the value, which may refer to labels whose addresses
+/// depend on the forms chosen. Layout therefore starts every pseudo-instruction in
+/// the shortest form for its value before layout (symbols count as 0) and re-lowers
+/// after each pass, only ever widening a form, until no length changes.
+use crate::layout::{Layout, LayoutError};
+use sayo_ast::{Diagnostic, EvalError, Instruction, Item, Operand, OperandType, Program, Spanned};
+use std::collections::HashMap;
+
+/// Lower every pseudo-instruction of `program` into `lowered` (keyed by item index).
+/// Without a layout, operands that refer to symbols count as 0.
+/// Returns whether any instruction changed length.
+pub(crate) fn relax(
+    program: &Program,
+    layout: Option<&Layout>,
+    lowered: &mut HashMap<usize, Instruction>,
+) -> Result<bool, Box<Diagnostic>> {
+    let mut changed = false;
+    for (idx, item) in program.items.iter().enumerate() {
+        let Item::Instruction(instr) = &item.node else {
+            continue;
+        };
+        if !instr.mnemonic.is_pseudo() {
+            continue;
+        }
+
+        let min_length = lowered.get(&idx).map_or(0, |real| real.mnemonic.metadata().length);
+        let real = lower(instr, layout, idx, min_length).map_err(|e| e.diagnostic().with_span(item.span))?;
+        changed |= real.mnemonic.metadata().length != min_length;
+        lowered.insert(idx, real);
+    }
+    Ok(changed)
+}
+
+/// Real instruction for one pseudo-instruction, at least `min_length` bytes long
+fn lower(instr: &Instruction, layout: Option<&Layout>, idx: usize, min_length: u8) -> Result<Instruction, LayoutError> {
+    let mnemonic = instr.mnemonic;
+    let (register, register_span, immediate_operand) = match &instr.operands[..] {
+        [Spanned { node: Operand::Register(register), span }, immediate] if !immediate.node.is_register() => (*register, *span, immediate),
+        _ => return Err(LayoutError::PseudoOperands(mnemonic)),
+    };
+
+    let value = operand_value(&immediate_operand.node, layout, idx)
+        .map_err(|source| LayoutError::PseudoOperand { mnemonic, source })?;
+    let (form, immediate) = mnemonic
+        .lower(register, value, min_length)
+        .ok_or(LayoutError::PseudoOutOfRange { mnemonic, value })?;
+
+    let operands = form.metadata().operands.iter()
+        .map(|def| match def.op_type {
+            OperandType::Register => Spanned::new(Operand::Register(register), register_span),
+            _ => Spanned::new(Operand::Immediate(immediate.unwrap_or(value)), immediate_operand.span),
+        })
+        .collect();
+    Ok(Instruction {
+        mnemonic: form,
+        operands,
+        encoding: instr.encoding.clone(),
+    })
+}
+
+fn operand_value(operand: &Operand, layout: Option<&Layout>, idx: usize) -> Result<i64, EvalError> {
+    let resolve = |name: &str| match layout {
+        Some(layout) => {
+            let scope = layout.item_scopes.get(idx).and_then(|s| s.as_ref());
+            layout.symbols.resolve_value_at(name, scope, idx)
+        }
+        None => Some(0),
+    };
+    match operand {
+        Operand::Immediate(value) => Ok(*value),
+        Operand::Hid(code) => Ok(code.code as i64),
+        Operand::Color(color) => Ok(color.rgb888() as i64),
+        Operand::Label(name) => resolve(name).ok_or_else(|| EvalError::UndefinedSymbol(name.clone())),
+        Operand::Expr(expr) => match layout {
+            Some(_) => expr.eval(&resolve),
+            // Before layout the expression may not even be computable (e.g. division by a symbol)
+            None => Ok(expr.eval(&resolve).unwrap_or(0)),
+        },
+        Operand::Register(_) => unreachable!("register operands are rejected before evaluation"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layout::LayoutPass;
+    use sayo_ast::{Directive, Instruction, Item, Mnemonic, Operand, Program, Register, Span, Spanned};
+
+    fn item(node: Item) -> Spanned<Item> {
+        Spanned::new(node, Span::new(0, 0))
+    }
+
+    fn instruction(mnemonic: Mnemonic, operands: Vec<Operand>) -> Spanned<Item> {
+        let operands = operands.into_iter().map(|op| Spanned::new(op, Span::new(0, 0))).collect();
+        item(Item::Instruction(Instruction { mnemonic, operands, encoding: None }))
+    }
+
+    #[test]
+    fn test_relaxation_widens_forward_references() {
+        // `end` is assumed to be 0 before layout, but lies beyond 0xFF once placed
+        let program = Program {
+            items: vec![
+                item(Item::Label("main".to_string())),
+                instruction(Mnemonic::LI, vec![Operand::Register(Register::R0), Operand::Label("end".to_string())]),
+                instruction(Mnemonic::ADDI, vec![Operand::Register(Register::A), Operand::Immediate(-3)]),
+                item(Item::Directive(Directive::Zero(300))),
+                item(Item::Label("end".to_string())),
+                instruction(Mnemonic::RET, vec![]),
+            ],
+        };
+
+        let layout = LayoutPass::new().process(&program).unwrap();
+        let li = &layout.lowered[&1];
+        assert_eq!(li.mnemonic, Mnemonic::MOV16SX);
+        assert_eq!(li.operands[1].node, Operand::Immediate(layout.item_addresses[4] as i64));
+        let addi = &layout.lowered[&2];
+        assert_eq!(addi.mnemonic, Mnemonic::SUB8_A);
+        assert_eq!(addi.operands[0].node, Operand::Immediate(3));
+        // MOV16SX (4 bytes) + SUB8_A (2 bytes) + 300 bytes of zeros
+        assert_eq!(layout.item_addresses[4], layout.item_addresses[1] + 306);
+    }
+
+    #[test]
+    fn test_invalid_pseudo_operands() {
+        let program = Program {
+            items: vec![instruction(Mnemonic::LI, vec![Operand::Immediate(1), Operand::Immediate(2)])],
+        };
+        assert!(LayoutPass::new().process(&program).is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use crate::address::Address;
+use sayo_ast::{Constants, NumericLabels};
 use std::collections::HashMap;
 
 /// A symbol (label) in the program
@@ -9,13 +10,17 @@ pub struct Symbol {
     pub is_local: bool,
 }
 
-/// Symbol table managing global and local labels
+/// Symbol table managing global and local labels, plus absolute constants
 #[derive(Debug, Clone)]
 pub struct SymbolTable {
     /// Global symbols (e.g., "main", "foo")
     globals: HashMap<String, Address>,
+    /// Absolute symbols defined with .equ/.set/= (plain values, not addresses)
+    constants: Constants,
     /// Local symbols scoped by global label (e.g., ".LBB0_1" under "main")
     locals: HashMap<String, HashMap<String, Address>>,
+    /// Numeric local labels (`1:`), resolved by item position
+    numeric: NumericLabels<Address>,
     /// Current global scope for local label resolution
     current_global: Option<String>,
 }
@@ -24,7 +29,9 @@ impl SymbolTable {
     pub fn new() -> Self {
         Self {
             globals: HashMap::new(),
+            constants: Constants::default(),
             locals: HashMap::new(),
+            numeric: NumericLabels::new(),
             current_global: None,
         }
     }
@@ -62,6 +69,37 @@ impl SymbolTable {
         }
     }
 
+    /// Define a numeric local label (`1:`) at item `index`
+    /// Numeric labels may be redefined and do not open a new local scope
+    pub fn define_numeric(&mut self, number: u64, index: usize, address: Address) {
+        self.numeric.define(number, index, address);
+    }
+
+    /// Set the absolute symbols (constants), evaluated once every label is placed
+    pub fn set_constants(&mut self, constants: Constants) {
+        self.constants = constants;
+    }
+
+    /// Check if a name refers to an absolute symbol rather than an address
+    pub fn is_constant(&self, name: &str) -> bool {
+        self.constants.contains(name)
+    }
+
+    /// Resolve a symbol used by item `index`: constants to the value in effect at that
+    /// item, anything else as in `resolve_address_at`
+    pub fn resolve_value_at(&self, name: &str, scope: Option<&String>, index: usize) -> Option<i64> {
+        if self.constants.contains(name) {
+            return self.constants.value_at(name, index);
+        }
+        self.resolve_address_at(name, scope, index).map(|addr| addr as i64)
+    }
+
+    /// Resolve a label used by item `index`: `1b` / `1f` resolve to the nearest
+    /// numeric label in that direction, other names within `scope`
+    pub fn resolve_address_at(&self, name: &str, scope: Option<&String>, index: usize) -> Option<Address> {
+        self.numeric.resolve(name, index).or_else(|| self.resolve_with_scope(name, scope))
+    }
+
     /// Resolve a label to an address
     pub fn resolve(&self, name: &str) -> Option<Address> {
         // First check globals (includes .L. static symbols)
@@ -110,6 +148,11 @@ impl SymbolTable {
         &self.globals
     }
 
+    /// Get all absolute symbols
+    pub fn constants(&self) -> &Constants {
+        &self.constants
+    }
+
     /// Get all local symbols for a specific global
     pub fn locals_for(&self, global: &str) -> Option<&HashMap<String, Address>> {
         self.locals.get(global)
@@ -121,3 +164,23 @@ impl Default for SymbolTable {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numeric_label_resolution() {
+        let mut symbols = SymbolTable::new();
+        symbols.define_global("main".to_string(), 0x20);
+        symbols.define_numeric(1, 1, 0x20);
+        symbols.define_numeric(1, 3, 0x24);
+
+        assert_eq!(symbols.resolve_value_at("1b", None, 2), Some(0x20));
+        assert_eq!(symbols.resolve_value_at("1f", None, 2), Some(0x24));
+        assert_eq!(symbols.resolve_value_at("1f", None, 3), None);
+        // Numeric labels do not change the local scope
+        assert_eq!(symbols.current_global(), Some(&"main".to_string()));
+        assert_eq!(symbols.resolve_value_at("main", None, 2), Some(0x20));
+    }
+}
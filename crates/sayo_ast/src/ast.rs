@@ -1,5 +1,8 @@
-use crate::instr::Mnemonic;
-use crate::reg::Register;
+use crate::color::Color;
+use crate::expr::Expr;
+use crate::hid::HidCode;
+use crate::instr::{Mnemonic, OperandType};
+use crate::reg::{RegWidth, Register};
 use crate::span::Spanned;
 use serde::{Deserialize, Serialize};
 
@@ -9,11 +12,12 @@ pub struct Program {
     pub items: Vec<Spanned<Item>>,
 }
 
-/// A data value that can be either an immediate or a label reference
+/// A data value: an immediate, a label reference or an expression over labels
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum DataValue {
     Immediate(i64),
     Label(String),
+    Expr(Expr),
 }
 
 /// Top-level item in assembly file
@@ -39,10 +43,11 @@ pub enum Directive {
     
     // Symbol metadata
     Type(String, String),  // .type <symbol>, <type>
-    Size(String, String),  // .size <symbol>, <size>
+    Size(String, Expr),    // .size <symbol>, <expr>
     File(String),          // .file <filename>
     Ident(String),         // .ident <string>
     Loc(String),           // .loc <params>
+    Equ(String, Expr, bool),  // .equ <name>, <expr>, or .set <name>, <expr> / <name> = <expr> (true: may be reassigned)
     
     // Data directives (support both immediate values and label references)
     Byte(Vec<DataValue>),   // .byte <values>
@@ -65,6 +70,9 @@ pub enum Directive {
     // Address signature directives
     Addrsig,              // .addrsig
     AddrsigSym(String),   // .addrsig_sym <symbol>
+    
+    // Any other directive (e.g. .cfi_startproc, .weak), kept verbatim and ignored by layout
+    Unknown { name: String, args: String },  // .<name> <args>
 }
 
 /// Instruction with operands
@@ -75,12 +83,32 @@ pub struct Instruction {
     pub encoding: Option<Vec<u8>>,
 }
 
+impl Instruction {
+    /// Whether operand `index` takes an RGB888 colour: an Rgb888 operand, or an
+    /// immediate written to a 24-bit register such as `SELECTED_LED_COL`
+    pub fn accepts_color(&self, index: usize) -> bool {
+        match self.mnemonic.metadata().operands.get(index).map(|def| &def.op_type) {
+            Some(OperandType::Rgb888) => true,
+            Some(OperandType::U8 | OperandType::I8 | OperandType::U16 | OperandType::I16 | OperandType::U32 | OperandType::I32) => {
+                self.operands.iter().any(|op| matches!(&op.node, Operand::Register(reg) if reg.metadata().width == RegWidth::W24))
+            }
+            _ => false,
+        }
+    }
+}
+
 /// Operand types
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Operand {
     Register(Register),
     Immediate(i64),
     Label(String),
+    /// Expression that could not be folded at parse time (references symbols)
+    Expr(Expr),
+    /// Built-in HID code name such as `KEY_A` or `MOUSE_LEFT`
+    Hid(HidCode),
+    /// Colour literal: `#RRGGBB`, `rgb(r, g, b)` or a named colour
+    Color(Color),
 }
 
 impl Operand {
@@ -95,4 +123,8 @@ impl Operand {
     pub fn is_label(&self) -> bool {
         matches!(self, Self::Label(_))
     }
+
+    pub fn is_expr(&self) -> bool {
+        matches!(self, Self::Expr(_))
+    }
 }
@@ -0,0 +1,53 @@
+/// Branch forms
+///
+/// Unconditional jumps to a label come in three sizes, which the assembler picks
+/// between by distance (branch relaxation). Conditional jumps take their target either
+/// as a label (`JZ R0, loop`) or in a register (`JFZ R0, R1`); the label spelling also
+/// accepts a register and is assembled as the `JF*` form.
+use crate::instr::Mnemonic;
+
+impl Mnemonic {
+    /// Unconditional jump forms, shortest reach first:
+    /// `SJMP` (PC-relative, -128..127), `AJMP` (within the first 256 bytes), `JMP` (absolute)
+    pub const JUMP_FORMS: [Mnemonic; 3] = [Mnemonic::SJMP, Mnemonic::AJMP, Mnemonic::JMP];
+
+    pub fn is_jump(&self) -> bool {
+        Self::JUMP_FORMS.contains(self)
+    }
+
+    /// Register-target form of a conditional jump that takes a label (`JZ` -> `JFZ`)
+    pub fn register_target_form(&self) -> Option<Mnemonic> {
+        match self {
+            Self::JC => Some(Self::JFC),
+            Self::JNC => Some(Self::JFNC),
+            Self::JZ => Some(Self::JFZ),
+            Self::JNZ => Some(Self::JFNZ),
+            Self::DJNZ => Some(Self::DJFNZ),
+            Self::JA => Some(Self::JFA),
+            Self::JB => Some(Self::JFB),
+            Self::JG => Some(Self::JFG),
+            Self::JL => Some(Self::JFL),
+            Self::CJNE => Some(Self::CJFNE),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_target_forms() {
+        // The register-target form takes a register exactly where the label was
+        for mnemonic in Mnemonic::all_variants() {
+            if let Some(form) = mnemonic.register_target_form() {
+                let (label, register) = (mnemonic.metadata(), form.metadata());
+                assert_eq!(label.operands.len(), register.operands.len(), "{}", mnemonic);
+                assert!(register.length < label.length, "{}", mnemonic);
+            }
+        }
+        assert_eq!(Mnemonic::JMP.register_target_form(), None);
+        assert!(Mnemonic::AJMP.is_jump() && !Mnemonic::CALL.is_jump());
+    }
+}
@@ -0,0 +1,81 @@
+/// RGB888 colour literals: `#RRGGBB`, `rgb(r, g, b)` and named colours
+///
+/// A colour is stored as its components and encoded as the value `0xRRGGBB`,
+/// which the encoder writes in little-endian order like any other Rgb888 operand.
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// Named colours, chosen for LEDs (full-intensity primaries rather than CSS shades)
+const NAMED_COLORS: &[(&str, u32)] = &[
+    ("BLACK", 0x000000), ("WHITE", 0xFFFFFF), ("RED", 0xFF0000), ("GREEN", 0x00FF00),
+    ("BLUE", 0x0000FF), ("YELLOW", 0xFFFF00), ("CYAN", 0x00FFFF), ("MAGENTA", 0xFF00FF),
+    ("ORANGE", 0xFF8000), ("PURPLE", 0x8000FF), ("PINK", 0xFF60A0), ("GRAY", 0x808080),
+];
+
+impl Color {
+    pub fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// Colour of a `0xRRGGBB` value (higher bits are ignored)
+    pub fn from_rgb888(value: u32) -> Self {
+        Self::new((value >> 16) as u8, (value >> 8) as u8, value as u8)
+    }
+
+    /// `0xRRGGBB`
+    pub fn rgb888(self) -> u32 {
+        (self.r as u32) << 16 | (self.g as u32) << 8 | self.b as u32
+    }
+
+    /// Parse the digits of a `#RRGGBB` literal (without the `#`)
+    pub fn from_hex(digits: &str) -> Option<Self> {
+        if digits.len() != 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        u32::from_str_radix(digits, 16).ok().map(Self::from_rgb888)
+    }
+
+    /// Look up a named colour (case-insensitive)
+    pub fn from_name(name: &str) -> Option<Self> {
+        NAMED_COLORS.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, value)| Self::from_rgb888(value))
+    }
+
+    /// Name of the colour, if it is one of the named colours
+    pub fn name(self) -> Option<&'static str> {
+        NAMED_COLORS.iter().find(|(_, value)| *value == self.rgb888()).map(|(name, _)| *name)
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:06X}", self.rgb888())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_literals() {
+        let orange = Color::from_hex("ff8000").unwrap();
+        assert_eq!(orange, Color::new(0xFF, 0x80, 0x00));
+        assert_eq!(orange.rgb888(), 0xFF8000);
+        assert_eq!(orange.to_string(), "#FF8000");
+        assert_eq!(orange.name(), Some("ORANGE"));
+
+        assert_eq!(Color::from_hex("FFF"), None);
+        assert_eq!(Color::from_hex("GG0000"), None);
+        assert_eq!(Color::from_name("red"), Some(Color::new(255, 0, 0)));
+        assert_eq!(Color::from_name("KEY_A"), None);
+    }
+}
@@ -0,0 +1,182 @@
+/// Constant symbols (`.equ`, `.set`, `NAME = expr`)
+///
+/// As in GNU as, `.set` and `=` may assign a name again: each value holds from its
+/// definition up to the next one, and a use before the first definition takes the
+/// first value. A name defined with `.equ` cannot be defined again. Constants may
+/// refer to labels and to constants defined further down, so they are evaluated
+/// together, repeating while progress is made.
+use std::collections::HashMap;
+use thiserror::Error;
+use crate::ast::{Directive, Item, Program};
+use crate::expr::EvalError;
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ConstantError {
+    #[error("Symbol '{name}' is already defined")]
+    Redefinition { name: String, index: usize },
+
+    #[error("Cannot evaluate constant '{name}': {source}")]
+    Eval { name: String, source: EvalError, index: usize },
+}
+
+impl ConstantError {
+    /// Item index of the offending definition
+    pub fn index(&self) -> usize {
+        match self {
+            Self::Redefinition { index, .. } | Self::Eval { index, .. } => *index,
+        }
+    }
+}
+
+/// Values of the constants of a program
+#[derive(Debug, Clone, Default)]
+pub struct Constants {
+    /// Name -> (item index, value) of each definition, in item order
+    definitions: HashMap<String, Vec<(usize, Option<i64>)>>,
+}
+
+impl Constants {
+    /// Evaluate the constants of `program`; `symbol(name, index)` gives the value of any
+    /// other symbol (a label) used by item `index`, where known
+    pub fn evaluate<F>(program: &Program, symbol: F) -> Result<Self, ConstantError>
+    where
+        F: Fn(&str, usize) -> Option<i64>,
+    {
+        Self::evaluate_with(program, symbol, true)
+    }
+
+    /// Values of the constants that can be told before layout, where labels have no
+    /// value; the others, and any invalid definitions, are left unknown
+    pub fn evaluate_known(program: &Program) -> Self {
+        Self::evaluate_with(program, |_, _| None, false).unwrap_or_default()
+    }
+
+    /// `strict`: fail on the first invalid definition instead of leaving it unknown
+    fn evaluate_with<F>(program: &Program, symbol: F, strict: bool) -> Result<Self, ConstantError>
+    where
+        F: Fn(&str, usize) -> Option<i64>,
+    {
+        let mut constants = Self::default();
+        let mut reassignable: HashMap<&str, bool> = HashMap::new();
+        let mut pending = Vec::new();
+        for (index, item) in program.items.iter().enumerate() {
+            let Item::Directive(Directive::Equ(name, expr, may_reassign)) = &item.node else {
+                continue;
+            };
+            if let Some(&previous) = reassignable.get(name.as_str()) {
+                if strict && !(previous && *may_reassign) {
+                    return Err(ConstantError::Redefinition { name: name.clone(), index });
+                }
+            }
+            reassignable.insert(name, *may_reassign);
+            constants.definitions.entry(name.clone()).or_default().push((index, None));
+            pending.push((name, expr, index));
+        }
+
+        while !pending.is_empty() {
+            let before = pending.len();
+            let mut deferred = Vec::new();
+            let mut first_error = None;
+            for (name, expr, index) in pending {
+                let lookup = |sym: &str| if constants.contains(sym) {
+                    constants.value_at(sym, index)
+                } else {
+                    symbol(sym, index)
+                };
+                match expr.eval(&lookup) {
+                    Ok(value) => constants.set(name, index, value),
+                    Err(EvalError::UndefinedSymbol(sym)) => {
+                        first_error.get_or_insert((name, EvalError::UndefinedSymbol(sym), index));
+                        deferred.push((name, expr, index));
+                    }
+                    Err(source) if strict => return Err(ConstantError::Eval { name: name.clone(), source, index }),
+                    Err(_) => {}
+                }
+            }
+            if deferred.len() == before {
+                if !strict {
+                    break;
+                }
+                // No progress: undefined or circular references
+                let (name, source, index) = first_error.expect("deferred constants have an error");
+                return Err(ConstantError::Eval { name: name.clone(), source, index });
+            }
+            pending = deferred;
+        }
+        Ok(constants)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.definitions.contains_key(name)
+    }
+
+    /// Value of `name` as used by item `index`
+    pub fn value_at(&self, name: &str, index: usize) -> Option<i64> {
+        let definitions = self.definitions.get(name)?;
+        definitions.iter().rev()
+            .find(|(at, _)| *at < index)
+            .or(definitions.first())
+            .and_then(|(_, value)| *value)
+    }
+
+    fn set(&mut self, name: &str, index: usize, value: i64) {
+        if let Some(definition) = self.definitions.get_mut(name).and_then(|d| d.iter_mut().find(|(at, _)| *at == index)) {
+            definition.1 = Some(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{BinaryOp, Expr};
+    use crate::span::{Span, Spanned};
+
+    fn program(definitions: Vec<(&str, Expr, bool)>) -> Program {
+        let items = definitions.into_iter()
+            .map(|(name, expr, reassignable)| Spanned::new(Item::Directive(Directive::Equ(name.to_string(), expr, reassignable)), Span::new(0, 0)))
+            .collect();
+        Program { items }
+    }
+
+    #[test]
+    fn test_forward_references() {
+        let symbol = |name: &str| Expr::Symbol(name.to_string());
+        let defined = program(vec![
+            ("A", Expr::binary(BinaryOp::Add, symbol("B"), symbol("end")), false),
+            ("B", Expr::Number(4), false),
+        ]);
+        let constants = Constants::evaluate(&defined, |name, _| (name == "end").then_some(0x100)).unwrap();
+        assert_eq!(constants.value_at("A", 0), Some(0x104));
+        assert!(!constants.contains("end"));
+
+        let error = Constants::evaluate(&defined, |_, _| None).unwrap_err();
+        assert!(matches!(error, ConstantError::Eval { ref name, .. } if name == "A"));
+
+        let known = Constants::evaluate_known(&defined);
+        assert_eq!(known.value_at("A", 0), None);
+        assert_eq!(known.value_at("B", 0), Some(4));
+    }
+
+    #[test]
+    fn test_reassignment() {
+        let x = || Expr::Symbol("X".to_string());
+        let set = program(vec![
+            ("X", Expr::Number(1), true),
+            ("Y", x(), false),
+            ("X", Expr::binary(BinaryOp::Add, x(), Expr::Number(1)), true),
+            ("Z", x(), false),
+        ]);
+        let constants = Constants::evaluate(&set, |_, _| None).unwrap();
+        assert_eq!(constants.value_at("Y", 4), Some(1));
+        assert_eq!(constants.value_at("Z", 4), Some(2));
+        // Before the first definition
+        assert_eq!(constants.value_at("X", 0), Some(1));
+
+        let redefined = program(vec![("X", Expr::Number(1), false), ("X", Expr::Number(2), true)]);
+        assert_eq!(
+            Constants::evaluate(&redefined, |_, _| None).unwrap_err(),
+            ConstantError::Redefinition { name: "X".to_string(), index: 1 },
+        );
+    }
+}
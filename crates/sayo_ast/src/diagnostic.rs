@@ -0,0 +1,195 @@
+/// Diagnostics shared by the parser, semantic checker, layout and encoder
+///
+/// Every error or warning is turned into a `Diagnostic` with a stable code
+/// (`E0105`, `W0101`, ...), a primary span and optional secondary labels, notes and
+/// help, so that `sayoasm` and the language server report them the same way.
+/// Codes are grouped by stage: `E00xx` parser, `E01xx` semantic checker,
+/// `E02xx` layout, `E03xx` encoder; warnings use `W` with the same numbering.
+use crate::source::SourceMap;
+use crate::span::Span;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error => write!(f, "error"),
+            Self::Warning => write!(f, "warning"),
+            Self::Info => write!(f, "info"),
+        }
+    }
+}
+
+/// A secondary location with a short explanation ("first defined here")
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpanLabel {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub code: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    /// Where the problem is (None for whole-program problems such as a missing entry point)
+    pub span: Option<Span>,
+    pub labels: Vec<SpanLabel>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            severity,
+            message: message.into(),
+            span: None,
+            labels: Vec::new(),
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    pub fn error(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, code, message)
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, code, message)
+    }
+
+    pub fn info(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(Severity::Info, code, message)
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(SpanLabel { span, message: message.into() });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Render for a terminal, rustc style:
+    ///
+    /// ```text
+    /// error[E0105]: Undefined label 'loop'
+    ///  --> main.s:3:9
+    ///   |
+    /// 3 |     JMP loop
+    ///   |         ^^^^
+    ///   = help: ...
+    /// ```
+    pub fn render(&self, sources: &SourceMap) -> String {
+        let mut out = format!("{}\n", self);
+        let spans = self.span.iter().map(|span| (*span, '^', ""))
+            .chain(self.labels.iter().map(|label| (label.span, '-', label.message.as_str())));
+        for (i, (span, marker, message)) in spans.enumerate() {
+            render_snippet(&mut out, sources, span, if i == 0 { "-->" } else { ":::" }, marker, message);
+        }
+        for note in &self.notes {
+            out.push_str(&format!("  = note: {}\n", note));
+        }
+        if let Some(help) = &self.help {
+            out.push_str(&format!("  = help: {}\n", help));
+        }
+        out
+    }
+}
+
+/// Location, source line and underline of one span, followed by the `.include` chain
+fn render_snippet(out: &mut String, sources: &SourceMap, span: Span, arrow: &str, marker: char, message: &str) {
+    let text = sources.text(span.file);
+    let (line, col) = sources.line_col(span.file, span.start);
+    let source_line = text.lines().nth(line - 1).unwrap_or("");
+    let gutter = " ".repeat(line.to_string().len());
+
+    out.push_str(&format!("{}{} {}:{}:{}\n", gutter, arrow, display_path(sources, span), line, col));
+    out.push_str(&format!("{} |\n", gutter));
+    out.push_str(&format!("{} | {}\n", line, source_line));
+    // Underline up to the end of the span or of its first line
+    let start = span.start.min(text.len());
+    let width = text[start..span.end.clamp(start, text.len())].lines().next().map_or(0, |s| s.chars().count()).max(1);
+    out.push_str(&format!("{} | {}{}", gutter, " ".repeat(col - 1), marker.to_string().repeat(width)));
+    if !message.is_empty() {
+        out.push_str(&format!(" {}", message));
+    }
+    out.push('\n');
+
+    let mut file = span.file;
+    while let Some(site) = sources.get(file).and_then(|f| f.included_from) {
+        let (line, col) = sources.line_col(site.file, site.start);
+        out.push_str(&format!("{} = note: included from {}:{}:{}\n", gutter, display_path(sources, site), line, col));
+        file = site.file;
+    }
+}
+
+fn display_path(sources: &SourceMap, span: Span) -> String {
+    match sources.path(span.file).display().to_string() {
+        path if path.is_empty() => "<input>".to_string(),
+        path => path,
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut sources = SourceMap::with_root("main.s", ".include \"keys.s\"\nmain:\n    JMP loop\n");
+        let keys = sources.add("keys.s", "loop:\n", Some(Span::new(0, 17)));
+        let diagnostic = Diagnostic::error("E0107", "Duplicate label definition 'loop'")
+            .with_span(Span::new(0, 4).with_file(keys))
+            .with_label(Span::new(32, 36), "used here")
+            .with_help("rename one of the labels");
+
+        assert_eq!(diagnostic.to_string(), "error[E0107]: Duplicate label definition 'loop'");
+        assert_eq!(
+            diagnostic.render(&sources),
+            "error[E0107]: Duplicate label definition 'loop'\n\
+             \x20--> keys.s:1:1\n\
+             \x20 |\n\
+             1 | loop:\n\
+             \x20 | ^^^^\n\
+             \x20 = note: included from main.s:1:1\n\
+             \x20::: main.s:3:9\n\
+             \x20 |\n\
+             3 |     JMP loop\n\
+             \x20 |         ---- used here\n\
+             \x20 = help: rename one of the labels\n"
+        );
+    }
+}
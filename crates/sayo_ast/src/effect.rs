@@ -0,0 +1,180 @@
+/// Instruction effects
+///
+/// What an instruction does beyond its operands: registers it uses without naming them
+/// (`MUL_A` reads `A` and `B`), the `CY` flag set by the compares and read by `JC`/`JNC`,
+/// the stack entries pushed and popped, and where execution continues afterwards. The
+/// same tables fill in `InstructionMetadata`, so the semantic passes, the LSP hovers and
+/// the generated instruction reference agree.
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use crate::instr::Mnemonic;
+use crate::reg::Register;
+
+/// Processor flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Flag {
+    /// Carry, set by the compares (`CY = i < j`)
+    CY,
+}
+
+impl fmt::Display for Flag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Where execution continues after an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlFlow {
+    /// The next instruction
+    Next,
+    /// The target operand (`JMP`, `SJMP`, `AJMP`)
+    Jump,
+    /// The label operand or the next instruction (`JZ`, `CJNE`, ...); the target is the
+    /// last operand
+    Branch,
+    /// An address held in a register or the next instruction (`JFZ`, `CJFNE`, ...)
+    RegisterBranch,
+    /// The target operand, then the next instruction once it returns
+    Call,
+    /// The address on top of the stack
+    Return,
+    /// Nowhere: the script ends (`END`, `EXIT`, `JMP_TO_SCRIPT`)
+    Exit,
+    /// The script ends or the next instruction (`EXIT_IF_PRESS`, ...)
+    ExitIf,
+    /// The start of the program (`RES`)
+    Restart,
+}
+
+impl fmt::Display for ControlFlow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Self::Next => "next instruction",
+            Self::Jump => "jump",
+            Self::Branch => "conditional jump",
+            Self::RegisterBranch => "conditional jump to register",
+            Self::Call => "call",
+            Self::Return => "return",
+            Self::Exit => "exit",
+            Self::ExitIf => "conditional exit",
+            Self::Restart => "restart",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+impl Mnemonic {
+    pub fn control_flow(&self) -> ControlFlow {
+        match self {
+            Self::JMP | Self::SJMP | Self::AJMP => ControlFlow::Jump,
+            Self::JC | Self::JNC | Self::JZ | Self::JNZ | Self::DJNZ
+            | Self::JA | Self::JB | Self::JG | Self::JL | Self::CJNE => ControlFlow::Branch,
+            Self::JFC | Self::JFNC | Self::JFZ | Self::JFNZ | Self::DJFNZ
+            | Self::JFA | Self::JFB | Self::JFG | Self::JFL | Self::CJFNE => ControlFlow::RegisterBranch,
+            Self::CALL => ControlFlow::Call,
+            Self::RET => ControlFlow::Return,
+            Self::END | Self::EXIT | Self::JMP_TO_SCRIPT => ControlFlow::Exit,
+            Self::EXIT_IF_RELEAS | Self::EXIT_IF_PRESS | Self::EXIT_IF_ANYKEY => ControlFlow::ExitIf,
+            Self::RES => ControlFlow::Restart,
+            _ => ControlFlow::Next,
+        }
+    }
+
+    /// Registers read without being named as operands
+    pub fn implicit_reads(&self) -> &'static [Register] {
+        match self {
+            Self::MUL_A | Self::DIV_A | Self::IMUL_A => &[Register::A, Register::B],
+            Self::ADD_A | Self::SUB_A | Self::OR_A | Self::ADD8_A | Self::SUB8_A | Self::OR8_A => &[Register::A],
+            Self::WAIT_IF_RELEASE | Self::WAIT_IF_PRESS | Self::EXIT_IF_RELEAS | Self::EXIT_IF_PRESS => &[Register::KeyIO],
+            Self::EXIT_IF_ANYKEY => &[Register::SysKeyCount],
+            _ => &[],
+        }
+    }
+
+    /// Registers written without being named as operands
+    pub fn implicit_writes(&self) -> &'static [Register] {
+        match self {
+            Self::DIV_A => &[Register::A, Register::B],
+            Self::MUL_A | Self::IMUL_A
+            | Self::ADD_A | Self::SUB_A | Self::OR_A | Self::ADD8_A | Self::SUB8_A | Self::OR8_A => &[Register::A],
+            Self::LED_CTRL => &[Register::SelectedLed],
+            Self::LED_COL => &[Register::SelectedLedCol],
+            _ => &[],
+        }
+    }
+
+    pub fn flags_read(&self) -> &'static [Flag] {
+        match self {
+            Self::JC | Self::JNC | Self::JFC | Self::JFNC => &[Flag::CY],
+            _ => &[],
+        }
+    }
+
+    pub fn flags_written(&self) -> &'static [Flag] {
+        match self {
+            Self::CMP | Self::CJNE | Self::CJFNE => &[Flag::CY],
+            _ => &[],
+        }
+    }
+
+    /// Stack entries pushed (positive) or popped (negative); `CALL` and `RET` count the
+    /// return address
+    pub fn stack_effect(&self) -> i8 {
+        match self {
+            Self::PUSH | Self::CALL => 1,
+            Self::POP | Self::RET => -1,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accumulator_effects() {
+        assert_eq!(Mnemonic::DIV_A.implicit_reads(), &[Register::A, Register::B]);
+        assert_eq!(Mnemonic::DIV_A.implicit_writes(), &[Register::A, Register::B]);
+        assert_eq!(Mnemonic::MUL_A.implicit_writes(), &[Register::A]);
+        assert_eq!(Mnemonic::ADD8_A.implicit_reads(), &[Register::A]);
+        assert!(Mnemonic::ADD_R.implicit_reads().is_empty());
+    }
+
+    #[test]
+    fn test_carry_flag() {
+        assert_eq!(Mnemonic::CMP.flags_written(), &[Flag::CY]);
+        assert_eq!(Mnemonic::JNC.flags_read(), &[Flag::CY]);
+        assert!(Mnemonic::JZ.flags_read().is_empty());
+    }
+
+    #[test]
+    fn test_control_flow() {
+        assert_eq!(Mnemonic::SJMP.control_flow(), ControlFlow::Jump);
+        assert_eq!(Mnemonic::CJNE.control_flow(), ControlFlow::Branch);
+        assert_eq!(Mnemonic::JFZ.control_flow(), ControlFlow::RegisterBranch);
+        assert_eq!(Mnemonic::JMP_TO_SCRIPT.control_flow(), ControlFlow::Exit);
+        assert_eq!(Mnemonic::ADD.control_flow(), ControlFlow::Next);
+        // The label spelling of a conditional jump and its register-target form
+        for mnemonic in Mnemonic::all_variants() {
+            if let Some(form) = mnemonic.register_target_form() {
+                assert_eq!(mnemonic.control_flow(), ControlFlow::Branch);
+                assert_eq!(form.control_flow(), ControlFlow::RegisterBranch);
+            }
+        }
+    }
+
+    #[test]
+    fn test_metadata() {
+        let metadata = Mnemonic::CALL.metadata();
+        assert_eq!(metadata.control_flow, ControlFlow::Call);
+        assert_eq!(metadata.stack, 1);
+        assert_eq!(Mnemonic::POP.metadata().stack, -1);
+        assert_eq!(Mnemonic::IMUL_A.metadata().implicit_reads, vec![Register::A, Register::B]);
+        // INC reads the register it increments, MOV does not
+        assert!(Mnemonic::INC.metadata().operands[0].is_read);
+        assert!(!Mnemonic::MOV.metadata().operands[0].is_read);
+    }
+}
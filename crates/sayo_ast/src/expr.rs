@@ -0,0 +1,279 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use thiserror::Error;
+
+/// Unary operators in constant expressions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UnaryOp {
+    Neg,        // -x
+    Not,        // ~x
+    LogicalNot, // !x
+}
+
+/// Binary operators in constant expressions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BinaryOp {
+    Mul, Div, Mod,
+    Add, Sub,
+    Shl, Shr,
+    Lt, Le, Gt, Ge,
+    Eq, Ne,
+    And, Xor, Or,
+    LogicalAnd, LogicalOr,
+}
+
+impl BinaryOp {
+    /// Binding strength (higher binds tighter), C-like ordering
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Self::Mul | Self::Div | Self::Mod => 9,
+            Self::Add | Self::Sub => 8,
+            Self::Shl | Self::Shr => 7,
+            Self::Lt | Self::Le | Self::Gt | Self::Ge => 6,
+            Self::Eq | Self::Ne => 5,
+            Self::And => 4,
+            Self::Xor => 3,
+            Self::Or => 2,
+            Self::LogicalAnd => 1,
+            Self::LogicalOr => 0,
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Mod => "%",
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Shl => "<<",
+            Self::Shr => ">>",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::And => "&",
+            Self::Xor => "^",
+            Self::Or => "|",
+            Self::LogicalAnd => "&&",
+            Self::LogicalOr => "||",
+        }
+    }
+}
+
+/// Constant expression (e.g. `table+4`, `.Lfunc_end0-foo`, `(0xFF << 16) | 0x80`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Expr {
+    Number(i64),
+    Symbol(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+/// Errors produced while evaluating an expression
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum EvalError {
+    #[error("Undefined symbol '{0}' in expression")]
+    UndefinedSymbol(String),
+
+    #[error("Division by zero in expression")]
+    DivisionByZero,
+
+    #[error("Arithmetic overflow in expression")]
+    Overflow,
+
+    #[error("Shift amount {0} out of range")]
+    InvalidShift(i64),
+}
+
+impl Expr {
+    pub fn unary(op: UnaryOp, operand: Expr) -> Self {
+        Self::Unary(op, Box::new(operand))
+    }
+
+    pub fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Self {
+        Self::Binary(op, Box::new(lhs), Box::new(rhs))
+    }
+
+    /// Evaluate the expression, resolving symbols through `lookup`
+    pub fn eval<F>(&self, lookup: &F) -> Result<i64, EvalError>
+    where
+        F: Fn(&str) -> Option<i64>,
+    {
+        match self {
+            Self::Number(n) => Ok(*n),
+            Self::Symbol(name) => lookup(name).ok_or_else(|| EvalError::UndefinedSymbol(name.clone())),
+            Self::Unary(op, operand) => {
+                let value = operand.eval(lookup)?;
+                match op {
+                    UnaryOp::Neg => value.checked_neg().ok_or(EvalError::Overflow),
+                    UnaryOp::Not => Ok(!value),
+                    UnaryOp::LogicalNot => Ok((value == 0) as i64),
+                }
+            }
+            Self::Binary(op, lhs, rhs) => {
+                let l = lhs.eval(lookup)?;
+                let r = rhs.eval(lookup)?;
+                match op {
+                    BinaryOp::Add => l.checked_add(r).ok_or(EvalError::Overflow),
+                    BinaryOp::Sub => l.checked_sub(r).ok_or(EvalError::Overflow),
+                    BinaryOp::Mul => l.checked_mul(r).ok_or(EvalError::Overflow),
+                    BinaryOp::Div => {
+                        if r == 0 {
+                            return Err(EvalError::DivisionByZero);
+                        }
+                        l.checked_div(r).ok_or(EvalError::Overflow)
+                    }
+                    BinaryOp::Mod => {
+                        if r == 0 {
+                            return Err(EvalError::DivisionByZero);
+                        }
+                        l.checked_rem(r).ok_or(EvalError::Overflow)
+                    }
+                    BinaryOp::Shl | BinaryOp::Shr => {
+                        if !(0..64).contains(&r) {
+                            return Err(EvalError::InvalidShift(r));
+                        }
+                        if *op == BinaryOp::Shl {
+                            Ok(l << r)
+                        } else {
+                            Ok(l >> r)
+                        }
+                    }
+                    // Comparisons and logical operators yield 1 (true) or 0 (false)
+                    BinaryOp::Lt => Ok((l < r) as i64),
+                    BinaryOp::Le => Ok((l <= r) as i64),
+                    BinaryOp::Gt => Ok((l > r) as i64),
+                    BinaryOp::Ge => Ok((l >= r) as i64),
+                    BinaryOp::Eq => Ok((l == r) as i64),
+                    BinaryOp::Ne => Ok((l != r) as i64),
+                    BinaryOp::And => Ok(l & r),
+                    BinaryOp::Xor => Ok(l ^ r),
+                    BinaryOp::Or => Ok(l | r),
+                    BinaryOp::LogicalAnd => Ok((l != 0 && r != 0) as i64),
+                    BinaryOp::LogicalOr => Ok((l != 0 || r != 0) as i64),
+                }
+            }
+        }
+    }
+
+    /// Evaluate an expression that must not reference any symbol
+    pub fn eval_const(&self) -> Result<i64, EvalError> {
+        self.eval(&|_| None)
+    }
+
+    /// Names of all symbols referenced by this expression, in source order
+    pub fn symbols(&self) -> Vec<&str> {
+        let mut out = Vec::new();
+        self.collect_symbols(&mut out);
+        out
+    }
+
+    fn collect_symbols<'a>(&'a self, out: &mut Vec<&'a str>) {
+        match self {
+            Self::Number(_) => {}
+            Self::Symbol(name) => out.push(name),
+            Self::Unary(_, operand) => operand.collect_symbols(out),
+            Self::Binary(_, lhs, rhs) => {
+                lhs.collect_symbols(out);
+                rhs.collect_symbols(out);
+            }
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Number(n) => write!(f, "{}", n),
+            Self::Symbol(name) => write!(f, "{}", name),
+            Self::Unary(op, operand) => {
+                let sym = match op {
+                    UnaryOp::Neg => "-",
+                    UnaryOp::Not => "~",
+                    UnaryOp::LogicalNot => "!",
+                };
+                if matches!(**operand, Self::Binary(..)) {
+                    write!(f, "{}({})", sym, operand)
+                } else {
+                    write!(f, "{}{}", sym, operand)
+                }
+            }
+            Self::Binary(op, lhs, rhs) => {
+                // Parenthesize children that bind looser than this operator
+                match **lhs {
+                    Self::Binary(l_op, ..) if l_op.precedence() < op.precedence() => write!(f, "({})", lhs)?,
+                    _ => write!(f, "{}", lhs)?,
+                }
+                write!(f, "{}", op.symbol())?;
+                match **rhs {
+                    Self::Binary(r_op, ..) if r_op.precedence() <= op.precedence() => write!(f, "({})", rhs),
+                    _ => write!(f, "{}", rhs),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval_precedence() {
+        // (0xFF << 16) | 0x80
+        let expr = Expr::binary(
+            BinaryOp::Or,
+            Expr::binary(BinaryOp::Shl, Expr::Number(0xFF), Expr::Number(16)),
+            Expr::Number(0x80),
+        );
+        assert_eq!(expr.eval_const(), Ok(0xFF0080));
+        assert_eq!(expr.to_string(), "255<<16|128");
+    }
+
+    #[test]
+    fn test_eval_label_difference() {
+        let expr = Expr::binary(
+            BinaryOp::Sub,
+            Expr::Symbol(".Lfunc_end0".to_string()),
+            Expr::Symbol("foo".to_string()),
+        );
+        let lookup = |name: &str| match name {
+            ".Lfunc_end0" => Some(0x20),
+            "foo" => Some(0x0C),
+            _ => None,
+        };
+        assert_eq!(expr.eval(&lookup), Ok(0x14));
+        assert_eq!(expr.symbols(), vec![".Lfunc_end0", "foo"]);
+        assert_eq!(expr.eval_const(), Err(EvalError::UndefinedSymbol(".Lfunc_end0".to_string())));
+    }
+
+    #[test]
+    fn test_eval_errors() {
+        let div = Expr::binary(BinaryOp::Div, Expr::Number(1), Expr::Number(0));
+        assert_eq!(div.eval_const(), Err(EvalError::DivisionByZero));
+
+        let shift = Expr::binary(BinaryOp::Shl, Expr::Number(1), Expr::Number(64));
+        assert_eq!(shift.eval_const(), Err(EvalError::InvalidShift(64)));
+
+        let neg = Expr::unary(UnaryOp::Neg, Expr::Number(i64::MIN));
+        assert_eq!(neg.eval_const(), Err(EvalError::Overflow));
+    }
+
+    #[test]
+    fn test_eval_conditions() {
+        // KEYS >= 8 && !(KEYS == 12)
+        let keys = || Expr::Symbol("KEYS".to_string());
+        let expr = Expr::binary(
+            BinaryOp::LogicalAnd,
+            Expr::binary(BinaryOp::Ge, keys(), Expr::Number(8)),
+            Expr::unary(UnaryOp::LogicalNot, Expr::binary(BinaryOp::Eq, keys(), Expr::Number(12))),
+        );
+        assert_eq!(expr.eval(&|_| Some(16)), Ok(1));
+        assert_eq!(expr.eval(&|_| Some(12)), Ok(0));
+        assert_eq!(expr.eval(&|_| Some(4)), Ok(0));
+        assert_eq!(expr.to_string(), "KEYS>=8&&!(KEYS==12)");
+    }
+}
@@ -0,0 +1,212 @@
+/// Built-in names for the HID codes taken by the PRESS_* / RELEASE_* instructions
+///
+/// Codes are HID usage IDs: the keyboard page for keys and modifiers, the button
+/// page for mouse and gamepad buttons and the consumer page for media keys.
+use crate::instr::Mnemonic;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Which table an instruction's code comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum HidNamespace {
+    /// Keyboard keys (`PRESS_GK`)
+    Key,
+    /// Keyboard modifiers (`PRESS_SK`)
+    Modifier,
+    /// Mouse buttons (`PRESS_MK`)
+    Mouse,
+    /// Media keys (`PRESS_MU`)
+    Media,
+    /// Gamepad buttons (`PRESS_GAK`)
+    Gamepad,
+}
+
+impl HidNamespace {
+    pub const ALL: [Self; 5] = [Self::Key, Self::Modifier, Self::Mouse, Self::Media, Self::Gamepad];
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Key => "keyboard key",
+            Self::Modifier => "modifier key",
+            Self::Mouse => "mouse button",
+            Self::Media => "media key",
+            Self::Gamepad => "gamepad button",
+        }
+    }
+
+    /// Built-in (name, code) pairs
+    pub fn codes(self) -> &'static [(&'static str, u8)] {
+        match self {
+            Self::Key => KEYS,
+            Self::Modifier => MODIFIERS,
+            Self::Mouse => MOUSE_BUTTONS,
+            Self::Media => MEDIA_KEYS,
+            Self::Gamepad => GAMEPAD_BUTTONS,
+        }
+    }
+
+    /// Built-in name of a code, if it has one
+    pub fn name_of(self, code: u8) -> Option<&'static str> {
+        self.codes().iter().find(|(_, c)| *c == code).map(|(name, _)| *name)
+    }
+}
+
+impl fmt::Display for HidNamespace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+/// A code referenced by its built-in name, e.g. `KEY_A`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct HidCode {
+    pub namespace: HidNamespace,
+    pub code: u8,
+}
+
+impl HidCode {
+    /// Look up a built-in name (case-insensitive, like mnemonics and registers)
+    pub fn from_name(name: &str) -> Option<Self> {
+        let upper = name.to_ascii_uppercase();
+        HidNamespace::ALL.into_iter().find_map(|namespace| {
+            namespace.codes().iter()
+                .find(|(n, _)| *n == upper)
+                .map(|&(_, code)| Self { namespace, code })
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        // Every HidCode is built from a table entry, and codes are unique per namespace
+        self.namespace.name_of(self.code).unwrap_or("?")
+    }
+}
+
+impl fmt::Display for HidCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// What a PRESS_* / RELEASE_* instruction does to its code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeyAction {
+    Press,
+    Release,
+}
+
+impl Mnemonic {
+    /// Namespace of the u8 code taken by a PRESS_* / RELEASE_* instruction
+    /// The `_VAL` forms take the code in a register and return `None`
+    pub fn hid_namespace(&self) -> Option<HidNamespace> {
+        match self {
+            Self::PRESS_GK | Self::RELEASE_GK => Some(HidNamespace::Key),
+            Self::PRESS_SK | Self::RELEASE_SK => Some(HidNamespace::Modifier),
+            Self::PRESS_MK | Self::RELEASE_MK => Some(HidNamespace::Mouse),
+            Self::PRESS_MU | Self::RELEASE_MU => Some(HidNamespace::Media),
+            Self::PRESS_GAK | Self::RELEASE_GAK => Some(HidNamespace::Gamepad),
+            _ => None,
+        }
+    }
+
+    /// Namespace and action of a PRESS_* / RELEASE_* instruction, `_VAL` forms included
+    pub fn key_action(&self) -> Option<(HidNamespace, KeyAction)> {
+        match self {
+            Self::PRESS_GK | Self::PRESS_GK_VAL => Some((HidNamespace::Key, KeyAction::Press)),
+            Self::PRESS_SK | Self::PRESS_SK_VAL => Some((HidNamespace::Modifier, KeyAction::Press)),
+            Self::PRESS_MK | Self::PRESS_MK_VAL => Some((HidNamespace::Mouse, KeyAction::Press)),
+            Self::PRESS_MU | Self::PRESS_MU_VAL => Some((HidNamespace::Media, KeyAction::Press)),
+            Self::PRESS_GAK | Self::PRESS_GAK_VAL => Some((HidNamespace::Gamepad, KeyAction::Press)),
+            Self::RELEASE_GK | Self::RELEASE_GK_VAL => Some((HidNamespace::Key, KeyAction::Release)),
+            Self::RELEASE_SK | Self::RELEASE_SK_VAL => Some((HidNamespace::Modifier, KeyAction::Release)),
+            Self::RELEASE_MK | Self::RELEASE_MK_VAL => Some((HidNamespace::Mouse, KeyAction::Release)),
+            Self::RELEASE_MU | Self::RELEASE_MU_VAL => Some((HidNamespace::Media, KeyAction::Release)),
+            Self::RELEASE_GAK | Self::RELEASE_GAK_VAL => Some((HidNamespace::Gamepad, KeyAction::Release)),
+            _ => None,
+        }
+    }
+}
+
+/// Keyboard page (0x07) keys
+const KEYS: &[(&str, u8)] = &[
+    ("KEY_A", 0x04), ("KEY_B", 0x05), ("KEY_C", 0x06), ("KEY_D", 0x07), ("KEY_E", 0x08),
+    ("KEY_F", 0x09), ("KEY_G", 0x0A), ("KEY_H", 0x0B), ("KEY_I", 0x0C), ("KEY_J", 0x0D),
+    ("KEY_K", 0x0E), ("KEY_L", 0x0F), ("KEY_M", 0x10), ("KEY_N", 0x11), ("KEY_O", 0x12),
+    ("KEY_P", 0x13), ("KEY_Q", 0x14), ("KEY_R", 0x15), ("KEY_S", 0x16), ("KEY_T", 0x17),
+    ("KEY_U", 0x18), ("KEY_V", 0x19), ("KEY_W", 0x1A), ("KEY_X", 0x1B), ("KEY_Y", 0x1C),
+    ("KEY_Z", 0x1D), ("KEY_1", 0x1E), ("KEY_2", 0x1F), ("KEY_3", 0x20), ("KEY_4", 0x21),
+    ("KEY_5", 0x22), ("KEY_6", 0x23), ("KEY_7", 0x24), ("KEY_8", 0x25), ("KEY_9", 0x26),
+    ("KEY_0", 0x27), ("KEY_ENTER", 0x28), ("KEY_ESC", 0x29), ("KEY_BACKSPACE", 0x2A),
+    ("KEY_TAB", 0x2B), ("KEY_SPACE", 0x2C), ("KEY_MINUS", 0x2D), ("KEY_EQUAL", 0x2E),
+    ("KEY_LEFT_BRACKET", 0x2F), ("KEY_RIGHT_BRACKET", 0x30), ("KEY_BACKSLASH", 0x31),
+    ("KEY_NON_US_HASH", 0x32), ("KEY_SEMICOLON", 0x33), ("KEY_QUOTE", 0x34), ("KEY_GRAVE", 0x35),
+    ("KEY_COMMA", 0x36), ("KEY_DOT", 0x37), ("KEY_SLASH", 0x38), ("KEY_CAPS_LOCK", 0x39),
+    ("KEY_F1", 0x3A), ("KEY_F2", 0x3B), ("KEY_F3", 0x3C), ("KEY_F4", 0x3D), ("KEY_F5", 0x3E),
+    ("KEY_F6", 0x3F), ("KEY_F7", 0x40), ("KEY_F8", 0x41), ("KEY_F9", 0x42), ("KEY_F10", 0x43),
+    ("KEY_F11", 0x44), ("KEY_F12", 0x45), ("KEY_PRINT_SCREEN", 0x46), ("KEY_SCROLL_LOCK", 0x47),
+    ("KEY_PAUSE", 0x48), ("KEY_INSERT", 0x49), ("KEY_HOME", 0x4A), ("KEY_PAGE_UP", 0x4B),
+    ("KEY_DELETE", 0x4C), ("KEY_END", 0x4D), ("KEY_PAGE_DOWN", 0x4E), ("KEY_RIGHT", 0x4F),
+    ("KEY_LEFT", 0x50), ("KEY_DOWN", 0x51), ("KEY_UP", 0x52), ("KEY_NUM_LOCK", 0x53),
+    ("KEY_KP_SLASH", 0x54), ("KEY_KP_ASTERISK", 0x55), ("KEY_KP_MINUS", 0x56),
+    ("KEY_KP_PLUS", 0x57), ("KEY_KP_ENTER", 0x58), ("KEY_KP_1", 0x59), ("KEY_KP_2", 0x5A),
+    ("KEY_KP_3", 0x5B), ("KEY_KP_4", 0x5C), ("KEY_KP_5", 0x5D), ("KEY_KP_6", 0x5E),
+    ("KEY_KP_7", 0x5F), ("KEY_KP_8", 0x60), ("KEY_KP_9", 0x61), ("KEY_KP_0", 0x62),
+    ("KEY_KP_DOT", 0x63), ("KEY_NON_US_BACKSLASH", 0x64), ("KEY_APPLICATION", 0x65),
+    ("KEY_POWER", 0x66), ("KEY_KP_EQUAL", 0x67), ("KEY_F13", 0x68), ("KEY_F14", 0x69),
+    ("KEY_F15", 0x6A), ("KEY_F16", 0x6B), ("KEY_F17", 0x6C), ("KEY_F18", 0x6D), ("KEY_F19", 0x6E),
+    ("KEY_F20", 0x6F), ("KEY_F21", 0x70), ("KEY_F22", 0x71), ("KEY_F23", 0x72), ("KEY_F24", 0x73),
+];
+
+/// Keyboard page (0x07) modifiers
+const MODIFIERS: &[(&str, u8)] = &[
+    ("MOD_LCTRL", 0xE0), ("MOD_LSHIFT", 0xE1), ("MOD_LALT", 0xE2), ("MOD_LGUI", 0xE3),
+    ("MOD_RCTRL", 0xE4), ("MOD_RSHIFT", 0xE5), ("MOD_RALT", 0xE6), ("MOD_RGUI", 0xE7),
+];
+
+/// Button page (0x09), as used by mice
+const MOUSE_BUTTONS: &[(&str, u8)] = &[
+    ("MOUSE_LEFT", 0x01), ("MOUSE_RIGHT", 0x02), ("MOUSE_MIDDLE", 0x03), ("MOUSE_BACK", 0x04),
+    ("MOUSE_FORWARD", 0x05),
+];
+
+/// Consumer page (0x0C) usages that fit in a byte
+const MEDIA_KEYS: &[(&str, u8)] = &[
+    ("MEDIA_BRIGHTNESS_UP", 0x6F), ("MEDIA_BRIGHTNESS_DOWN", 0x70), ("MEDIA_PLAY", 0xB0),
+    ("MEDIA_PAUSE", 0xB1), ("MEDIA_RECORD", 0xB2), ("MEDIA_FAST_FORWARD", 0xB3),
+    ("MEDIA_REWIND", 0xB4), ("MEDIA_NEXT", 0xB5), ("MEDIA_PREV", 0xB6), ("MEDIA_STOP", 0xB7),
+    ("MEDIA_EJECT", 0xB8), ("MEDIA_PLAY_PAUSE", 0xCD), ("MEDIA_MUTE", 0xE2), ("MEDIA_VOL_UP", 0xE9),
+    ("MEDIA_VOL_DOWN", 0xEA),
+];
+
+/// Button page (0x09), as used by gamepads
+const GAMEPAD_BUTTONS: &[(&str, u8)] = &[
+    ("GAMEPAD_BUTTON_1", 0x01), ("GAMEPAD_BUTTON_2", 0x02), ("GAMEPAD_BUTTON_3", 0x03),
+    ("GAMEPAD_BUTTON_4", 0x04), ("GAMEPAD_BUTTON_5", 0x05), ("GAMEPAD_BUTTON_6", 0x06),
+    ("GAMEPAD_BUTTON_7", 0x07), ("GAMEPAD_BUTTON_8", 0x08), ("GAMEPAD_BUTTON_9", 0x09),
+    ("GAMEPAD_BUTTON_10", 0x0A), ("GAMEPAD_BUTTON_11", 0x0B), ("GAMEPAD_BUTTON_12", 0x0C),
+    ("GAMEPAD_BUTTON_13", 0x0D), ("GAMEPAD_BUTTON_14", 0x0E), ("GAMEPAD_BUTTON_15", 0x0F),
+    ("GAMEPAD_BUTTON_16", 0x10),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hid_names() {
+        let key_a = HidCode::from_name("KEY_A").unwrap();
+        assert_eq!(key_a, HidCode { namespace: HidNamespace::Key, code: 0x04 });
+        assert_eq!(HidCode::from_name("mod_lshift").map(|c| c.code), Some(0xE1));
+        assert_eq!(HidCode::from_name("MEDIA_VOL_UP").map(|c| c.namespace), Some(HidNamespace::Media));
+        assert_eq!(HidCode::from_name("KEY_NOPE"), None);
+
+        assert_eq!(HidNamespace::Key.name_of(0x28), Some("KEY_ENTER"));
+        assert_eq!(HidNamespace::Mouse.name_of(1), Some("MOUSE_LEFT"));
+        assert_eq!(HidNamespace::Key.name_of(0xFF), None);
+        assert_eq!(key_a.to_string(), "KEY_A");
+        assert_eq!(Mnemonic::RELEASE_MU.hid_namespace(), Some(HidNamespace::Media));
+        assert_eq!(Mnemonic::PRESS_GK_VAL.hid_namespace(), None);
+        assert_eq!(Mnemonic::PRESS_GK_VAL.key_action(), Some((HidNamespace::Key, KeyAction::Press)));
+        assert_eq!(Mnemonic::RELEASE_MK.key_action(), Some((HidNamespace::Mouse, KeyAction::Release)));
+        assert_eq!(Mnemonic::MO_XYZ.key_action(), None);
+    }
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use crate::effect::{ControlFlow, Flag};
+use crate::reg::Register;
 
 /// Operand types for instructions
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -20,21 +22,29 @@ pub enum OperandType {
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct OperandDef {
     pub op_type: OperandType,
+    /// Whether this operand is read (a written register is also read when the
+    /// instruction updates it, as `INC` does)
+    pub is_read: bool,
     /// Whether this operand is written to (for register operands)
     pub is_write: bool,
 }
 
 impl OperandDef {
     pub fn new(op_type: OperandType, is_write: bool) -> Self {
-        Self { op_type, is_write }
+        Self { op_type, is_read: !is_write, is_write }
     }
     
     pub fn read(op_type: OperandType) -> Self {
-        Self { op_type, is_write: false }
+        Self { op_type, is_read: true, is_write: false }
     }
     
     pub fn write(op_type: OperandType) -> Self {
-        Self { op_type, is_write: true }
+        Self { op_type, is_read: false, is_write: true }
+    }
+    
+    /// Read and written (`INC`, `ADD8`, `XCH`, ...)
+    pub fn update(op_type: OperandType) -> Self {
+        Self { op_type, is_read: true, is_write: true }
     }
 }
 
@@ -79,13 +89,13 @@ impl Mnemonic {
             Self::TB_XY => InstructionMetadata::new(self, 0x25, 5, vec![OperandDef::read(OperandType::I16), OperandDef::read(OperandType::I16)]),
             
             // Arithmetic with immediate
-            Self::AND8 => InstructionMetadata::new(self, 0x57, 3, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::U8)]),
+            Self::AND8 => InstructionMetadata::new(self, 0x57, 3, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::U8)]),
             Self::ADD8_A => InstructionMetadata::new(self, 0x59, 2, vec![OperandDef::read(OperandType::U8)]),
             Self::SUB8_A => InstructionMetadata::new(self, 0x5B, 2, vec![OperandDef::read(OperandType::U8)]),
             Self::OR8_A => InstructionMetadata::new(self, 0x5D, 2, vec![OperandDef::read(OperandType::U8)]),
-            Self::XOR8 => InstructionMetadata::new(self, 0x63, 3, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::U8)]),
-            Self::SHL8 => InstructionMetadata::new(self, 0x65, 3, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::U8)]),
-            Self::SHR8 => InstructionMetadata::new(self, 0x67, 3, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::U8)]),
+            Self::XOR8 => InstructionMetadata::new(self, 0x63, 3, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::U8)]),
+            Self::SHL8 => InstructionMetadata::new(self, 0x65, 3, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::U8)]),
+            Self::SHR8 => InstructionMetadata::new(self, 0x67, 3, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::U8)]),
             
             // MOV with immediate
             Self::MOV8 => InstructionMetadata::new(self, 0x6F, 3, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::U8)]),
@@ -93,43 +103,43 @@ impl Mnemonic {
             Self::MOV32 => InstructionMetadata::new(self, 0x71, 6, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::U32)]),
             
             // ADD/SUB with immediate
-            Self::ADD8 => InstructionMetadata::new(self, 0x73, 3, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::U8)]),
-            Self::ADD16 => InstructionMetadata::new(self, 0x74, 4, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::U16)]),
-            Self::ADD32 => InstructionMetadata::new(self, 0x7D, 6, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::U32)]),
-            Self::SUB8 => InstructionMetadata::new(self, 0x76, 3, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::U8)]),
-            Self::SUB16 => InstructionMetadata::new(self, 0x77, 4, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::U16)]),
-            Self::SUB32 => InstructionMetadata::new(self, 0x7E, 6, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::U32)]),
+            Self::ADD8 => InstructionMetadata::new(self, 0x73, 3, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::U8)]),
+            Self::ADD16 => InstructionMetadata::new(self, 0x74, 4, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::U16)]),
+            Self::ADD32 => InstructionMetadata::new(self, 0x7D, 6, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::U32)]),
+            Self::SUB8 => InstructionMetadata::new(self, 0x76, 3, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::U8)]),
+            Self::SUB16 => InstructionMetadata::new(self, 0x77, 4, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::U16)]),
+            Self::SUB32 => InstructionMetadata::new(self, 0x7E, 6, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::U32)]),
             
             // Bitwise with immediate
-            Self::AND16 => InstructionMetadata::new(self, 0x7A, 4, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::U16)]),
-            Self::AND32 => InstructionMetadata::new(self, 0x7F, 6, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::U32)]),
-            Self::OR16 => InstructionMetadata::new(self, 0x7B, 4, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::U16)]),
-            Self::OR32 => InstructionMetadata::new(self, 0x80, 6, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::U32)]),
-            Self::XOR16 => InstructionMetadata::new(self, 0x7C, 4, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::U16)]),
-            Self::XOR32 => InstructionMetadata::new(self, 0x81, 6, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::U32)]),
+            Self::AND16 => InstructionMetadata::new(self, 0x7A, 4, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::U16)]),
+            Self::AND32 => InstructionMetadata::new(self, 0x7F, 6, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::U32)]),
+            Self::OR16 => InstructionMetadata::new(self, 0x7B, 4, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::U16)]),
+            Self::OR32 => InstructionMetadata::new(self, 0x80, 6, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::U32)]),
+            Self::XOR16 => InstructionMetadata::new(self, 0x7C, 4, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::U16)]),
+            Self::XOR32 => InstructionMetadata::new(self, 0x81, 6, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::U32)]),
             
             // Sign extension with immediate
             Self::MOV8SX => InstructionMetadata::new(self, 0x8C, 3, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::I8)]),
             Self::MOV16SX => InstructionMetadata::new(self, 0x8D, 4, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::I16)]),
             
-            // Register operations that write to first operand
-            Self::DEC => InstructionMetadata::new(self, 0x5E, 2, vec![OperandDef::write(OperandType::Register)]),
-            Self::INC => InstructionMetadata::new(self, 0x5F, 2, vec![OperandDef::write(OperandType::Register)]),
+            // Register operations on the first operand
+            Self::DEC => InstructionMetadata::new(self, 0x5E, 2, vec![OperandDef::update(OperandType::Register)]),
+            Self::INC => InstructionMetadata::new(self, 0x5F, 2, vec![OperandDef::update(OperandType::Register)]),
             Self::CLR => InstructionMetadata::new(self, 0x68, 2, vec![OperandDef::write(OperandType::Register)]),
-            Self::NOT => InstructionMetadata::new(self, 0x69, 2, vec![OperandDef::write(OperandType::Register)]),
+            Self::NOT => InstructionMetadata::new(self, 0x69, 2, vec![OperandDef::update(OperandType::Register)]),
             Self::PUSH => InstructionMetadata::new(self, 0x6C, 2, vec![OperandDef::read(OperandType::Register)]),
             Self::POP => InstructionMetadata::new(self, 0x6D, 2, vec![OperandDef::write(OperandType::Register)]),
             
             // Two-register operations
-            Self::AND => InstructionMetadata::new(self, 0x56, 3, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::Register)]),
-            Self::ADD => InstructionMetadata::new(self, 0x72, 3, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::Register)]),
-            Self::SUB => InstructionMetadata::new(self, 0x75, 3, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::Register)]),
-            Self::OR => InstructionMetadata::new(self, 0x78, 3, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::Register)]),
-            Self::XOR => InstructionMetadata::new(self, 0x62, 3, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::Register)]),
-            Self::SHL => InstructionMetadata::new(self, 0x64, 3, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::Register)]),
-            Self::SHR => InstructionMetadata::new(self, 0x66, 3, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::Register)]),
+            Self::AND => InstructionMetadata::new(self, 0x56, 3, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::Register)]),
+            Self::ADD => InstructionMetadata::new(self, 0x72, 3, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::Register)]),
+            Self::SUB => InstructionMetadata::new(self, 0x75, 3, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::Register)]),
+            Self::OR => InstructionMetadata::new(self, 0x78, 3, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::Register)]),
+            Self::XOR => InstructionMetadata::new(self, 0x62, 3, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::Register)]),
+            Self::SHL => InstructionMetadata::new(self, 0x64, 3, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::Register)]),
+            Self::SHR => InstructionMetadata::new(self, 0x66, 3, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::Register)]),
             Self::MOV => InstructionMetadata::new(self, 0x6E, 3, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::Register)]),
-            Self::XCH => InstructionMetadata::new(self, 0x6A, 3, vec![OperandDef::write(OperandType::Register), OperandDef::write(OperandType::Register)]),
+            Self::XCH => InstructionMetadata::new(self, 0x6A, 3, vec![OperandDef::update(OperandType::Register), OperandDef::update(OperandType::Register)]),
             Self::MOVSX8b => InstructionMetadata::new(self, 0x8A, 3, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::Register)]),
             Self::MOVSX16b => InstructionMetadata::new(self, 0x8B, 3, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::Register)]),
             
@@ -219,7 +229,7 @@ impl Mnemonic {
             Self::JMP_TO_SCRIPT => InstructionMetadata::new(self, 0xF5, 2, vec![OperandDef::read(OperandType::U8)]),
             
             // Memory management
-            Self::MALLOC => InstructionMetadata::new(self, 0xF0, 2, vec![OperandDef::write(OperandType::Register)]),
+            Self::MALLOC => InstructionMetadata::new(self, 0xF0, 2, vec![OperandDef::update(OperandType::Register)]),
             Self::FREE => InstructionMetadata::new(self, 0xF1, 2, vec![OperandDef::read(OperandType::Register)]),
             Self::MOV_PC2REG => InstructionMetadata::new(self, 0xF6, 2, vec![OperandDef::write(OperandType::Register)]),
             Self::VALUE_RELOAD => InstructionMetadata::new(self, 0xF7, 2, vec![OperandDef::write(OperandType::Register)]),
@@ -228,7 +238,7 @@ impl Mnemonic {
             Self::CMP => InstructionMetadata::new(self, 0x6B, 3, vec![OperandDef::read(OperandType::Register), OperandDef::read(OperandType::Register)]),
             
             // Bitwise with register
-            Self::OR8 => InstructionMetadata::new(self, 0x79, 3, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::U8)]),
+            Self::OR8 => InstructionMetadata::new(self, 0x79, 3, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::U8)]),
             
             // Three-operand mouse/joystick instructions
             Self::MO_XYZ_VAL => InstructionMetadata::new(self, 0x22, 3, vec![OperandDef::read(OperandType::U8), OperandDef::read(OperandType::Register)]),
@@ -238,12 +248,12 @@ impl Mnemonic {
             // Three-operand jump instructions with registers
             Self::JFZ => InstructionMetadata::new(self, 0x4A, 3, vec![OperandDef::read(OperandType::Register), OperandDef::read(OperandType::Register)]),
             Self::JFNZ => InstructionMetadata::new(self, 0x4B, 3, vec![OperandDef::read(OperandType::Register), OperandDef::read(OperandType::Register)]),
-            Self::DJFNZ => InstructionMetadata::new(self, 0x4C, 3, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::Register)]),
+            Self::DJFNZ => InstructionMetadata::new(self, 0x4C, 3, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::Register)]),
             
             // Jump instructions with register and label
             Self::JZ => InstructionMetadata::new(self, 0x50, 4, vec![OperandDef::read(OperandType::Register), OperandDef::read(OperandType::Label)]),
             Self::JNZ => InstructionMetadata::new(self, 0x51, 4, vec![OperandDef::read(OperandType::Register), OperandDef::read(OperandType::Label)]),
-            Self::DJNZ => InstructionMetadata::new(self, 0x52, 4, vec![OperandDef::write(OperandType::Register), OperandDef::read(OperandType::Label)]),
+            Self::DJNZ => InstructionMetadata::new(self, 0x52, 4, vec![OperandDef::update(OperandType::Register), OperandDef::read(OperandType::Label)]),
             
             // Four-operand instructions
             Self::JFA => InstructionMetadata::new(self, 0x40, 4, vec![OperandDef::read(OperandType::Register), OperandDef::read(OperandType::Register), OperandDef::read(OperandType::Register)]),
@@ -261,6 +271,12 @@ impl Mnemonic {
             Self::JG => InstructionMetadata::new(self, 0x46, 5, vec![OperandDef::read(OperandType::Register), OperandDef::read(OperandType::Register), OperandDef::read(OperandType::Label)]),
             Self::JL => InstructionMetadata::new(self, 0x47, 5, vec![OperandDef::read(OperandType::Register), OperandDef::read(OperandType::Register), OperandDef::read(OperandType::Label)]),
             Self::CJNE => InstructionMetadata::new(self, 0x53, 5, vec![OperandDef::read(OperandType::Register), OperandDef::read(OperandType::Register), OperandDef::read(OperandType::Label)]),
+            
+            // Pseudo-instructions share the metadata of their widest form (see pseudo.rs)
+            Self::LI | Self::ADDI | Self::ANDI | Self::ORI | Self::XORI => {
+                let widest = self.pseudo_forms().last().expect("pseudo-instruction has forms");
+                InstructionMetadata { mnemonic: *self, ..widest.metadata() }
+            }
         }
     }
     
@@ -418,6 +434,11 @@ impl Mnemonic {
             Self::ADD8_A => ("A = A + i;", "8-bit immediate add to A"),
             Self::SUB8_A => ("A = A - i;", "8-bit immediate subtract from A"),
             Self::OR8_A => ("A = A | i;", "8-bit immediate bitwise OR to A"),
+            Self::LI => ("i=j;", "Pseudo-instruction: shortest of CLR, MOV8, MOV8SX, MOV16, MOV16SX, MOV32"),
+            Self::ADDI => ("i=i+j;", "Pseudo-instruction: shortest of INC, DEC, ADD8_A, SUB8_A, ADD8/16/32, SUB8/16/32"),
+            Self::ANDI => ("i=i&j;", "Pseudo-instruction: shortest of AND8, AND16, AND32"),
+            Self::ORI => ("i=i|j;", "Pseudo-instruction: shortest of OR8_A, OR8, OR16, OR32"),
+            Self::XORI => ("i=i^j;", "Pseudo-instruction: shortest of XOR8, XOR16, XOR32"),
         }
     }
 }
@@ -431,6 +452,14 @@ pub struct InstructionMetadata {
     pub operands: Vec<OperandDef>,
     pub description: String,
     pub note: String,
+    /// Registers used without being named as operands (see effect.rs)
+    pub implicit_reads: Vec<Register>,
+    pub implicit_writes: Vec<Register>,
+    pub flags_read: Vec<Flag>,
+    pub flags_written: Vec<Flag>,
+    /// Stack entries pushed (positive) or popped (negative)
+    pub stack: i8,
+    pub control_flow: ControlFlow,
 }
 
 impl InstructionMetadata {
@@ -442,6 +471,12 @@ impl InstructionMetadata {
             operands,
             description: String::new(),
             note: String::new(),
+            implicit_reads: mnemonic.implicit_reads().to_vec(),
+            implicit_writes: mnemonic.implicit_writes().to_vec(),
+            flags_read: mnemonic.flags_read().to_vec(),
+            flags_written: mnemonic.flags_written().to_vec(),
+            stack: mnemonic.stack_effect(),
+            control_flow: mnemonic.control_flow(),
         }
     }
     
@@ -527,6 +562,9 @@ pub enum Mnemonic {
     MODE_JOG, WAIT_IF_RELEASE, WAIT_IF_PRESS,
     EXIT_IF_RELEAS, EXIT_IF_PRESS, EXIT_IF_ANYKEY,
     RES, EXIT,
+    
+    // Pseudo-instructions
+    LI, ADDI, ANDI, ORI, XORI,
 }
 
 impl Mnemonic {
@@ -574,6 +612,7 @@ impl Mnemonic {
             Self::MODE_JOG, Self::WAIT_IF_RELEASE, Self::WAIT_IF_PRESS,
             Self::EXIT_IF_RELEAS, Self::EXIT_IF_PRESS, Self::EXIT_IF_ANYKEY,
             Self::RES, Self::EXIT,
+            Self::LI, Self::ADDI, Self::ANDI, Self::ORI, Self::XORI,
         ]
     }
     
@@ -729,6 +768,11 @@ impl Mnemonic {
             "EXIT_IF_ANYKEY" => Some(Self::EXIT_IF_ANYKEY),
             "RES" => Some(Self::RES),
             "EXIT" => Some(Self::EXIT),
+            "LI" => Some(Self::LI),
+            "ADDI" => Some(Self::ADDI),
+            "ANDI" => Some(Self::ANDI),
+            "ORI" => Some(Self::ORI),
+            "XORI" => Some(Self::XORI),
             _ => None,
         }
     }
@@ -0,0 +1,99 @@
+/// Numeric local labels (GNU style)
+///
+/// `1:` may be defined any number of times. `1b` refers to the nearest definition
+/// before the reference and `1f` to the nearest one after it, so references are
+/// resolved by item position rather than by name.
+use std::collections::HashMap;
+
+/// Direction of a numeric label reference
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelDirection {
+    /// `1b`
+    Backward,
+    /// `1f`
+    Forward,
+}
+
+/// Number of a numeric label definition (`"1"` for `1:`)
+pub fn numeric_label(name: &str) -> Option<u64> {
+    if name.is_empty() || !name.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    name.parse().ok()
+}
+
+/// Number and direction of a numeric label reference (`"1b"`, `"2f"`)
+pub fn numeric_label_ref(name: &str) -> Option<(u64, LabelDirection)> {
+    let direction = match name.as_bytes().last()? {
+        b'b' => LabelDirection::Backward,
+        b'f' => LabelDirection::Forward,
+        _ => return None,
+    };
+    numeric_label(&name[..name.len() - 1]).map(|number| (number, direction))
+}
+
+/// Definitions of numeric labels, in item order
+#[derive(Debug, Clone)]
+pub struct NumericLabels<T> {
+    /// Label number -> (item index, value) of each definition
+    definitions: HashMap<u64, Vec<(usize, T)>>,
+}
+
+impl<T: Copy> NumericLabels<T> {
+    pub fn new() -> Self {
+        Self { definitions: HashMap::new() }
+    }
+
+    /// Record a definition at item `index`; definitions must be added in item order
+    pub fn define(&mut self, number: u64, index: usize, value: T) {
+        self.definitions.entry(number).or_default().push((index, value));
+    }
+
+    /// Resolve a reference (`1b` / `1f`) made from item `index`
+    /// Returns `None` if the name is not a numeric reference or no definition exists in that direction
+    pub fn resolve(&self, reference: &str, index: usize) -> Option<T> {
+        let (number, direction) = numeric_label_ref(reference)?;
+        let definitions = self.definitions.get(&number)?;
+        match direction {
+            LabelDirection::Backward => definitions.iter().rev().find(|(at, _)| *at < index),
+            LabelDirection::Forward => definitions.iter().find(|(at, _)| *at > index),
+        }
+        .map(|(_, value)| *value)
+    }
+}
+
+impl<T: Copy> Default for NumericLabels<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numeric_label_names() {
+        assert_eq!(numeric_label("10"), Some(10));
+        assert_eq!(numeric_label("main"), None);
+        assert_eq!(numeric_label_ref("1b"), Some((1, LabelDirection::Backward)));
+        assert_eq!(numeric_label_ref("42f"), Some((42, LabelDirection::Forward)));
+        assert_eq!(numeric_label_ref("b"), None);
+        assert_eq!(numeric_label_ref("0x1f"), None);
+    }
+
+    #[test]
+    fn test_nearest_definition() {
+        let mut labels = NumericLabels::new();
+        labels.define(1, 0, 100);
+        labels.define(1, 4, 104);
+        labels.define(2, 6, 106);
+
+        assert_eq!(labels.resolve("1b", 3), Some(100));
+        assert_eq!(labels.resolve("1f", 3), Some(104));
+        assert_eq!(labels.resolve("1b", 5), Some(104));
+        assert_eq!(labels.resolve("1f", 5), None);
+        assert_eq!(labels.resolve("2b", 5), None);
+        assert_eq!(labels.resolve("1", 5), None);
+    }
+}
@@ -1,11 +1,30 @@
 pub mod ast;
+pub mod branch;
+pub mod color;
+pub mod constants;
+pub mod diagnostic;
+pub mod effect;
+pub mod expr;
+pub mod hid;
 pub mod instr;
+pub mod label;
+pub mod pseudo;
 pub mod reg;
+pub mod source;
 pub mod span;
+pub mod width;
 
 pub use ast::*;
+pub use color::*;
+pub use constants::*;
+pub use diagnostic::*;
+pub use effect::*;
+pub use expr::*;
+pub use hid::*;
 pub use instr::*;
+pub use label::*;
 pub use reg::*;
+pub use source::*;
 pub use span::*;
 
 /// Generate markdown documentation for all instructions
@@ -15,11 +34,12 @@ pub fn generate_instruction_markdown() -> String {
     md.push_str("# Sayo Assembly Instruction Reference\n\n");
     md.push_str("Auto-generated documentation containing all Sayo assembly instructions.\n\n");
     md.push_str("## Instruction List\n\n");
-    md.push_str("| Mnemonic | Opcode(Hex) | Length | Operands | Description | Notes |\n");
-    md.push_str("|--------|-------------|----------|--------|------|------|\n");
+    md.push_str("| Mnemonic | Opcode(Hex) | Length | Operands | Effects | Description | Notes |\n");
+    md.push_str("|--------|-------------|----------|--------|------|------|------|\n");
     
     let mnemonics = Mnemonic::all_variants();
     let mut sorted_mnemonics: Vec<_> = mnemonics.iter()
+        .filter(|m| !m.is_pseudo())
         .map(|m| (m, m.metadata()))
         .collect();
     
@@ -29,14 +49,16 @@ pub fn generate_instruction_markdown() -> String {
     for (mnemonic, meta) in sorted_mnemonics {
         let opcode_hex = format!("0x{:02X}", meta.opcode);
         let operands = format_operands(&meta.operands);
+        let effects = format_effects(&meta);
         let (description, note) = mnemonic.description();
         
         md.push_str(&format!(
-            "| {} | {} | {} | {} | {} | {} |\n",
+            "| {} | {} | {} | {} | {} | {} | {} |\n",
             mnemonic,
             opcode_hex,
             meta.length,
             operands,
+            effects,
             description,
             note
         ));
@@ -215,6 +237,16 @@ pub fn generate_instruction_markdown() -> String {
         md.push_str(&format!("| {} | {} |\n", mnemonic, full_desc));
     }
     
+    // Pseudo-instructions
+    md.push_str("\n### Pseudo-Instructions\n\n");
+    md.push_str("Lowered by the assembler to the shortest real instruction that fits the value.\n\n");
+    md.push_str("| Instruction | Description |\n");
+    md.push_str("|-------------|-------------|\n");
+    for mnemonic in &[Mnemonic::LI, Mnemonic::ADDI, Mnemonic::ANDI, Mnemonic::ORI, Mnemonic::XORI] {
+        let (desc, note) = mnemonic.description();
+        md.push_str(&format!("| {} | {} ({}) |\n", mnemonic, desc, note));
+    }
+    
     md
 }
 
@@ -348,3 +380,38 @@ fn format_operands(operands: &[OperandDef]) -> String {
         .collect::<Vec<_>>()
         .join(", ")
 }
+
+/// Effects beyond the operands: implicit registers, flags, stack and control flow
+fn format_effects(meta: &InstructionMetadata) -> String {
+    fn list<T: std::fmt::Display>(items: &[T]) -> String {
+        items.iter().map(|item| item.to_string()).collect::<Vec<_>>().join(", ")
+    }
+    
+    let mut effects = Vec::new();
+    if !meta.implicit_reads.is_empty() {
+        effects.push(format!("reads {}", list(&meta.implicit_reads)));
+    }
+    if !meta.implicit_writes.is_empty() {
+        effects.push(format!("writes {}", list(&meta.implicit_writes)));
+    }
+    if !meta.flags_read.is_empty() {
+        effects.push(format!("reads {}", list(&meta.flags_read)));
+    }
+    if !meta.flags_written.is_empty() {
+        effects.push(format!("sets {}", list(&meta.flags_written)));
+    }
+    match meta.stack {
+        0 => {}
+        n if n > 0 => effects.push(format!("pushes {}", n)),
+        n => effects.push(format!("pops {}", -n)),
+    }
+    if meta.control_flow != ControlFlow::Next {
+        effects.push(meta.control_flow.to_string());
+    }
+    
+    if effects.is_empty() {
+        "-".to_string()
+    } else {
+        effects.join("; ")
+    }
+}
@@ -0,0 +1,135 @@
+/// Pseudo-instructions
+///
+/// `LI`, `ADDI`, `ANDI`, `ORI` and `XORI` take a register and an immediate and are
+/// lowered by the assembler to the shortest real instruction with the same effect:
+/// `LI R0, 0x1234` becomes `MOV16SX R0, 0x1234`, `ADDI A, -3` becomes `SUB8_A 3`.
+/// The 8/16-bit forms only write the low bits of the register, so they are chosen only
+/// for registers no wider than the form; wider registers take the sign-extending moves
+/// (`MOV8SX`/`MOV16SX`), which fill the whole register, or the 32-bit forms.
+use crate::instr::Mnemonic;
+use crate::reg::Register;
+
+impl Mnemonic {
+    pub fn is_pseudo(&self) -> bool {
+        matches!(self, Self::LI | Self::ADDI | Self::ANDI | Self::ORI | Self::XORI)
+    }
+
+    /// Real instructions a pseudo-instruction may be lowered to, shortest first
+    pub fn pseudo_forms(&self) -> &'static [Mnemonic] {
+        match self {
+            Self::LI => &[Self::CLR, Self::MOV8, Self::MOV8SX, Self::MOV16, Self::MOV16SX, Self::MOV32],
+            Self::ADDI => &[Self::INC, Self::DEC, Self::ADD8_A, Self::SUB8_A, Self::ADD8, Self::SUB8, Self::ADD16, Self::SUB16, Self::ADD32, Self::SUB32],
+            Self::ANDI => &[Self::AND8, Self::AND16, Self::AND32],
+            Self::ORI => &[Self::OR8_A, Self::OR8, Self::OR16, Self::OR32],
+            Self::XORI => &[Self::XOR8, Self::XOR16, Self::XOR32],
+            _ => &[],
+        }
+    }
+
+    /// Shortest real instruction of at least `min_length` bytes that performs this
+    /// pseudo-instruction on `register` with `value`, and the immediate it takes
+    /// (`None` for `CLR`, `INC` and `DEC`)
+    /// Returns `None` if this is not a pseudo-instruction or no form fits the value
+    pub fn lower(&self, register: Register, value: i64, min_length: u8) -> Option<(Mnemonic, Option<i64>)> {
+        let is_a = matches!(register, Register::A | Register::R6);
+        // A sized form leaves the bits of the register above its width as they were
+        let bits = register.metadata().width.bits();
+        let (fits8, fits16) = (bits <= 8, bits <= 16);
+        let u8_value = (0..=0xFF).contains(&value);
+        let u16_value = (0..=0xFFFF).contains(&value);
+        let u32_value = (0..=u32::MAX as i64).contains(&value);
+        // Bitwise operations take negative values as 32-bit masks
+        let mask = if (i32::MIN as i64..0).contains(&value) { value & 0xFFFF_FFFF } else { value };
+
+        let candidates = match self {
+            Self::LI => vec![
+                (value == 0).then_some((Self::CLR, None)),
+                (fits8 && u8_value).then_some((Self::MOV8, Some(value))),
+                (-0x80..0x80).contains(&value).then_some((Self::MOV8SX, Some(value))),
+                (fits16 && u16_value).then_some((Self::MOV16, Some(value))),
+                (-0x8000..0x8000).contains(&value).then_some((Self::MOV16SX, Some(value))),
+                (0..=u32::MAX as i64).contains(&mask).then_some((Self::MOV32, Some(mask))),
+            ],
+            Self::ADDI => vec![
+                (value == 1).then_some((Self::INC, None)),
+                (value == -1).then_some((Self::DEC, None)),
+                (is_a && u8_value).then_some((Self::ADD8_A, Some(value))),
+                (is_a && (-0xFF..0).contains(&value)).then_some((Self::SUB8_A, Some(-value))),
+                (fits8 && u8_value).then_some((Self::ADD8, Some(value))),
+                (fits8 && (-0xFF..0).contains(&value)).then_some((Self::SUB8, Some(-value))),
+                (fits16 && u16_value).then_some((Self::ADD16, Some(value))),
+                (fits16 && (-0xFFFF..0).contains(&value)).then_some((Self::SUB16, Some(-value))),
+                u32_value.then_some((Self::ADD32, Some(value))),
+                (-(u32::MAX as i64)..0).contains(&value).then_some((Self::SUB32, Some(-value))),
+            ],
+            Self::ANDI | Self::ORI | Self::XORI => {
+                let (short_a, [short, medium, long]) = match self {
+                    Self::ANDI => (None, [Self::AND8, Self::AND16, Self::AND32]),
+                    Self::ORI => (Some(Self::OR8_A), [Self::OR8, Self::OR16, Self::OR32]),
+                    _ => (None, [Self::XOR8, Self::XOR16, Self::XOR32]),
+                };
+                vec![
+                    short_a.filter(|_| is_a && (0..=0xFF).contains(&mask)).map(|form| (form, Some(mask))),
+                    (fits8 && (0..=0xFF).contains(&mask)).then_some((short, Some(mask))),
+                    (fits16 && (0..=0xFFFF).contains(&mask)).then_some((medium, Some(mask))),
+                    (0..=u32::MAX as i64).contains(&mask).then_some((long, Some(mask))),
+                ]
+            }
+            _ => return None,
+        };
+
+        candidates.into_iter()
+            .flatten()
+            .filter(|(form, _)| form.metadata().length >= min_length)
+            .min_by_key(|(form, _)| form.metadata().length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shortest_form() {
+        let lower = |mnemonic: Mnemonic, register: Register, value: i64| mnemonic.lower(register, value, 0);
+
+        assert_eq!(lower(Mnemonic::LI, Register::R0, 0), Some((Mnemonic::CLR, None)));
+        assert_eq!(lower(Mnemonic::LI, Register::R0, 5), Some((Mnemonic::MOV8SX, Some(5))));
+        assert_eq!(lower(Mnemonic::LI, Register::R0, 200), Some((Mnemonic::MOV16SX, Some(200))));
+        assert_eq!(lower(Mnemonic::LI, Register::R0, -2), Some((Mnemonic::MOV8SX, Some(-2))));
+        assert_eq!(lower(Mnemonic::LI, Register::R0, 0x1234), Some((Mnemonic::MOV16SX, Some(0x1234))));
+        // MOV16 would leave the upper half of R0 as it was
+        assert_eq!(lower(Mnemonic::LI, Register::R0, 0x9000), Some((Mnemonic::MOV32, Some(0x9000))));
+        assert_eq!(lower(Mnemonic::LI, Register::R0, -40000), Some((Mnemonic::MOV32, Some(0xFFFF_63C0))));
+        assert_eq!(lower(Mnemonic::LI, Register::R0, 1 << 32), None);
+
+        assert_eq!(lower(Mnemonic::ADDI, Register::R1, 1), Some((Mnemonic::INC, None)));
+        assert_eq!(lower(Mnemonic::ADDI, Register::A, -3), Some((Mnemonic::SUB8_A, Some(3))));
+        assert_eq!(lower(Mnemonic::ADDI, Register::R1, -3), Some((Mnemonic::SUB32, Some(3))));
+        assert_eq!(lower(Mnemonic::ADDI, Register::R1, 300), Some((Mnemonic::ADD32, Some(300))));
+
+        assert_eq!(lower(Mnemonic::ORI, Register::A, 0x80), Some((Mnemonic::OR8_A, Some(0x80))));
+        assert_eq!(lower(Mnemonic::ANDI, Register::A, 0x0F), Some((Mnemonic::AND32, Some(0x0F))));
+        assert_eq!(lower(Mnemonic::XORI, Register::R2, -1), Some((Mnemonic::XOR32, Some(0xFFFF_FFFF))));
+        assert_eq!(lower(Mnemonic::MOV8, Register::R0, 1), None);
+    }
+
+    #[test]
+    fn test_narrow_registers() {
+        let lower = |mnemonic: Mnemonic, register: Register, value: i64| mnemonic.lower(register, value, 0);
+
+        // The sized forms write all of an 8-bit register
+        assert_eq!(lower(Mnemonic::LI, Register::V0, 200), Some((Mnemonic::MOV8, Some(200))));
+        assert_eq!(lower(Mnemonic::ADDI, Register::V1, -3), Some((Mnemonic::SUB8, Some(3))));
+        assert_eq!(lower(Mnemonic::ANDI, Register::V2, 0x0F), Some((Mnemonic::AND8, Some(0x0F))));
+        assert_eq!(lower(Mnemonic::ORI, Register::V3, 0x80), Some((Mnemonic::OR8, Some(0x80))));
+    }
+
+    #[test]
+    fn test_min_length() {
+        // A form that had to grow during layout never shrinks back
+        assert_eq!(Mnemonic::LI.lower(Register::R0, 5, 4), Some((Mnemonic::MOV16SX, Some(5))));
+        assert_eq!(Mnemonic::ADDI.lower(Register::A, 1, 3), Some((Mnemonic::ADD32, Some(1))));
+        assert_eq!(Mnemonic::LI.lower(Register::R0, 5, 7), None);
+    }
+}
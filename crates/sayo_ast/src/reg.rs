@@ -187,7 +187,37 @@ impl Register {
             Self::Gl(n) => RegisterMetadata::new(0x80 + n, RegWidth::W32, RegAccess::RW),
         }
     }
-    
+
+    /// General purpose register holding this register's value: `R0`-`R15` themselves,
+    /// `DPTR` (mapped to `R4`), `A` (`R6`) and `B` (`R7`)
+    pub fn storage(&self) -> Option<Self> {
+        match self {
+            Self::R0 | Self::R1 | Self::R2 | Self::R3 | Self::R4 | Self::R5 | Self::R6 | Self::R7 |
+            Self::R8 | Self::R9 | Self::R10 | Self::R11 | Self::R12 | Self::R13 | Self::R14 | Self::R15 => Some(*self),
+            Self::DPTR => Some(Self::R4),
+            Self::A => Some(Self::R6),
+            Self::B => Some(Self::R7),
+            _ => None,
+        }
+    }
+
+    /// Register an indirect register takes its address from: `*R3`, `*R3_16b` and
+    /// `*R3_32b` use `R3`, `*DPTR` uses `DPTR`
+    pub fn address_register(&self) -> Option<Self> {
+        match self {
+            Self::StarR0 | Self::StarR0_16b | Self::StarR0_32b => Some(Self::R0),
+            Self::StarR1 | Self::StarR1_16b | Self::StarR1_32b => Some(Self::R1),
+            Self::StarR2 | Self::StarR2_16b | Self::StarR2_32b => Some(Self::R2),
+            Self::StarR3 | Self::StarR3_16b | Self::StarR3_32b => Some(Self::R3),
+            Self::StarR4 | Self::StarR4_16b | Self::StarR4_32b => Some(Self::R4),
+            Self::StarR5 | Self::StarR5_16b | Self::StarR5_32b => Some(Self::R5),
+            Self::StarR6 | Self::StarR6_16b | Self::StarR6_32b => Some(Self::R6),
+            Self::StarR7 | Self::StarR7_16b | Self::StarR7_32b => Some(Self::R7),
+            Self::StarDPTR => Some(Self::DPTR),
+            _ => None,
+        }
+    }
+
     /// Get the human-readable description for this register
     pub fn description(&self) -> &'static str {
         match self {
@@ -437,6 +467,22 @@ impl fmt::Display for Register {
             Self::StarR5 => write!(f, "*R5"),
             Self::StarR6 => write!(f, "*R6"),
             Self::StarR7 => write!(f, "*R7"),
+            Self::StarR0_16b => write!(f, "*R0_16b"),
+            Self::StarR1_16b => write!(f, "*R1_16b"),
+            Self::StarR2_16b => write!(f, "*R2_16b"),
+            Self::StarR3_16b => write!(f, "*R3_16b"),
+            Self::StarR4_16b => write!(f, "*R4_16b"),
+            Self::StarR5_16b => write!(f, "*R5_16b"),
+            Self::StarR6_16b => write!(f, "*R6_16b"),
+            Self::StarR7_16b => write!(f, "*R7_16b"),
+            Self::StarR0_32b => write!(f, "*R0_32b"),
+            Self::StarR1_32b => write!(f, "*R1_32b"),
+            Self::StarR2_32b => write!(f, "*R2_32b"),
+            Self::StarR3_32b => write!(f, "*R3_32b"),
+            Self::StarR4_32b => write!(f, "*R4_32b"),
+            Self::StarR5_32b => write!(f, "*R5_32b"),
+            Self::StarR6_32b => write!(f, "*R6_32b"),
+            Self::StarR7_32b => write!(f, "*R7_32b"),
             Self::SysTimeMs => write!(f, "SYS_TIME_MS"),
             Self::SysTimeS => write!(f, "SYS_TIME_S"),
             Self::SysKbled => write!(f, "SYS_KBLED"),
@@ -454,7 +500,6 @@ impl fmt::Display for Register {
             Self::SysUsbSusp => write!(f, "SYS_USB_SUSP"),
             Self::GlSize => write!(f, "GL_SIZE"),
             Self::Gl(n) => write!(f, "GL_{}", n),
-            _ => write!(f, "{:?}", self),
         }
     }
 }
@@ -0,0 +1,104 @@
+use crate::span::{FileId, Span};
+use std::path::{Path, PathBuf};
+
+/// A loaded source file
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub text: String,
+    /// Span of the `.include` directive that pulled this file in (None for the root)
+    pub included_from: Option<Span>,
+}
+
+/// All source files of a program, indexed by `FileId`
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a source map holding only the root file
+    pub fn with_root(path: impl Into<PathBuf>, text: impl Into<String>) -> Self {
+        let mut sources = Self::new();
+        sources.add(path, text, None);
+        sources
+    }
+
+    /// Add a file; the first file added is `FileId::ROOT`
+    pub fn add(&mut self, path: impl Into<PathBuf>, text: impl Into<String>, included_from: Option<Span>) -> FileId {
+        let id = FileId(self.files.len() as u32);
+        self.files.push(SourceFile {
+            path: path.into(),
+            text: text.into(),
+            included_from,
+        });
+        id
+    }
+
+    pub fn get(&self, id: FileId) -> Option<&SourceFile> {
+        self.files.get(id.0 as usize)
+    }
+
+    /// Path of a file, or an empty path for unknown ids
+    pub fn path(&self, id: FileId) -> &Path {
+        self.get(id).map_or(Path::new(""), |f| f.path.as_path())
+    }
+
+    /// Source text of a file, or an empty string for unknown ids
+    pub fn text(&self, id: FileId) -> &str {
+        self.get(id).map_or("", |f| f.text.as_str())
+    }
+
+    /// Iterate over all files with their ids, in load order
+    pub fn files(&self) -> impl Iterator<Item = (FileId, &SourceFile)> {
+        self.files.iter().enumerate().map(|(i, f)| (FileId(i as u32), f))
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Follow `.include` sites back to a span in the root file
+    pub fn root_span(&self, mut span: Span) -> Span {
+        while let Some(site) = self.get(span.file).and_then(|f| f.included_from) {
+            span = site;
+        }
+        span
+    }
+
+    /// 1-based line and column of a byte offset in a file
+    pub fn line_col(&self, file: FileId, offset: usize) -> (usize, usize) {
+        let text = self.text(file);
+        let offset = offset.min(text.len());
+        let before = &text[..offset];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        (line, before[line_start..].chars().count() + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_root_span_and_line_col() {
+        let mut sources = SourceMap::with_root("main.s", ".include \"keys.s\"\nmain:\n");
+        let site = Span::new(0, 17);
+        let keys = sources.add("keys.s", "KEY_A = 4\nKEY_B = 5\n", Some(site));
+
+        let inner = Span::new(10, 19).with_file(keys);
+        assert_eq!(sources.root_span(inner), site);
+        assert_eq!(sources.line_col(keys, 10), (2, 1));
+        assert_eq!(sources.line_col(FileId::ROOT, 20), (2, 3));
+        assert_eq!(sources.path(keys), Path::new("keys.s"));
+    }
+}
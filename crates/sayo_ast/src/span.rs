@@ -1,21 +1,37 @@
 use serde::{Deserialize, Serialize};
 
-/// Span represents a location in the source file
+/// Identifies a source file in a `SourceMap` (the root input is always `FileId::ROOT`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct FileId(pub u32);
+
+impl FileId {
+    pub const ROOT: FileId = FileId(0);
+}
+
+/// Span represents a location in a source file
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Span {
     pub start: usize,
     pub end: usize,
+    pub file: FileId,
 }
 
 impl Span {
+    /// Span in the root file
     pub fn new(start: usize, end: usize) -> Self {
-        Self { start, end }
+        Self { start, end, file: FileId::ROOT }
+    }
+
+    /// The same byte range in another file
+    pub fn with_file(self, file: FileId) -> Self {
+        Self { file, ..self }
     }
 
     pub fn merge(self, other: Self) -> Self {
         Self {
             start: self.start.min(other.start),
             end: self.end.max(other.end),
+            file: self.file,
         }
     }
 }
@@ -0,0 +1,45 @@
+/// Operation widths
+///
+/// The sized moves and immediate arithmetic (`MOV8`, `ADD16`, `XOR32`, ...) operate on a
+/// fixed number of bits whatever register they name, and the sign extensions (`MOV8SX`,
+/// `MOVSX16b`, ...) take a fixed number of bits and widen them to the destination. The
+/// semantic checker compares these widths with the widths of the registers.
+use crate::instr::Mnemonic;
+use crate::reg::RegWidth;
+
+impl Mnemonic {
+    /// Width of a sized operation, None for the unsized ones (`MOV`, `ADD`, `INC`, ...)
+    pub fn width(&self) -> Option<RegWidth> {
+        match self {
+            Self::MOV8 | Self::MOV8SX | Self::MOVSX8b
+            | Self::ADD8 | Self::SUB8 | Self::AND8 | Self::XOR8 => Some(RegWidth::W8),
+            Self::MOV16 | Self::MOV16SX | Self::MOVSX16b
+            | Self::ADD16 | Self::SUB16 | Self::AND16 | Self::OR16 | Self::XOR16 => Some(RegWidth::W16),
+            Self::MOV32
+            | Self::ADD32 | Self::SUB32 | Self::AND32 | Self::OR32 | Self::XOR32 => Some(RegWidth::W32),
+            _ => None,
+        }
+    }
+
+    /// Whether only `width` bits of the destination are written (`MOV8`, `MOV16`, `MOV32`);
+    /// sign extensions fill the whole destination and arithmetic zero-extends its immediate
+    pub fn is_sized_move(&self) -> bool {
+        matches!(self, Self::MOV8 | Self::MOV16 | Self::MOV32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_widths() {
+        assert_eq!(Mnemonic::MOV32.width(), Some(RegWidth::W32));
+        assert_eq!(Mnemonic::MOVSX16b.width(), Some(RegWidth::W16));
+        assert_eq!(Mnemonic::MOV.width(), None);
+        // SHL8/SHR8 take a shift count, not an 8-bit value
+        assert_eq!(Mnemonic::SHL8.width(), None);
+        assert!(Mnemonic::MOV8.is_sized_move());
+        assert!(!Mnemonic::MOV8SX.is_sized_move());
+    }
+}
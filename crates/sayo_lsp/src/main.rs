@@ -1,8 +1,8 @@
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer, LspService, Server};
-use sayo_parser::{parse, ParseError};
-use sayo_ast::{Mnemonic, Item, Register};
-use sayo_sema::{SemanticChecker, SemanticError};
+use sayo_parser::{parse_sources, ParseOptions, ParseOutput};
+use sayo_ast::{Color, ControlFlow, FileId, Flag, HidCode, Mnemonic, Item, Register, SourceMap, Span};
+use sayo_sema::{LintConfig, SemanticChecker};
 use std::collections::HashMap;
 
 // Helper function to convert byte offset to line/column
@@ -23,6 +23,82 @@ fn position_from_offset(text: &str, offset: usize) -> (usize, usize) {
     (line, col)
 }
 
+/// Range of a span in its own file
+fn range_of(sources: &SourceMap, span: Span) -> Range {
+    let text = sources.text(span.file);
+    let (start_line, start_col) = position_from_offset(text, span.start);
+    let (end_line, end_col) = position_from_offset(text, span.end);
+    // Keep empty spans visible
+    let end_col = if (end_line, end_col) == (start_line, start_col) { end_col + 1 } else { end_col };
+    Range {
+        start: Position { line: start_line as u32, character: start_col as u32 },
+        end: Position { line: end_line as u32, character: end_col as u32 },
+    }
+}
+
+fn location_of(sources: &SourceMap, span: Span, uri: &Url) -> Option<Location> {
+    let file_uri = if span.file == FileId::ROOT {
+        uri.clone()
+    } else {
+        Url::from_file_path(sources.path(span.file)).ok()?
+    };
+    Some(Location { uri: file_uri, range: range_of(sources, span) })
+}
+
+/// Convert an assembler diagnostic for the document at `uri`
+/// Problems in included files are shown on the `.include` line and point to the file as related information
+fn to_lsp_diagnostic(diagnostic: &sayo_ast::Diagnostic, sources: &SourceMap, uri: &Url) -> Diagnostic {
+    let span = diagnostic.span.unwrap_or(Span::new(0, 0));
+    let root_span = sources.root_span(span);
+    
+    let mut message = diagnostic.message.clone();
+    let mut related = Vec::new();
+    if root_span != span {
+        message = format!("In included file '{}': {}", sources.path(span.file).display(), message);
+        related.extend(location_of(sources, span, uri).map(|location| DiagnosticRelatedInformation {
+            location,
+            message: diagnostic.message.clone(),
+        }));
+    }
+    for label in &diagnostic.labels {
+        related.extend(location_of(sources, label.span, uri).map(|location| DiagnosticRelatedInformation {
+            location,
+            message: label.message.clone(),
+        }));
+    }
+    for note in &diagnostic.notes {
+        message.push_str(&format!("\nnote: {}", note));
+    }
+    if let Some(help) = &diagnostic.help {
+        message.push_str(&format!("\nhelp: {}", help));
+    }
+    
+    Diagnostic {
+        range: range_of(sources, root_span),
+        severity: Some(match diagnostic.severity {
+            sayo_ast::Severity::Error => DiagnosticSeverity::ERROR,
+            sayo_ast::Severity::Warning => DiagnosticSeverity::WARNING,
+            sayo_ast::Severity::Info => DiagnosticSeverity::INFORMATION,
+        }),
+        code: Some(NumberOrString::String(diagnostic.code.to_string())),
+        source: Some("sayo-asm".to_string()),
+        message,
+        related_information: (!related.is_empty()).then_some(related),
+        ..Default::default()
+    }
+}
+
+// Parse a document, resolving .include relative to its file path
+fn parse_document(uri: &str, text: &str) -> (SourceMap, ParseOutput) {
+    let path = Url::parse(uri)
+        .ok()
+        .and_then(|url| url.to_file_path().ok())
+        .unwrap_or_default();
+    let mut sources = SourceMap::with_root(path, text);
+    let result = parse_sources(&mut sources, &ParseOptions::default());
+    (sources, result)
+}
+
 // Format hover text for instruction with full metadata
 fn format_instruction_hover(mnemonic: &Mnemonic) -> String {
     let metadata = mnemonic.metadata();
@@ -33,8 +109,14 @@ fn format_instruction_hover(mnemonic: &Mnemonic) -> String {
     
     // Metadata section
     text.push_str("---\n\n");
-    text.push_str(&format!("**Opcode:** 0x{:02X} ({})\n\n", metadata.opcode, metadata.opcode));
-    text.push_str(&format!("**Length:** {} byte(s)\n\n", metadata.length));
+    if mnemonic.is_pseudo() {
+        let forms: Vec<String> = mnemonic.pseudo_forms().iter().map(|form| form.to_string()).collect();
+        text.push_str(&format!("**Pseudo-instruction:** assembled as the shortest of {}\n\n", forms.join(", ")));
+        text.push_str(&format!("**Length:** up to {} byte(s)\n\n", metadata.length));
+    } else {
+        text.push_str(&format!("**Opcode:** 0x{:02X} ({})\n\n", metadata.opcode, metadata.opcode));
+        text.push_str(&format!("**Length:** {} byte(s)\n\n", metadata.length));
+    }
     
     // Operands
     if !metadata.operands.is_empty() {
@@ -47,7 +129,11 @@ fn format_instruction_hover(mnemonic: &Mnemonic) -> String {
                 _ => "?",
             };
             let op_type_str = format!("{:?}", op.op_type);
-            let rw = if op.is_write { "W" } else { "R" };
+            let rw = match (op.is_read, op.is_write) {
+                (true, true) => "RW",
+                (_, true) => "W",
+                _ => "R",
+            };
             operands.push(format!("{}: {} ({})", param_name, op_type_str, rw));
         }
         text.push_str(&format!("**Operands:** {}\n\n", operands.join(", ")));
@@ -55,6 +141,29 @@ fn format_instruction_hover(mnemonic: &Mnemonic) -> String {
         text.push_str("**Operands:** None\n\n");
     }
     
+    // Effects beyond the operands
+    let registers = |regs: &[Register]| regs.iter().map(|reg| reg.to_string()).collect::<Vec<_>>().join(", ");
+    if !metadata.implicit_reads.is_empty() {
+        text.push_str(&format!("**Implicit reads:** {}\n\n", registers(&metadata.implicit_reads)));
+    }
+    if !metadata.implicit_writes.is_empty() {
+        text.push_str(&format!("**Implicit writes:** {}\n\n", registers(&metadata.implicit_writes)));
+    }
+    let flags = |flags: &[Flag]| flags.iter().map(|flag| flag.to_string()).collect::<Vec<_>>().join(", ");
+    if !metadata.flags_read.is_empty() {
+        text.push_str(&format!("**Flags read:** {}\n\n", flags(&metadata.flags_read)));
+    }
+    if !metadata.flags_written.is_empty() {
+        text.push_str(&format!("**Flags set:** {}\n\n", flags(&metadata.flags_written)));
+    }
+    if metadata.stack != 0 {
+        let (verb, count) = if metadata.stack > 0 { ("pushes", metadata.stack) } else { ("pops", -metadata.stack) };
+        text.push_str(&format!("**Stack:** {} {} entry\n\n", verb, count));
+    }
+    if metadata.control_flow != ControlFlow::Next {
+        text.push_str(&format!("**Control flow:** {}\n\n", metadata.control_flow));
+    }
+    
     // Description
     if !desc.is_empty() {
         text.push_str("---\n\n");
@@ -96,6 +205,73 @@ fn format_register_hover(register: &Register) -> String {
     text
 }
 
+fn format_hid_hover(code: &HidCode) -> String {
+    let mut text = format!("**{}**\n\n", code.name());
+    text.push_str("---\n\n");
+    text.push_str(&format!("**Type:** {}\n\n", code.namespace));
+    text.push_str(&format!("**HID Code:** 0x{:02X} ({})\n\n", code.code, code.code));
+    text
+}
+
+/// HID code at the cursor: a built-in name, or a raw code operand of PRESS_*/RELEASE_*
+fn hid_code_at(word: &str, line: &str) -> Option<HidCode> {
+    if let Some(code) = HidCode::from_name(word) {
+        return Some(code);
+    }
+    let code = match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16).ok()?,
+        None => word.parse().ok()?,
+    };
+    // The mnemonic is the first word after any labels
+    let mnemonic = line.split(';').next()?
+        .split_whitespace()
+        .find(|w| !w.ends_with(':'))
+        .and_then(|w| Mnemonic::from_str(&w.to_uppercase()))?;
+    let namespace = mnemonic.hid_namespace()?;
+    namespace.name_of(code).map(|_| HidCode { namespace, code })
+}
+
+fn format_color_hover(color: &Color) -> String {
+    // An SVG swatch as a data URI ('#' is escaped as %23)
+    let hex = format!("{:06X}", color.rgb888());
+    let mut text = format!(
+        "![#{hex}](data:image/svg+xml;utf8,%3Csvg%20xmlns='http://www.w3.org/2000/svg'%20width='48'%20height='16'%3E%3Crect%20width='48'%20height='16'%20fill='%23{hex}'/%3E%3C/svg%3E)\n\n"
+    );
+    text.push_str(&format!("**{}**", color));
+    if let Some(name) = color.name() {
+        text.push_str(&format!(" ({})", name));
+    }
+    text.push_str("\n\n---\n\n");
+    text.push_str(&format!("**RGB:** rgb({}, {}, {})\n\n", color.r, color.g, color.b));
+    text.push_str(&format!("**Value:** 0x{}\n\n", hex));
+    text
+}
+
+/// Colour literal at the cursor: `#RRGGBB` (`hex_prefixed` when `word` follows a '#'),
+/// `rgb(r, g, b)` (with `rest` the text after `rgb`) or a named colour
+fn color_at(word: &str, hex_prefixed: bool, rest: &str) -> Option<Color> {
+    if hex_prefixed {
+        return Color::from_hex(word);
+    }
+    if word.eq_ignore_ascii_case("rgb") {
+        let args = rest.trim_start().strip_prefix('(')?.split(')').next()?;
+        let components: Vec<u8> = args.split(',')
+            .map(|c| {
+                let c = c.trim();
+                match c.strip_prefix("0x").or_else(|| c.strip_prefix("0X")) {
+                    Some(hex) => u8::from_str_radix(hex, 16).ok(),
+                    None => c.parse().ok(),
+                }
+            })
+            .collect::<Option<_>>()?;
+        return match components[..] {
+            [r, g, b] => Some(Color::new(r, g, b)),
+            _ => None,
+        };
+    }
+    Color::from_name(word)
+}
+
 #[derive(Debug)]
 struct Backend {
     client: Client,
@@ -179,94 +355,94 @@ impl LanguageServer for Backend {
         
         let mut tokens_data = Vec::new();
         
-        match parse(text) {
-            Ok(program) => {
-                let mut prev_line = 0;
-                let mut prev_col = 0;
-                
-                for item in &program.items {
-                    let item_inner = &item.node;
-                    let span = &item.span;
-                    let (line, col) = position_from_offset(text, span.start);
+        let program = parse_document(&uri, text).1.program;
+        let mut prev_line = 0;
+        let mut prev_col = 0;
+        
+        // Only items from this document (not from included files)
+        for item in program.items.iter().filter(|item| item.span.file == FileId::ROOT) {
+            let item_inner = &item.node;
+            let span = &item.span;
+            let (line, col) = position_from_offset(text, span.start);
+            
+            match item_inner {
+                Item::Directive(_) => {
+                    let delta_line = line - prev_line;
+                    let delta_col = if delta_line == 0 { col - prev_col } else { col };
                     
-                    match item_inner {
-                        Item::Directive(_) => {
-                            let delta_line = line - prev_line;
-                            let delta_col = if delta_line == 0 { col - prev_col } else { col };
-                            
-                            tokens_data.push(SemanticToken {
-                                delta_line: delta_line as u32,
-                                delta_start: delta_col as u32,
-                                length: (span.end - span.start) as u32,
-                                token_type: 0,
-                                token_modifiers_bitset: 0,
-                            });
-                            
-                            prev_line = line;
-                            prev_col = col;
-                        }
-                        Item::Instruction(inst) => {
-                            let mnem_text = format!("{:?}", inst.mnemonic);
-                            let mnem_len = mnem_text.len();
-                            
-                            let delta_line = line - prev_line;
-                            let delta_col = if delta_line == 0 { col - prev_col } else { col };
-                            
-                            tokens_data.push(SemanticToken {
-                                delta_line: delta_line as u32,
-                                delta_start: delta_col as u32,
-                                length: mnem_len as u32,
-                                token_type: 3,
-                                token_modifiers_bitset: 0,
-                            });
-                            
-                            prev_line = line;
-                            prev_col = col;
-                            
-                            for operand in &inst.operands {
-                                let op_span = &operand.span;
-                                let (op_line, op_col) = position_from_offset(text, op_span.start);
-                                
-                                let delta_line = op_line - prev_line;
-                                let delta_col = if delta_line == 0 { op_col - prev_col } else { op_col };
-                                
-                                let token_type = match &operand.node {
-                                    sayo_ast::Operand::Register(_) => 2,
-                                    sayo_ast::Operand::Immediate(_) => 4,
-                                    sayo_ast::Operand::Label(_) => 1,
-                                };
-                                
-                                tokens_data.push(SemanticToken {
-                                    delta_line: delta_line as u32,
-                                    delta_start: delta_col as u32,
-                                    length: (op_span.end - op_span.start) as u32,
-                                    token_type,
-                                    token_modifiers_bitset: 0,
-                                });
-                                
-                                prev_line = op_line;
-                                prev_col = op_col;
-                            }
-                        }
-                        Item::Label(_) => {
-                            let delta_line = line - prev_line;
-                            let delta_col = if delta_line == 0 { col - prev_col } else { col };
-                            
-                            tokens_data.push(SemanticToken {
-                                delta_line: delta_line as u32,
-                                delta_start: delta_col as u32,
-                                length: (span.end - span.start) as u32,
-                                token_type: 1,
-                                token_modifiers_bitset: 0,
-                            });
-                            
-                            prev_line = line;
-                            prev_col = col;
-                        }
+                    tokens_data.push(SemanticToken {
+                        delta_line: delta_line as u32,
+                        delta_start: delta_col as u32,
+                        length: (span.end - span.start) as u32,
+                        token_type: 0,
+                        token_modifiers_bitset: 0,
+                    });
+                    
+                    prev_line = line;
+                    prev_col = col;
+                }
+                Item::Instruction(inst) => {
+                    let mnem_text = format!("{:?}", inst.mnemonic);
+                    let mnem_len = mnem_text.len();
+                    
+                    let delta_line = line - prev_line;
+                    let delta_col = if delta_line == 0 { col - prev_col } else { col };
+                    
+                    tokens_data.push(SemanticToken {
+                        delta_line: delta_line as u32,
+                        delta_start: delta_col as u32,
+                        length: mnem_len as u32,
+                        token_type: 3,
+                        token_modifiers_bitset: 0,
+                    });
+                    
+                    prev_line = line;
+                    prev_col = col;
+                    
+                    for operand in &inst.operands {
+                        let op_span = &operand.span;
+                        let (op_line, op_col) = position_from_offset(text, op_span.start);
+                        
+                        let delta_line = op_line - prev_line;
+                        let delta_col = if delta_line == 0 { op_col - prev_col } else { op_col };
+                        
+                        let token_type = match &operand.node {
+                            sayo_ast::Operand::Register(_) => 2,
+                            sayo_ast::Operand::Immediate(_) => 4,
+                            sayo_ast::Operand::Label(_) => 1,
+                            sayo_ast::Operand::Expr(_) => 4,
+                            sayo_ast::Operand::Hid(_) => 4,
+                            sayo_ast::Operand::Color(_) => 4,
+                        };
+                        
+                        tokens_data.push(SemanticToken {
+                            delta_line: delta_line as u32,
+                            delta_start: delta_col as u32,
+                            length: (op_span.end - op_span.start) as u32,
+                            token_type,
+                            token_modifiers_bitset: 0,
+                        });
+                        
+                        prev_line = op_line;
+                        prev_col = op_col;
                     }
                 }
+                Item::Label(_) => {
+                    let delta_line = line - prev_line;
+                    let delta_col = if delta_line == 0 { col - prev_col } else { col };
+                    
+                    tokens_data.push(SemanticToken {
+                        delta_line: delta_line as u32,
+                        delta_start: delta_col as u32,
+                        length: (span.end - span.start) as u32,
+                        token_type: 1,
+                        token_modifiers_bitset: 0,
+                    });
+                    
+                    prev_line = line;
+                    prev_col = col;
+                }
             }
-            Err(_) => return Ok(None),
         }
         
         Ok(Some(SemanticTokensResult::Tokens(SemanticTokens {
@@ -512,6 +688,30 @@ impl LanguageServer for Backend {
             }));
         }
         
+        // Try colour literals
+        let hex_prefixed = word_start > 0 && chars[word_start - 1] == '#';
+        let rest: String = chars[word_end..].iter().collect();
+        if let Some(color) = color_at(&word, hex_prefixed, &rest) {
+            return Ok(Some(Hover {
+                contents: HoverContents::Markup(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: format_color_hover(&color),
+                }),
+                range: None,
+            }));
+        }
+        
+        // Try HID code names (and raw codes of PRESS_*/RELEASE_*)
+        if let Some(code) = hid_code_at(&word, line) {
+            return Ok(Some(Hover {
+                contents: HoverContents::Markup(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: format_hid_hover(&code),
+                }),
+                range: None,
+            }));
+        }
+        
         Ok(None)
     }
 
@@ -542,110 +742,26 @@ impl LanguageServer for Backend {
 
 impl Backend {
     async fn validate_document(&self, uri: &str, text: &str) {
-        let mut diagnostics = Vec::new();
+        let (sources, parsed) = parse_document(uri, text);
+        let mut found = parsed.diagnostics();
         
-        match parse(text) {
-            Ok(program) => {
-                let mut checker = SemanticChecker::new();
-                if let Err(semantic_errors) = checker.check(&program) {
-                    for error in semantic_errors {
-                        let (line, col, message) = match &error {
-                            SemanticError::ImmediateOutOfRange { value, expected_type, line, col } => {
-                                (*line, *col, format!("Immediate value {} out of range for type {}", value, expected_type))
-                            }
-                            SemanticError::WriteToReadOnlyRegister { register, line, col } => {
-                                (*line, *col, format!("Cannot write to read-only register {}", register))
-                            }
-                            SemanticError::OperandCountMismatch { instruction, expected, actual, line, col } => {
-                                (*line, *col, format!("{} requires {} operand(s), but {} provided", instruction, expected, actual))
-                            }
-                            SemanticError::InvalidOperandType { instruction, expected, actual, line, col } => {
-                                (*line, *col, format!("Invalid operand type for {}: expected {}, got {}", instruction, expected, actual))
-                            }
-                            SemanticError::UndefinedLabel { label, line, col } => {
-                                (*line, *col, format!("Undefined label '{}'", label))
-                            }
-                            SemanticError::DuplicateLabel { label, line, col } => {
-                                (*line, *col, format!("Duplicate label definition '{}'", label))
-                            }
-                            SemanticError::LocalLabelWithoutGlobal { label, line, col } => {
-                                (*line, *col, format!("Local label '{}' used without a preceding global label", label))
-                            }
-                            SemanticError::MixedDirectivesAndInstructions { line, col } => {
-                                (*line, *col, "Directives and instructions cannot be mixed in the same section (between labels)".to_string())
-                            }
-                            SemanticError::UnsupportedAlignment { value, line, col } => {
-                                (*line, *col, format!("Unsupported alignment value: .align {} (only .align 1 is supported)", value))
-                            }
-                        };
-                        
-                        let (lsp_line, lsp_col) = position_from_offset(text, line);
-                        let (_, end_col) = position_from_offset(text, col);
-                        
-                        diagnostics.push(Diagnostic {
-                            range: Range {
-                                start: Position { 
-                                    line: lsp_line as u32, 
-                                    character: lsp_col as u32 
-                                },
-                                end: Position { 
-                                    line: lsp_line as u32, 
-                                    character: end_col.max(lsp_col + 1) as u32
-                                },
-                            },
-                            severity: Some(DiagnosticSeverity::ERROR),
-                            code: None,
-                            source: Some("sayo-asm-semantic".to_string()),
-                            message,
-                            ..Default::default()
-                        });
-                    }
-                }
-            }
-            Err(e) => {
-                let (line, column, message) = match &e {
-                    ParseError::UnexpectedToken { line, column, message } => {
-                        (*line, *column, message.clone())
-                    }
-                    ParseError::InvalidToken { line, column, token } => {
-                        (*line, *column, token.clone())
-                    }
-                    ParseError::UnrecognizedToken { line, column, token } => {
-                        (*line, *column, format!("Unrecognized token: {}", token))
-                    }
-                    ParseError::ExtraToken { line, column, token } => {
-                        (*line, *column, format!("Extra token: {}", token))
-                    }
-                    ParseError::UnexpectedEof => {
-                        (0, 0, "Unexpected end of file".to_string())
-                    }
-                };
-                
-                let lsp_line = if line > 0 { line - 1 } else { 0 };
-                let lsp_column = if column > 0 { column - 1 } else { 0 };
-                
-                diagnostics.push(Diagnostic {
-                    range: Range {
-                        start: Position { 
-                            line: lsp_line as u32, 
-                            character: lsp_column as u32 
-                        },
-                        end: Position { 
-                            line: lsp_line as u32, 
-                            character: (lsp_column + 20).min(100) as u32 
-                        },
-                    },
-                    severity: Some(DiagnosticSeverity::ERROR),
-                    code: None,
-                    source: Some("sayo-asm".to_string()),
-                    message,
-                    ..Default::default()
-                });
-            }
+        // Semantic errors would mostly be follow-ups of the syntax errors
+        if parsed.is_ok() {
+            // Lints follow `; sayo: allow(...)` comments; there are no command-line levels here
+            let mut lints = LintConfig::new();
+            lints.read_inline(&sources);
+            let mut checker = SemanticChecker::new().with_lints(lints);
+            // Errors and warnings are both collected by `diagnostics()`
+            let _ = checker.check(&parsed.program);
+            found.extend(checker.diagnostics());
         }
         
+        let uri: Url = uri.parse().unwrap();
+        let diagnostics = found.iter()
+            .map(|diagnostic| to_lsp_diagnostic(diagnostic, &sources, &uri))
+            .collect();
         self.client
-            .publish_diagnostics(uri.parse().unwrap(), diagnostics, None)
+            .publish_diagnostics(uri, diagnostics, None)
             .await;
     }
 }
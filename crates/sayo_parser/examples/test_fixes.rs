@@ -36,6 +36,9 @@ global_label:
                             Operand::Register(r) => print!("Reg({})", r),
                             Operand::Immediate(v) => print!("Imm({})", v),
                             Operand::Label(l) => print!("Label({})", l),
+                            Operand::Expr(e) => print!("Expr({})", e),
+                            Operand::Hid(c) => print!("Hid({})", c),
+                            Operand::Color(c) => print!("Color({})", c),
                         }
                     }
                     println!();
@@ -0,0 +1,117 @@
+use sayo_ast::Span;
+
+/// Conditional assembly state (`.if` / `.elseif` / `.else` / `.endif`)
+#[derive(Debug, Default)]
+pub struct ConditionalStack {
+    frames: Vec<Frame>,
+}
+
+#[derive(Debug)]
+struct Frame {
+    /// Lines in the current branch are assembled
+    active: bool,
+    /// A branch of this block has been (or can no longer be) taken
+    taken: bool,
+    seen_else: bool,
+    /// Span of the opening `.if`
+    opened_at: Span,
+}
+
+impl ConditionalStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether lines at this point are assembled
+    pub fn is_active(&self) -> bool {
+        self.frames.last().is_none_or(|f| f.active)
+    }
+
+    /// Number of open `.if` blocks
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Span of the innermost open `.if`
+    pub fn innermost(&self) -> Option<Span> {
+        self.frames.last().map(|f| f.opened_at)
+    }
+
+    /// Open a block; `condition` is ignored when the enclosing block is skipped
+    pub fn enter_if(&mut self, condition: bool, opened_at: Span) {
+        let enclosing = self.is_active();
+        let active = enclosing && condition;
+        self.frames.push(Frame {
+            active,
+            taken: active || !enclosing,
+            seen_else: false,
+            opened_at,
+        });
+    }
+
+    /// Whether an `.elseif` condition here needs evaluating
+    pub fn wants_elseif(&self) -> bool {
+        self.frames.last().is_some_and(|f| !f.taken)
+    }
+
+    pub fn enter_elseif(&mut self, condition: bool) -> Result<(), String> {
+        let frame = self.frames.last_mut().ok_or(".elseif without .if")?;
+        if frame.seen_else {
+            return Err(".elseif after .else".to_string());
+        }
+        frame.active = !frame.taken && condition;
+        frame.taken |= frame.active;
+        Ok(())
+    }
+
+    pub fn enter_else(&mut self) -> Result<(), String> {
+        let frame = self.frames.last_mut().ok_or(".else without .if")?;
+        if frame.seen_else {
+            return Err("Duplicate .else".to_string());
+        }
+        frame.active = !frame.taken;
+        frame.taken = true;
+        frame.seen_else = true;
+        Ok(())
+    }
+
+    pub fn exit(&mut self) -> Result<(), String> {
+        self.frames.pop().map(|_| ()).ok_or_else(|| ".endif without .if".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_branch_selection() {
+        let mut stack = ConditionalStack::new();
+        stack.enter_if(false, Span::new(0, 3));
+        assert!(!stack.is_active());
+        assert!(stack.wants_elseif());
+        stack.enter_elseif(true).unwrap();
+        assert!(stack.is_active());
+        assert!(!stack.wants_elseif());
+        stack.enter_else().unwrap();
+        assert!(!stack.is_active());
+        stack.exit().unwrap();
+        assert!(stack.is_active());
+        assert!(stack.exit().is_err());
+    }
+
+    #[test]
+    fn test_nested_in_skipped_block() {
+        let mut stack = ConditionalStack::new();
+        stack.enter_if(false, Span::new(0, 3));
+        stack.enter_if(true, Span::new(4, 7));
+        assert!(!stack.is_active());
+        assert!(!stack.wants_elseif());
+        stack.enter_else().unwrap();
+        assert!(!stack.is_active());
+        stack.exit().unwrap();
+        stack.enter_else().unwrap();
+        assert!(stack.is_active());
+        assert!(stack.enter_else().is_err());
+    }
+}
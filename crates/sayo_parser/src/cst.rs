@@ -0,0 +1,282 @@
+/// Lossless concrete syntax tree
+///
+/// Unlike the AST, the tree keeps every byte of the source: whitespace, comments,
+/// blank lines and the original spelling of each token (`0x10` vs `16`, `mov` vs `MOV`).
+/// Printing a tree gives back its input unchanged, so formatters and refactorings can
+/// edit token text in place and write the file back. `lower` produces the `Program`
+/// the assembler works on.
+use crate::lexer::{Lexer, Token};
+use crate::parser::{parse_sources, ParseOptions, ParseOutput};
+use sayo_ast::{SourceMap, Span};
+use std::fmt;
+use std::ops::Range;
+
+/// Kind of a CST token
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyntaxKind {
+    /// Spaces, tabs and carriage returns
+    Whitespace,
+    Newline,
+    /// `; ...` up to the end of the line
+    Comment,
+    /// Mnemonic, register, label or symbol name (including `.L` local labels)
+    Identifier,
+    /// Known directive such as `.byte` (unknown ones lex as identifiers)
+    Directive,
+    /// Integer or character literal
+    Number,
+    /// `#RRGGBB` colour literal
+    Color,
+    String,
+    /// `,` `:` `=` `(` `)`
+    Punct,
+    /// Expression operator
+    Operator,
+    /// Characters the lexer skips (e.g. a stray `$`)
+    Unknown,
+}
+
+impl SyntaxKind {
+    /// Whether the token carries no meaning for the assembler
+    pub fn is_trivia(self) -> bool {
+        matches!(self, Self::Whitespace | Self::Newline | Self::Comment | Self::Unknown)
+    }
+
+    fn of(token: &Token) -> Self {
+        match token {
+            Token::Identifier(_) | Token::Label(_) | Token::Register(_) => Self::Identifier,
+            Token::Integer(_) | Token::HexInteger(_) => Self::Number,
+            Token::String(_) => Self::String,
+            Token::Color(_) => Self::Color,
+            Token::Directive(_) => Self::Directive,
+            Token::Comment(_) => Self::Comment,
+            Token::Newline => Self::Newline,
+            Token::Comma | Token::Colon | Token::Equals | Token::Dot | Token::LParen | Token::RParen => Self::Punct,
+            Token::Encoding(_) => Self::Unknown,
+            _ => Self::Operator,
+        }
+    }
+}
+
+/// A token with its original text
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxToken {
+    pub kind: SyntaxKind,
+    /// Text printed for the token; may be edited, the span keeps the original location
+    pub text: String,
+    pub span: Span,
+}
+
+/// Kind of a statement node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    /// `name:` (or a numeric local label `1:`)
+    Label,
+    /// Instruction or macro invocation
+    Instruction,
+    /// Directive with its arguments, including unknown ones like `.cfi_startproc`
+    Directive,
+    /// `NAME = expression`
+    Assignment,
+    /// Anything else (e.g. a line starting with a number)
+    Unknown,
+}
+
+/// A statement within a line
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxNode {
+    pub kind: NodeKind,
+    /// Indices of the node's tokens: from its first to its last significant token
+    pub tokens: Range<usize>,
+}
+
+/// A source line: its statements plus the surrounding trivia
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxLine {
+    /// Indices of all tokens of the line, including the trailing newline
+    pub tokens: Range<usize>,
+    pub nodes: Vec<SyntaxNode>,
+}
+
+/// Lossless syntax tree of one source file
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SyntaxTree {
+    tokens: Vec<SyntaxToken>,
+    lines: Vec<SyntaxLine>,
+}
+
+impl SyntaxTree {
+    pub fn parse(input: &str) -> Self {
+        let tokens = tokenize(input);
+        let mut lines = Vec::new();
+        let mut start = 0;
+        for (index, token) in tokens.iter().enumerate() {
+            if token.kind == SyntaxKind::Newline {
+                lines.push(build_line(&tokens, start..index + 1));
+                start = index + 1;
+            }
+        }
+        if start < tokens.len() {
+            lines.push(build_line(&tokens, start..tokens.len()));
+        }
+        Self { tokens, lines }
+    }
+
+    pub fn tokens(&self) -> &[SyntaxToken] {
+        &self.tokens
+    }
+
+    /// Tokens with editable text (e.g. to rename a symbol or change a literal's spelling)
+    pub fn tokens_mut(&mut self) -> &mut [SyntaxToken] {
+        &mut self.tokens
+    }
+
+    pub fn lines(&self) -> &[SyntaxLine] {
+        &self.lines
+    }
+
+    /// Tokens of a node or line
+    pub fn slice(&self, range: Range<usize>) -> &[SyntaxToken] {
+        &self.tokens[range]
+    }
+
+    /// Current text of a range of tokens
+    pub fn text(&self, range: Range<usize>) -> String {
+        self.slice(range).iter().map(|token| token.text.as_str()).collect()
+    }
+
+    /// Parse the (possibly edited) text into a program
+    /// `.include` paths are resolved relative to the current directory, as in `parse`
+    pub fn lower(&self, options: &ParseOptions) -> ParseOutput {
+        let mut sources = SourceMap::with_root("", self.to_string());
+        parse_sources(&mut sources, options)
+    }
+}
+
+impl fmt::Display for SyntaxTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.tokens.iter().try_for_each(|token| f.write_str(&token.text))
+    }
+}
+
+/// Lex the whole input, turning the gaps between lexer tokens into trivia
+fn tokenize(input: &str) -> Vec<SyntaxToken> {
+    let mut tokens = Vec::new();
+    let mut offset = 0;
+    let push_gap = |tokens: &mut Vec<SyntaxToken>, start: usize, end: usize| {
+        let mut chars = input[start..end].char_indices().peekable();
+        while let Some((from, c)) = chars.next() {
+            let blank = c.is_whitespace();
+            let mut to = from + c.len_utf8();
+            while let Some(&(next, c)) = chars.peek() {
+                if c.is_whitespace() != blank {
+                    break;
+                }
+                to = next + c.len_utf8();
+                chars.next();
+            }
+            tokens.push(SyntaxToken {
+                kind: if blank { SyntaxKind::Whitespace } else { SyntaxKind::Unknown },
+                text: input[start + from..start + to].to_string(),
+                span: Span::new(start + from, start + to),
+            });
+        }
+    };
+
+    for (start, token, end) in Lexer::new(input) {
+        push_gap(&mut tokens, offset, start);
+        tokens.push(SyntaxToken {
+            kind: SyntaxKind::of(&token),
+            text: input[start..end].to_string(),
+            span: Span::new(start, end),
+        });
+        offset = end;
+    }
+    push_gap(&mut tokens, offset, input.len());
+    tokens
+}
+
+/// Split the significant tokens of a line into statements
+fn build_line(tokens: &[SyntaxToken], range: Range<usize>) -> SyntaxLine {
+    let significant: Vec<usize> = range.clone().filter(|&i| !tokens[i].kind.is_trivia()).collect();
+    let is = |position: usize, kind: SyntaxKind, text: Option<&str>| {
+        significant.get(position).is_some_and(|&i| {
+            tokens[i].kind == kind && text.is_none_or(|text| tokens[i].text == text)
+        })
+    };
+
+    let mut nodes = Vec::new();
+    let mut position = 0;
+    // Leading labels: `name:` or numeric `1:`
+    while (is(position, SyntaxKind::Identifier, None) || is(position, SyntaxKind::Number, None))
+        && is(position + 1, SyntaxKind::Punct, Some(":"))
+    {
+        nodes.push(SyntaxNode {
+            kind: NodeKind::Label,
+            tokens: significant[position]..significant[position + 1] + 1,
+        });
+        position += 2;
+    }
+
+    if let (Some(&first), Some(&last)) = (significant.get(position), significant.last()) {
+        let token = &tokens[first];
+        let kind = match token.kind {
+            SyntaxKind::Directive => NodeKind::Directive,
+            SyntaxKind::Identifier if is(position + 1, SyntaxKind::Punct, Some("=")) => NodeKind::Assignment,
+            SyntaxKind::Identifier if token.text.starts_with('.') => NodeKind::Directive,
+            SyntaxKind::Identifier => NodeKind::Instruction,
+            _ => NodeKind::Unknown,
+        };
+        nodes.push(SyntaxNode { kind, tokens: first..last + 1 });
+    }
+
+    SyntaxLine { tokens: range, nodes }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "; keyboard macro\n\n.text\nmain:  mov R0, 0x1F   ; hex stays hex\n\tkey_a = 'a'\nloop: JMP loop\n.cfi_startproc\n    RET";
+
+    #[test]
+    fn test_lossless_round_trip() {
+        let tree = SyntaxTree::parse(SOURCE);
+        assert_eq!(tree.to_string(), SOURCE);
+        assert_eq!(tree.lines().len(), 8);
+
+        let kinds: Vec<Vec<NodeKind>> = tree.lines().iter()
+            .map(|line| line.nodes.iter().map(|node| node.kind).collect())
+            .collect();
+        use NodeKind::*;
+        assert_eq!(kinds, vec![
+            vec![], vec![], vec![Directive], vec![Label, Instruction],
+            vec![Assignment], vec![Label, Instruction], vec![Directive], vec![Instruction],
+        ]);
+
+        let line = &tree.lines()[3];
+        assert_eq!(tree.text(line.nodes[1].tokens.clone()), "mov R0, 0x1F");
+        assert!(tree.slice(line.tokens.clone()).iter().any(|t| t.kind == SyntaxKind::Comment && t.text == "; hex stays hex"));
+    }
+
+    #[test]
+    fn test_edit_and_lower() {
+        let mut tree = SyntaxTree::parse(SOURCE);
+        for token in tree.tokens_mut() {
+            if token.text == "mov" {
+                token.text = "MOV".to_string();
+            }
+        }
+        assert_eq!(tree.to_string(), SOURCE.replace("mov", "MOV"));
+
+        let lowered = tree.lower(&ParseOptions::default()).into_result().expect("Failed to lower");
+        let parsed = crate::parse(SOURCE).unwrap();
+        let items = |program: &sayo_ast::Program| program.items.iter().map(|item| item.node.clone()).collect::<Vec<_>>();
+        assert_eq!(items(&lowered), items(&parsed));
+
+        // Stray characters are kept as unknown trivia
+        let tree = SyntaxTree::parse("NOP $ odd\r\n");
+        assert_eq!(tree.to_string(), "NOP $ odd\r\n");
+        assert!(tree.tokens().iter().any(|t| t.kind == SyntaxKind::Unknown && t.text == "$"));
+    }
+}
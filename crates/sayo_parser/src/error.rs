@@ -1,3 +1,4 @@
+use sayo_ast::{Diagnostic, FileId, Span};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -30,11 +31,119 @@ pub enum ParseError {
         token: String,
     },
 
+    #[error("Invalid literal at line {line}, column {column}: {message}")]
+    InvalidLiteral {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+
+    #[error("Invalid expression at line {line}, column {column}: {message}")]
+    InvalidExpression {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+
+    #[error("Macro error at line {line}, column {column}: {message}")]
+    Macro {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+
+    #[error("Include error at line {line}, column {column}: {message}")]
+    Include {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+
+    #[error("Repetition error at line {line}, column {column}: {message}")]
+    Repeat {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+
+    #[error("Conditional assembly error at line {line}, column {column}: {message}")]
+    Conditional {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+
+    /// Error inside an included file, reported at the `.include` directive
+    #[error("In '{path}' included at line {line}, column {column}: {error}")]
+    Included {
+        line: usize,
+        column: usize,
+        file: FileId,
+        path: String,
+        error: Box<ParseError>,
+    },
+
     #[error("Unexpected EOF")]
     UnexpectedEof,
 }
 
 impl ParseError {
+    /// Move an error to another location (e.g. from macro expansion text to the invocation site)
+    pub fn relocate(self, line: usize, column: usize) -> Self {
+        match self {
+            Self::UnexpectedToken { message, .. } => Self::UnexpectedToken { line, column, message },
+            Self::InvalidToken { token, .. } => Self::InvalidToken { line, column, token },
+            Self::UnrecognizedToken { token, .. } => Self::UnrecognizedToken { line, column, token },
+            Self::ExtraToken { token, .. } => Self::ExtraToken { line, column, token },
+            Self::InvalidLiteral { message, .. } => Self::InvalidLiteral { line, column, message },
+            Self::InvalidExpression { message, .. } => Self::InvalidExpression { line, column, message },
+            Self::Macro { message, .. } => Self::Macro { line, column, message },
+            Self::Include { message, .. } => Self::Include { line, column, message },
+            Self::Repeat { message, .. } => Self::Repeat { line, column, message },
+            Self::Conditional { message, .. } => Self::Conditional { line, column, message },
+            Self::Included { file, path, error, .. } => Self::Included { line, column, file, path, error },
+            Self::UnexpectedEof => Self::UnexpectedEof,
+        }
+    }
+
+    /// Line and column of the error, if known
+    pub fn location(&self) -> Option<(usize, usize)> {
+        match self {
+            Self::UnexpectedToken { line, column, .. }
+            | Self::InvalidToken { line, column, .. }
+            | Self::UnrecognizedToken { line, column, .. }
+            | Self::ExtraToken { line, column, .. }
+            | Self::InvalidLiteral { line, column, .. }
+            | Self::InvalidExpression { line, column, .. }
+            | Self::Macro { line, column, .. }
+            | Self::Include { line, column, .. }
+            | Self::Repeat { line, column, .. }
+            | Self::Conditional { line, column, .. }
+            | Self::Included { line, column, .. } => (*line > 0).then_some((*line, *column)),
+            Self::UnexpectedEof => None,
+        }
+    }
+
+    /// Diagnostic for this error found at `span`
+    /// Errors inside included files are reported at their own span; the render shows the `.include` chain
+    pub fn diagnostic(&self, span: Span) -> Diagnostic {
+        let (code, message) = match self {
+            Self::UnexpectedToken { message, .. } => ("E0001", message.clone()),
+            Self::InvalidToken { token, .. } => ("E0002", format!("Invalid token: {}", token)),
+            Self::UnrecognizedToken { token, .. } => ("E0003", format!("Unrecognized token: {}", token)),
+            Self::ExtraToken { token, .. } => ("E0004", format!("Extra token: {}", token)),
+            Self::InvalidLiteral { message, .. } => ("E0005", message.clone()),
+            Self::InvalidExpression { message, .. } => ("E0006", format!("Invalid expression: {}", message)),
+            Self::Macro { message, .. } => ("E0007", format!("Macro error: {}", message)),
+            Self::Include { message, .. } => ("E0008", message.clone()),
+            Self::Repeat { message, .. } => ("E0009", message.clone()),
+            Self::Conditional { message, .. } => ("E0010", message.clone()),
+            Self::Included { error, .. } => return error.diagnostic(span),
+            Self::UnexpectedEof => ("E0011", "Unexpected end of file".to_string()),
+        };
+        Diagnostic::error(code, message).with_span(span)
+    }
+
     pub fn from_lalrpop<T>(err: lalrpop_util::ParseError<usize, T, &str>, input: &str) -> Self
     where
         T: std::fmt::Display,
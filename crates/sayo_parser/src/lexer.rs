@@ -1,3 +1,4 @@
+use sayo_ast::Color;
 use std::fmt;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -18,7 +19,32 @@ pub enum Token {
     // Punctuation
     Comma,
     Colon,
+    Equals,
     Dot,
+    LParen,
+    RParen,
+    
+    // Expression operators
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Shl,
+    Shr,
+    Amp,
+    Pipe,
+    Caret,
+    Tilde,
+    Bang,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    EqEq,
+    NotEq,
+    AndAnd,
+    OrOr,
     
     // Directives
     Directive(String),
@@ -34,6 +60,9 @@ pub enum Token {
     
     // Quoted string
     String(String),
+    
+    // Colour literal (#RRGGBB)
+    Color(Color),
 }
 
 impl fmt::Display for Token {
@@ -46,27 +75,67 @@ impl fmt::Display for Token {
             Token::Register(s) => write!(f, "register {}", s),
             Token::Comma => write!(f, ","),
             Token::Colon => write!(f, ":"),
+            Token::Equals => write!(f, "="),
             Token::Dot => write!(f, "."),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Star => write!(f, "*"),
+            Token::Slash => write!(f, "/"),
+            Token::Percent => write!(f, "%"),
+            Token::Shl => write!(f, "<<"),
+            Token::Shr => write!(f, ">>"),
+            Token::Amp => write!(f, "&"),
+            Token::Pipe => write!(f, "|"),
+            Token::Caret => write!(f, "^"),
+            Token::Tilde => write!(f, "~"),
+            Token::Bang => write!(f, "!"),
+            Token::Lt => write!(f, "<"),
+            Token::Le => write!(f, "<="),
+            Token::Gt => write!(f, ">"),
+            Token::Ge => write!(f, ">="),
+            Token::EqEq => write!(f, "=="),
+            Token::NotEq => write!(f, "!="),
+            Token::AndAnd => write!(f, "&&"),
+            Token::OrOr => write!(f, "||"),
             Token::Directive(s) => write!(f, "directive .{}", s),
             Token::Comment(_s) => write!(f, "comment"),
             Token::Newline => write!(f, "newline"),
             Token::Encoding(_) => write!(f, "encoding"),
             Token::String(s) => write!(f, "string \"{}\"", s),
+            Token::Color(c) => write!(f, "colour {}", c),
         }
     }
 }
 
+/// A malformed token, such as `0xZZ` or an overflowing number
+/// The lexer still yields a placeholder token so parsing can go on
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    pub start: usize,
+    pub end: usize,
+    pub message: String,
+}
+
 /// Simple lexer for Sayo assembly
+#[derive(Clone)]
 pub struct Lexer<'input> {
     input: &'input str,
     pos: usize,
     line: usize,
     column: usize,
+    errors: Vec<LexError>,
 }
 
 impl<'input> Lexer<'input> {
     pub fn new(input: &'input str) -> Self {
-        Self { input, pos: 0, line: 1, column: 1 }
+        Self { input, pos: 0, line: 1, column: 1, errors: Vec::new() }
+    }
+    
+    /// Take the errors found since the last call
+    pub fn take_errors(&mut self) -> Vec<LexError> {
+        std::mem::take(&mut self.errors)
     }
     
     pub fn current_line(&self) -> usize {
@@ -76,6 +145,34 @@ impl<'input> Lexer<'input> {
     pub fn current_column(&self) -> usize {
         self.column
     }
+    
+    /// Byte offset of the next unread character
+    pub fn offset(&self) -> usize {
+        self.pos
+    }
+    
+    /// Consume the raw text up to (not including) the end of the current line
+    /// Used for macro arguments, which are substituted as text
+    pub fn rest_of_line(&mut self) -> &'input str {
+        let start = self.pos;
+        while let Some(ch) = self.peek_char() {
+            if ch == '\n' {
+                break;
+            }
+            self.advance();
+        }
+        &self.input[start..self.pos]
+    }
+    
+    /// Consume the next raw line including its newline, returning it without the newline
+    pub fn next_raw_line(&mut self) -> Option<&'input str> {
+        self.peek_char()?;
+        let line = self.rest_of_line();
+        if self.peek_char() == Some('\n') {
+            self.advance();
+        }
+        Some(line)
+    }
 
     fn peek_char(&self) -> Option<char> {
         self.input[self.pos..].chars().next()
@@ -118,14 +215,90 @@ impl<'input> Lexer<'input> {
         self.input[start..self.pos].to_string()
     }
 
+    /// Whether a '*' at the current position starts an indirect register
+    /// (`MOV R0, *R1`, `INC *R1`) rather than a multiplication (`4*2`, `SIZE *N`)
+    fn is_indirect_register_start(&self) -> bool {
+        let next = self.input[self.pos + 1..].chars().next();
+        if !next.is_some_and(|c| c.is_alphabetic() || c == '_') {
+            return false;
+        }
+        let line_start = self.input[..self.pos].rfind('\n').map_or(0, |i| i + 1);
+        let before = &self.input[line_start..self.pos];
+        let trimmed = before.trim_end();
+        let follows_word = trimmed.ends_with(|c: char| c.is_alphanumeric() || c == '_' || c == ')');
+        if !follows_word || trimmed.len() == before.len() {
+            return !follows_word;
+        }
+        // Separated by spaces: the first operand if the word is the mnemonic (after any label)
+        let statement = trimmed.rsplit(':').next().unwrap_or(trimmed).trim_start();
+        statement.chars().all(|c| c.is_alphanumeric() || c == '_')
+    }
+
+    fn error(&mut self, start: usize, message: String) {
+        self.errors.push(LexError { start, end: self.pos, message });
+    }
+
+    /// Read the rest of a character literal after the opening quote
+    fn read_char_literal(&mut self) -> Result<char, String> {
+        let c = match self.peek_char() {
+            None | Some('\n') => return Err("Unterminated character literal".to_string()),
+            Some('\'') => {
+                self.advance();
+                return Err("Empty character literal".to_string());
+            }
+            Some('\\') => {
+                self.advance();
+                let escaped = self.advance().filter(|&c| c != '\n').ok_or("Unterminated character literal")?;
+                match escaped {
+                    'n' => '\n',
+                    'r' => '\r',
+                    't' => '\t',
+                    '0' => '\0',
+                    'e' | 'E' => '\x1b',
+                    '\\' | '\'' | '"' => escaped,
+                    _ => {
+                        self.read_while(|c| c != '\'' && c != '\n');
+                        self.advance_if('\'');
+                        return Err(format!("Unknown escape '\\{}' in character literal", escaped));
+                    }
+                }
+            }
+            Some(c) => {
+                self.advance();
+                c
+            }
+        };
+        if self.advance_if('\'') {
+            return Ok(c);
+        }
+        // Skip to the closing quote (if any) so the error covers the whole literal
+        let rest = self.read_while(|c| c != '\'' && c != '\n');
+        if self.advance_if('\'') {
+            Err(format!("Character literal '{}{}' has more than one character", c, rest))
+        } else {
+            Err("Unterminated character literal".to_string())
+        }
+    }
+
+    fn advance_if(&mut self, expected: char) -> bool {
+        if self.peek_char() == Some(expected) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
     pub fn peek_token(&mut self) -> Option<(usize, Token, usize)> {
         let saved_pos = self.pos;
         let saved_line = self.line;
         let saved_column = self.column;
+        let saved_errors = self.errors.len();
         let token = self.next_token();
         self.pos = saved_pos;
         self.line = saved_line;
         self.column = saved_column;
+        self.errors.truncate(saved_errors);
         token
     }
 
@@ -148,6 +321,15 @@ impl<'input> Lexer<'input> {
                 self.advance();
                 Token::Colon
             }
+            '=' => {
+                self.advance();
+                if self.peek_char() == Some('=') {
+                    self.advance();
+                    Token::EqEq
+                } else {
+                    Token::Equals
+                }
+            }
             ';' => {
                 // Comment
                 self.advance();
@@ -180,7 +362,9 @@ impl<'input> Lexer<'input> {
                     "type" | "size" | "byte" | "word" | "short" | "long" | "quad" | 
                     "ascii" | "asciz" | "zero" | "align" | "p2align" | 
                     "org" | "skip" | "file" | "ident" | "loc" | "addrsig" | "addrsig_sym" | 
-                    "string" | "rodata") {
+                    "string" | "rodata" | "equ" | "set" | "macro" | "endm" | "exitm" | "include" |
+                    "if" | "ifdef" | "ifndef" | "elseif" | "else" | "endif" |
+                    "rept" | "irp" | "irpc" | "endr") {
                     Token::Directive(first_segment.to_string())
                 } else {
                     // It's a local label like .LBB14_25 or .L.str.1
@@ -248,26 +432,124 @@ impl<'input> Lexer<'input> {
                 Token::String(s)
             }
             '0'..='9' => {
-                let num_str = self.read_while(|c| c.is_alphanumeric() || c == 'x' || c == 'X');
-                if num_str.starts_with("0x") || num_str.starts_with("0X") {
-                    let hex = u64::from_str_radix(&num_str[2..], 16).unwrap_or(0);
-                    Token::HexInteger(hex)
-                } else {
-                    let num = num_str.parse::<i64>().unwrap_or(0);
-                    Token::Integer(num)
+                let literal = self.read_while(|c| c.is_alphanumeric() || c == '_');
+                if sayo_ast::numeric_label_ref(&literal).is_some() {
+                    // `1b` / `1f`: reference to a numeric local label
+                    return Some((start, Token::Identifier(literal), self.pos));
+                }
+                match parse_number(&literal) {
+                    Ok(token) => token,
+                    Err(message) => {
+                        self.error(start, message);
+                        Token::Integer(0)
+                    }
+                }
+            }
+            '#' => {
+                self.advance();
+                let digits = self.read_while(|c| c.is_alphanumeric() || c == '_');
+                match Color::from_hex(&digits) {
+                    Some(color) => Token::Color(color),
+                    None => {
+                        self.error(start, format!("Invalid colour literal '#{}' (expected #RRGGBB)", digits));
+                        Token::Integer(0)
+                    }
                 }
             }
+            '\'' => {
+                self.advance();
+                match self.read_char_literal() {
+                    Ok(c) => Token::Integer(c as i64),
+                    Err(message) => {
+                        self.error(start, message);
+                        Token::Integer(0)
+                    }
+                }
+            }
+            '(' => {
+                self.advance();
+                Token::LParen
+            }
+            ')' => {
+                self.advance();
+                Token::RParen
+            }
+            '+' => {
+                self.advance();
+                Token::Plus
+            }
             '-' => {
+                // Negative literals are folded by the parser (unary minus)
                 self.advance();
-                let num_str = self.read_while(|c| c.is_numeric());
-                let num = num_str.parse::<i64>().unwrap_or(0);
-                Token::Integer(-num)
+                Token::Minus
+            }
+            '/' => {
+                self.advance();
+                Token::Slash
+            }
+            '%' => {
+                self.advance();
+                Token::Percent
+            }
+            '&' => {
+                self.advance();
+                if self.peek_char() == Some('&') {
+                    self.advance();
+                    Token::AndAnd
+                } else {
+                    Token::Amp
+                }
+            }
+            '|' => {
+                self.advance();
+                if self.peek_char() == Some('|') {
+                    self.advance();
+                    Token::OrOr
+                } else {
+                    Token::Pipe
+                }
+            }
+            '!' => {
+                self.advance();
+                if self.peek_char() == Some('=') {
+                    self.advance();
+                    Token::NotEq
+                } else {
+                    Token::Bang
+                }
+            }
+            '^' => {
+                self.advance();
+                Token::Caret
+            }
+            '~' => {
+                self.advance();
+                Token::Tilde
+            }
+            '<' | '>' => {
+                self.advance();
+                let next = self.peek_char();
+                if next == Some(ch) || next == Some('=') {
+                    self.advance();
+                }
+                match (ch, next) {
+                    ('<', Some('<')) => Token::Shl,
+                    ('>', Some('>')) => Token::Shr,
+                    ('<', Some('=')) => Token::Le,
+                    ('>', Some('=')) => Token::Ge,
+                    ('<', _) => Token::Lt,
+                    _ => Token::Gt,
+                }
+            }
+            '*' if !self.is_indirect_register_start() => {
+                self.advance();
+                Token::Star
             }
             'a'..='z' | 'A'..='Z' | '_' | '*' => {
-                let ident = self.read_while(|c| {
-                    c.is_alphanumeric() || c == '_' || c == '*'
-                });
-                Token::Identifier(ident)
+                // '*' only leads an identifier (indirect register like *R0_32b)
+                self.advance();
+                let rest = self.read_while(|c| c.is_alphanumeric() || c == '_');
+                Token::Identifier(format!("{}{}", ch, rest))
             }
             _ => {
                 self.advance();
@@ -279,6 +561,28 @@ impl<'input> Lexer<'input> {
     }
 }
 
+/// Parse an integer literal: decimal, `0x` hex, `0b` binary or `0o` octal, with
+/// optional `_` separators. Decimal literals must fit in i64; prefixed literals
+/// are bit patterns and may use the full 64 bits.
+fn parse_number(literal: &str) -> Result<Token, String> {
+    let digits = literal.replace('_', "");
+    let (radix, body, name) = match digits.get(..2) {
+        Some("0x") | Some("0X") => (16, &digits[2..], "hexadecimal"),
+        Some("0b") | Some("0B") => (2, &digits[2..], "binary"),
+        Some("0o") | Some("0O") => (8, &digits[2..], "octal"),
+        _ => (10, digits.as_str(), "decimal"),
+    };
+    if body.is_empty() || !body.chars().all(|c| c.is_digit(radix)) {
+        return Err(format!("Invalid {} literal '{}'", name, literal));
+    }
+    let out_of_range = |_| format!("Integer literal '{}' is out of range", literal);
+    match radix {
+        10 => body.parse::<i64>().map(Token::Integer).map_err(out_of_range),
+        16 => u64::from_str_radix(body, 16).map(Token::HexInteger).map_err(out_of_range),
+        _ => u64::from_str_radix(body, radix).map(|n| Token::Integer(n as i64)).map_err(out_of_range),
+    }
+}
+
 impl<'input> Iterator for Lexer<'input> {
     type Item = (usize, Token, usize);
 
@@ -286,3 +590,57 @@ impl<'input> Iterator for Lexer<'input> {
         self.next_token()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(input: &str) -> (Vec<Token>, Vec<LexError>) {
+        let mut lexer = Lexer::new(input);
+        let tokens = lexer.by_ref().map(|(_, token, _)| token).collect();
+        (tokens, lexer.take_errors())
+    }
+
+    #[test]
+    fn test_numeric_literals() {
+        let (toks, errors) = tokens("0b1010 0o17 1_000 0xFF_FF 'A' '\\n' '\\''");
+        assert!(errors.is_empty());
+        assert_eq!(toks, vec![
+            Token::Integer(10),
+            Token::Integer(15),
+            Token::Integer(1000),
+            Token::HexInteger(0xFFFF),
+            Token::Integer(65),
+            Token::Integer(10),
+            Token::Integer(39),
+        ]);
+    }
+
+    #[test]
+    fn test_star() {
+        let identifier = |name: &str| Token::Identifier(name.to_string());
+        let (toks, _) = tokens("MOV32 R0, SIZE *N\n");
+        assert_eq!(toks, vec![identifier("MOV32"), identifier("R0"), Token::Comma, identifier("SIZE"), Token::Star, identifier("N"), Token::Newline]);
+        let (toks, _) = tokens("loop: INC *R1\nMOV R0, *R2\n");
+        assert_eq!(toks, vec![
+            identifier("loop"), Token::Colon, identifier("INC"), identifier("*R1"), Token::Newline,
+            identifier("MOV"), identifier("R0"), Token::Comma, identifier("*R2"), Token::Newline,
+        ]);
+        let (toks, _) = tokens("(2) *X 4*Y");
+        assert_eq!(toks, vec![Token::LParen, Token::Integer(2), Token::RParen, Token::Star, identifier("X"), Token::Integer(4), Token::Star, identifier("Y")]);
+    }
+
+    #[test]
+    fn test_malformed_literals() {
+        let input = "0xZZ 99999999999999999999 0b102 12ab 'AB' '";
+        let (_, errors) = tokens(input);
+        let spans: Vec<&str> = errors.iter().map(|e| &input[e.start..e.end]).collect();
+        assert_eq!(spans, vec!["0xZZ", "99999999999999999999", "0b102", "12ab", "'AB'", "'"]);
+
+        // Peeking does not report an error twice
+        let mut lexer = Lexer::new("0xZZ");
+        lexer.peek_token();
+        lexer.next_token();
+        assert_eq!(lexer.take_errors().len(), 1);
+    }
+}
@@ -3,16 +3,30 @@
 
 pub mod lexer;
 pub mod error;
+pub mod conditional;
+pub mod cst;
+pub mod macros;
+pub mod repeat;
 pub mod parser;
 
+pub use cst::SyntaxTree;
 pub use error::ParseError;
-use sayo_ast::{Program};
+pub use parser::{ParseOptions, ParseOutput};
+use sayo_ast::{Program, SourceMap};
 
-/// Parse assembly source code into an AST
+/// Parse assembly source code into an AST, returning the first error if any
+/// `.include` paths are resolved relative to the current directory
 pub fn parse(input: &str) -> Result<Program, ParseError> {
     parser::parse_program(input)
 }
 
+/// Parse the root file of `sources`, loading `.include`d files into the map
+/// Includes are searched next to the including file, then in `options.include_paths`
+/// Parsing recovers after errors: the output holds every statement that parsed and all errors
+pub fn parse_sources(sources: &mut SourceMap, options: &ParseOptions) -> ParseOutput {
+    parser::parse_sources(sources, options)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,277 @@
+/// GNU-as style macros (`.macro name a, b=1` ... `.endm`)
+///
+/// Macros are expanded textually: the body is kept as raw source lines and
+/// every invocation substitutes `\arg`, `\@` (expansion counter) and `\()`
+/// (separator) before the result is parsed again.
+use std::collections::HashMap;
+
+/// A macro parameter with an optional default value
+#[derive(Debug, Clone, PartialEq)]
+pub struct MacroParam {
+    pub name: String,
+    pub default: Option<String>,
+}
+
+/// A macro definition
+#[derive(Debug, Clone, PartialEq)]
+pub struct Macro {
+    pub name: String,
+    pub params: Vec<MacroParam>,
+    pub body: Vec<String>,
+}
+
+impl Macro {
+    /// Parse the header of a `.macro` directive (the text after `.macro`)
+    pub fn from_header(header: &str) -> Result<Self, String> {
+        let header = strip_comment(header).trim();
+        let name_end = header
+            .find(|c: char| c.is_whitespace() || c == ',')
+            .unwrap_or(header.len());
+        let name = &header[..name_end];
+        if !is_symbol_name(name) {
+            return Err(format!("Invalid macro name '{}'", name));
+        }
+
+        let mut params: Vec<MacroParam> = Vec::new();
+        for param in header[name_end..]
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|p| !p.is_empty())
+        {
+            let (param_name, default) = match param.split_once('=') {
+                Some((n, d)) => (n, Some(d.to_string())),
+                None => (param, None),
+            };
+            if !is_symbol_name(param_name) {
+                return Err(format!("Invalid parameter '{}' in macro '{}'", param_name, name));
+            }
+            if params.iter().any(|p| p.name == param_name) {
+                return Err(format!("Duplicate parameter '{}' in macro '{}'", param_name, name));
+            }
+            params.push(MacroParam { name: param_name.to_string(), default });
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            params,
+            body: Vec::new(),
+        })
+    }
+
+    /// Expand the macro body for one invocation
+    /// `args` is the raw argument text after the macro name; `.exitm` is kept and ends
+    /// the expansion when it is parsed, so it can sit inside `.if` blocks
+    pub fn expand(&self, args: &str, counter: usize) -> Result<String, String> {
+        let values = self.bind_args(args)?;
+        let mut output = String::new();
+
+        for line in &self.body {
+            output.push_str(&self.substitute(line, &values, counter));
+            output.push('\n');
+        }
+
+        Ok(output)
+    }
+
+    /// Match invocation arguments (positional or `name=value`) to parameters
+    fn bind_args(&self, args: &str) -> Result<Vec<String>, String> {
+        let mut values: Vec<Option<String>> = vec![None; self.params.len()];
+        let mut next_positional = 0;
+
+        for arg in split_args(strip_comment(args)) {
+            let keyword = arg.split_once('=').and_then(|(name, value)| {
+                let idx = self.params.iter().position(|p| p.name == name.trim())?;
+                Some((idx, value.trim()))
+            });
+
+            match keyword {
+                Some((idx, value)) => values[idx] = Some(value.to_string()),
+                None => {
+                    if next_positional >= self.params.len() {
+                        return Err(format!(
+                            "Too many arguments for macro '{}' (expected at most {})",
+                            self.name,
+                            self.params.len()
+                        ));
+                    }
+                    values[next_positional] = Some(arg.to_string());
+                    next_positional += 1;
+                }
+            }
+        }
+
+        // Missing arguments fall back to the default, or expand to nothing
+        Ok(values
+            .into_iter()
+            .zip(&self.params)
+            .map(|(value, param)| value.or_else(|| param.default.clone()).unwrap_or_default())
+            .collect())
+    }
+
+    fn substitute(&self, line: &str, values: &[String], counter: usize) -> String {
+        let value_of = |name: &str| {
+            let idx = self.params.iter().position(|p| p.name == name)?;
+            Some(values[idx].as_str())
+        };
+        substitute(line, value_of, Some(counter))
+    }
+}
+
+/// Macro definitions collected while parsing
+#[derive(Debug, Default)]
+pub struct MacroTable {
+    macros: HashMap<String, Macro>,
+    /// Number of expansions so far, substituted for `\@`
+    expansions: usize,
+}
+
+impl MacroTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Define a macro; names are case-insensitive like mnemonics
+    pub fn define(&mut self, mac: Macro) -> Result<(), String> {
+        let key = mac.name.to_lowercase();
+        if self.macros.contains_key(&key) {
+            return Err(format!("Macro '{}' is already defined", mac.name));
+        }
+        self.macros.insert(key, mac);
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Macro> {
+        self.macros.get(&name.to_lowercase())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.macros.contains_key(&name.to_lowercase())
+    }
+
+    /// Expand an invocation of macro `name`
+    pub fn expand(&mut self, name: &str, args: &str) -> Result<String, String> {
+        let counter = self.expansions;
+        self.expansions += 1;
+        self.get(name)
+            .ok_or_else(|| format!("Unknown macro '{}'", name))?
+            .expand(args, counter)
+    }
+}
+
+pub(crate) fn is_symbol_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Replace `\name` with `value_of(name)`, `\@` with `counter` and drop `\()`
+/// Unknown names (e.g. string escapes) and `\@` without a counter are kept as is
+pub(crate) fn substitute<'v>(line: &str, value_of: impl Fn(&str) -> Option<&'v str>, counter: Option<usize>) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+
+    while let Some(idx) = rest.find('\\') {
+        out.push_str(&rest[..idx]);
+        let after = &rest[idx + 1..];
+
+        if let (Some(stripped), Some(counter)) = (after.strip_prefix('@'), counter) {
+            out.push_str(&counter.to_string());
+            rest = stripped;
+        } else if let Some(stripped) = after.strip_prefix("()") {
+            rest = stripped;
+        } else {
+            let len = after
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            match value_of(&after[..len]) {
+                Some(value) => {
+                    out.push_str(value);
+                    rest = &after[len..];
+                }
+                None => {
+                    out.push('\\');
+                    rest = after;
+                }
+            }
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Drop a trailing `;` comment, ignoring semicolons inside string literals
+pub(crate) fn strip_comment(s: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &s[..i],
+            _ => {}
+        }
+    }
+    s
+}
+
+/// Split invocation arguments on top-level commas (not inside parentheses or strings)
+pub(crate) fn split_args(s: &str) -> Vec<&str> {
+    let mut args = Vec::new();
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut start = 0;
+
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '(' if !in_string => depth += 1,
+            ')' if !in_string => depth = depth.saturating_sub(1),
+            ',' if !in_string && depth == 0 => {
+                args.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    let last = s[start..].trim();
+    if !last.is_empty() || !args.is_empty() {
+        args.push(last);
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_macro_expansion() {
+        let mut mac = Macro::from_header("tap key, delay=10 ; press and release").unwrap();
+        mac.body = vec![
+            "PRESS_GK \\key".to_string(),
+            ".Ltap\\@: SLEEP \\delay".to_string(),
+            "RELEASE_GK \\key".to_string(),
+            ".exitm".to_string(),
+            "NOP".to_string(),
+        ];
+
+        assert_eq!(
+            mac.expand("R1", 3).unwrap(),
+            "PRESS_GK R1\n.Ltap3: SLEEP 10\nRELEASE_GK R1\n.exitm\nNOP\n"
+        );
+        assert_eq!(
+            mac.expand("delay=(2*5), key=R2", 4).unwrap(),
+            "PRESS_GK R2\n.Ltap4: SLEEP (2*5)\nRELEASE_GK R2\n.exitm\nNOP\n"
+        );
+        assert!(mac.expand("R1, 1, 2", 5).is_err());
+    }
+
+    #[test]
+    fn test_substitute_keeps_escapes() {
+        let mut mac = Macro::from_header("msg text").unwrap();
+        mac.body = vec![".asciz \"\\text\\n\"".to_string(), "lbl\\()_end:".to_string()];
+        assert_eq!(mac.expand("hi", 0).unwrap(), ".asciz \"hi\\n\"\nlbl_end:\n");
+    }
+}
@@ -1,51 +1,641 @@
 /// Simple manual parser for Sayo assembly
 /// This bypasses LALRPOP to avoid LR(1) conflicts during initial development
+use crate::conditional::ConditionalStack;
 use crate::error::ParseError;
 use crate::lexer::{Token, Lexer};
+use crate::macros::{strip_comment, Macro, MacroTable};
+use crate::repeat::Repeat;
 use sayo_ast::*;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Maximum nesting of macro invocations (guards against recursive macros)
+const MAX_MACRO_DEPTH: usize = 64;
+
+/// Options for parsing a set of source files
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Directories searched for `.include` files after the including file's directory
+    pub include_paths: Vec<PathBuf>,
+    /// Constants defined before the root file (`sayoasm -D NAME=VALUE`)
+    pub defines: Vec<(String, i64)>,
+}
+
+/// Result of parsing with error recovery
+#[derive(Debug)]
+pub struct ParseOutput {
+    /// Items of every statement that parsed
+    pub program: Program,
+    /// Every error, each spanning the statement it was found in
+    pub errors: Vec<Spanned<ParseError>>,
+}
+
+impl ParseOutput {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Every error as a diagnostic
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.errors.iter().map(|error| error.node.diagnostic(error.span)).collect()
+    }
+
+    /// The program, or the first error
+    pub fn into_result(self) -> Result<Program, ParseError> {
+        match self.errors.into_iter().next() {
+            Some(error) => Err(error.node),
+            None => Ok(self.program),
+        }
+    }
+}
+
+/// State shared by the root file, included files and macro expansions
+struct ParseContext<'a> {
+    macros: MacroTable,
+    sources: &'a mut SourceMap,
+    include_paths: &'a [PathBuf],
+    /// Canonical paths of the files currently being parsed (for cycle detection)
+    include_stack: Vec<PathBuf>,
+    /// Constants whose value is known so far (for `.if` conditions)
+    constants: HashMap<String, i64>,
+    /// Labels and constants defined so far (for `.ifdef`; every symbol once parsing ends)
+    symbols: HashSet<String>,
+    /// Set by `.exitm` until the macro expansion it ends has been left
+    exiting_macro: bool,
+    errors: Vec<Spanned<ParseError>>,
+}
+
+impl ParseContext<'_> {
+    /// Record the symbols defined by an item
+    fn define(&mut self, item: &Item) {
+        match item {
+            Item::Label(name) => {
+                self.symbols.insert(name.clone());
+            }
+            Item::Directive(Directive::Equ(name, value, _)) => {
+                // A reassignment replaces the value, or forgets it when not known yet
+                match value.eval(&|symbol| self.constants.get(symbol).copied()) {
+                    Ok(value) => self.constants.insert(name.clone(), value),
+                    Err(_) => self.constants.remove(name),
+                };
+                self.symbols.insert(name.clone());
+            }
+            _ => {}
+        }
+    }
+    
+    fn error(&mut self, error: ParseError, span: Span) {
+        self.errors.push(Spanned::new(error, span));
+    }
+    
+    /// Remove the errors recorded since `first` (to rewrite them for an enclosing context)
+    fn take_errors(&mut self, first: usize) -> Vec<Spanned<ParseError>> {
+        self.errors.split_off(first)
+    }
+}
 
 pub fn parse_program(input: &str) -> Result<Program, ParseError> {
+    let mut sources = SourceMap::with_root("", input);
+    parse_sources(&mut sources, &ParseOptions::default()).into_result()
+}
+
+/// Parse the root file of `sources`, adding every `.include`d file to it
+/// Command-line defines are parsed first, as `NAME = VALUE` lines of a `<command line>` file
+/// After an error the parser resumes at the next line, so all errors are reported at once
+pub fn parse_sources(sources: &mut SourceMap, options: &ParseOptions) -> ParseOutput {
+    let root_text = sources.text(FileId::ROOT).to_string();
+    let include_stack = std::fs::canonicalize(sources.path(FileId::ROOT)).into_iter().collect();
+    let mut ctx = ParseContext {
+        macros: MacroTable::new(),
+        sources,
+        include_paths: &options.include_paths,
+        include_stack,
+        constants: HashMap::new(),
+        symbols: HashSet::new(),
+        exiting_macro: false,
+        errors: Vec::new(),
+    };
+    
+    let mut items = Vec::new();
+    if !options.defines.is_empty() {
+        let text: String = options.defines.iter().map(|(name, value)| format!("{} = {}\n", name, value)).collect();
+        let file = ctx.sources.add("<command line>", text.clone(), None);
+        items = parse_items(&text, file, &mut ctx, 0);
+    }
+    items.extend(parse_items(&root_text, FileId::ROOT, &mut ctx, 0));
+    resolve_builtin_names(&mut items, &ctx.symbols);
+    ParseOutput {
+        program: Program { items },
+        errors: ctx.errors,
+    }
+}
+
+/// Turn operands naming a built-in HID code or colour into that value, unless the program
+/// defines a symbol of the same name; runs once every symbol is known, so a label defined
+/// after its use (`CALL key_a` ... `key_a:`) still takes precedence
+fn resolve_builtin_names(items: &mut [Spanned<Item>], symbols: &HashSet<String>) {
+    for item in items {
+        let Item::Instruction(inst) = &mut item.node else {
+            continue;
+        };
+        for index in 0..inst.operands.len() {
+            let accepts_color = inst.accepts_color(index);
+            let operand = &mut inst.operands[index];
+            let Operand::Label(label) = &operand.node else {
+                continue;
+            };
+            if symbols.contains(label) {
+                continue;
+            }
+            if let Some(code) = HidCode::from_name(label) {
+                operand.node = Operand::Hid(code);
+            } else if let Some(color) = Color::from_name(label).filter(|_| accepts_color) {
+                operand.node = Operand::Color(color);
+            }
+        }
+    }
+}
+
+/// Parse a source text into items, expanding macro invocations and includes in place
+/// Errors are recorded in `ctx` and parsing resumes at the next line
+fn parse_items(input: &str, file: FileId, ctx: &mut ParseContext, depth: usize) -> Vec<Spanned<Item>> {
     let mut lexer = Lexer::new(input);
     let mut items = Vec::new();
+    let mut conditionals = ConditionalStack::new();
     
     while let Some((start, token, end)) = lexer.next_token() {
-        let span = Span::new(start, end);
+        let line = lexer.current_line();
+        let span = Span::new(start, end).with_file(file);
+        let active = conditionals.is_active();
+        
+        // Conditional directives are tracked even inside skipped blocks
+        let result = match &token {
+            Token::Directive(name) if is_conditional_directive(name) => {
+                parse_conditional(name, statement_span(input, span), &mut conditionals, &mut lexer, ctx)
+            }
+            Token::Newline => Ok(()),
+            _ if !conditionals.is_active() => {
+                consume_rest_of_line(&mut lexer);
+                Ok(())
+            }
+            // Ends the macro expansion together with any `.if` open in it
+            Token::Directive(name) if name == "exitm" && depth > 0 => {
+                ctx.exiting_macro = true;
+                return items;
+            }
+            _ => parse_statement(token, span, &mut lexer, ctx, &mut items, depth),
+        };
+        
+        // Malformed literals are reported at the literal itself (skipped blocks are not checked)
+        for error in lexer.take_errors().into_iter().filter(|_| active) {
+            let (line, column) = line_col(input, error.start);
+            let literal = ParseError::InvalidLiteral { line, column, message: error.message };
+            ctx.error(literal, Span::new(error.start, error.end).with_file(file));
+        }
         
-        match token {
-            Token::Directive(name) => {
-                let directive = parse_directive(&name, &mut lexer)?;
-                items.push(Spanned::new(Item::Directive(directive), span));
-            }
-            Token::Identifier(name) => {
-                // Check if next is colon (label) or operands (instruction)
-                if let Some((_, Token::Colon, end2)) = lexer.peek_token() {
-                    lexer.next_token(); // consume colon
-                    items.push(Spanned::new(Item::Label(name), Span::new(start, end2)));
-                } else {
-                    // It's an instruction
-                    let inst = parse_instruction(&name, &mut lexer)?;
-                    items.push(Spanned::new(Item::Instruction(inst), span));
+        if let Err(error) = result {
+            ctx.error(error, statement_span(input, span));
+            // Resynchronise at the next line, unless the statement already consumed lines
+            if lexer.current_line() == line {
+                lexer.rest_of_line();
+            }
+        }
+        
+        // An `.exitm` in an included file or repeat block also ends this expansion
+        if ctx.exiting_macro {
+            return items;
+        }
+    }
+    
+    if let Some(opened) = conditionals.innermost() {
+        let (line, column) = line_col(input, opened.start);
+        ctx.error(
+            ParseError::Conditional { line, column, message: "Missing .endif".to_string() },
+            opened,
+        );
+    }
+    
+    items
+}
+
+/// Parse one statement starting with `token`, pushing the resulting items
+fn parse_statement(
+    token: Token,
+    span: Span,
+    lexer: &mut Lexer,
+    ctx: &mut ParseContext,
+    items: &mut Vec<Spanned<Item>>,
+    depth: usize,
+) -> Result<(), ParseError> {
+    let (start, file) = (span.start, span.file);
+    match token {
+        Token::Directive(name) if name == "macro" => {
+            parse_macro_definition(lexer, &mut ctx.macros)?;
+        }
+        Token::Directive(name) if name == "endm" || name == "exitm" => {
+            return Err(ParseError::Macro {
+                line: lexer.current_line(),
+                column: lexer.current_column(),
+                message: format!(".{} outside of a macro definition", name),
+            });
+        }
+        Token::Directive(name) if matches!(name.as_str(), "rept" | "irp" | "irpc") => {
+            let repeated = parse_repeat(&name, file, lexer, ctx, depth)?;
+            items.extend(repeated);
+        }
+        Token::Directive(name) if name == "endr" => {
+            return Err(ParseError::Repeat {
+                line: lexer.current_line(),
+                column: lexer.current_column(),
+                message: ".endr without .rept, .irp or .irpc".to_string(),
+            });
+        }
+        Token::Directive(name) if name == "include" => {
+            let included = parse_include(span, lexer, ctx, depth)?;
+            items.extend(included);
+        }
+        Token::Directive(name) => {
+            let directive = Item::Directive(parse_directive(&name, lexer)?);
+            ctx.define(&directive);
+            items.push(Spanned::new(directive, span));
+        }
+        Token::Identifier(name) => {
+            // Check if next is colon (label) or operands (instruction)
+            if let Some((_, Token::Colon, end2)) = lexer.peek_token() {
+                lexer.next_token(); // consume colon
+                let label = Item::Label(name);
+                ctx.define(&label);
+                items.push(Spanned::new(label, Span::new(start, end2).with_file(file)));
+            } else if let Some((_, Token::Equals, _)) = lexer.peek_token() {
+                // NAME = expr (same as .set NAME, expr: may be reassigned)
+                lexer.next_token(); // consume '='
+                let (expr, expr_span) = parse_expr(lexer)?;
+                let value = fold_expr(expr, lexer)?;
+                let constant = Item::Directive(Directive::Equ(name, value, true));
+                ctx.define(&constant);
+                items.push(Spanned::new(constant, span.merge(expr_span)));
+            } else if ctx.macros.contains(&name) {
+                let expanded = parse_macro_invocation(&name, span, lexer, ctx, depth)?;
+                items.extend(expanded);
+            } else if let Some(directive) = name.strip_prefix('.') {
+                // `.name` that is neither a label nor a known directive (e.g. .cfi_startproc)
+                let unknown = parse_unknown_directive(directive, lexer);
+                items.push(Spanned::new(Item::Directive(unknown), span));
+            } else {
+                // It's an instruction
+                let mut inst = parse_instruction(&name, lexer)?;
+                for operand in &mut inst.operands {
+                    operand.span = operand.span.with_file(file);
                 }
+                items.push(Spanned::new(Item::Instruction(inst), span));
             }
-            Token::Newline => {
-                // Skip newlines
-                continue;
+        }
+        Token::Integer(number) if number >= 0 && matches!(lexer.peek_token(), Some((_, Token::Colon, _))) => {
+            // Numeric local label (`1:`), referenced as `1b` / `1f`
+            let (_, _, end) = lexer.next_token().unwrap();
+            let label = Item::Label(number.to_string());
+            items.push(Spanned::new(label, Span::new(start, end).with_file(file)));
+        }
+        Token::Newline | Token::Comment(_) => {
+            // Skip newlines and comments
+        }
+        _ => {
+            return Err(ParseError::UnexpectedToken {
+                line: lexer.current_line(),
+                column: lexer.current_column(),
+                message: format!("Unexpected token: {}", token),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Span from `span` to the end of its line, excluding any trailing comment
+fn statement_span(input: &str, span: Span) -> Span {
+    let line_end = input[span.start..].find('\n').map_or(input.len(), |i| span.start + i);
+    let text = strip_comment(&input[span.start..line_end]).trim_end();
+    Span::new(span.start, (span.start + text.len()).max(span.end)).with_file(span.file)
+}
+
+/// 1-based line and column of a byte offset
+fn line_col(input: &str, offset: usize) -> (usize, usize) {
+    let before = &input[..offset.min(input.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+}
+
+fn is_conditional_directive(name: &str) -> bool {
+    matches!(name, "if" | "ifdef" | "ifndef" | "elseif" | "else" | "endif")
+}
+
+/// Handle `.if`/`.ifdef`/`.ifndef`/`.elseif`/`.else`/`.endif`
+/// Conditions are only evaluated where they can select a branch
+fn parse_conditional(
+    name: &str,
+    span: Span,
+    conditionals: &mut ConditionalStack,
+    lexer: &mut Lexer,
+    ctx: &ParseContext,
+) -> Result<(), ParseError> {
+    let (line, column) = (lexer.current_line(), lexer.current_column());
+    let conditional_error = |message| ParseError::Conditional { line, column, message };
+    
+    // A condition that fails to evaluate still opens its branch (as false),
+    // so the matching .else/.endif do not produce follow-up errors
+    match name {
+        "if" | "ifdef" | "ifndef" => {
+            let condition = if conditionals.is_active() {
+                parse_condition(name, lexer, ctx)
+            } else {
+                consume_rest_of_line(lexer);
+                Ok(false)
+            };
+            conditionals.enter_if(*condition.as_ref().unwrap_or(&false), span);
+            condition.map(|_| ())
+        }
+        "elseif" => {
+            let condition = if conditionals.wants_elseif() {
+                parse_condition("if", lexer, ctx)
+            } else {
+                consume_rest_of_line(lexer);
+                Ok(false)
+            };
+            conditionals.enter_elseif(*condition.as_ref().unwrap_or(&false)).map_err(conditional_error)?;
+            condition.map(|_| ())
+        }
+        "else" => conditionals.enter_else().map_err(conditional_error),
+        _ => conditionals.exit().map_err(conditional_error),
+    }
+}
+
+/// Evaluate the argument of `.if <expr>` or `.ifdef`/`.ifndef <symbol>`
+fn parse_condition(name: &str, lexer: &mut Lexer, ctx: &ParseContext) -> Result<bool, ParseError> {
+    if name == "if" {
+        let (expr, _) = parse_expr(lexer)?;
+        let value = expr
+            .eval(&|symbol| ctx.constants.get(symbol).copied())
+            .map_err(|e| invalid_expression(lexer, e.to_string()))?;
+        return Ok(value != 0);
+    }
+    
+    let symbol = match lexer.next_token() {
+        Some((_, Token::Identifier(symbol), _)) => symbol,
+        Some((_, Token::Directive(symbol), _)) => format!(".{}", symbol),
+        _ => {
+            return Err(ParseError::Conditional {
+                line: lexer.current_line(),
+                column: lexer.current_column(),
+                message: format!("Expected symbol name after .{}", name),
+            });
+        }
+    };
+    Ok(ctx.symbols.contains(&symbol) == (name == "ifdef"))
+}
+
+/// Handle `.include "path"`: load the file into the source map and parse it in place
+fn parse_include(
+    directive_span: Span,
+    lexer: &mut Lexer,
+    ctx: &mut ParseContext,
+    depth: usize,
+) -> Result<Vec<Spanned<Item>>, ParseError> {
+    let (line, column) = (lexer.current_line(), lexer.current_column());
+    let include_error = |message| ParseError::Include { line, column, message };
+    
+    let (name, span) = match lexer.next_token() {
+        Some((_, Token::String(name), end)) => (name, directive_span.merge(Span::new(directive_span.start, end))),
+        _ => return Err(include_error("Expected a quoted file name after .include".to_string())),
+    };
+    
+    // Search next to the including file first, then the -I directories
+    let base_dir = ctx.sources.path(directive_span.file).parent().map(Path::to_path_buf).unwrap_or_default();
+    let path = std::iter::once(&base_dir)
+        .chain(ctx.include_paths)
+        .map(|dir| dir.join(&name))
+        .find(|candidate| candidate.is_file())
+        .ok_or_else(|| include_error(format!("Cannot find include file '{}'", name)))?;
+    
+    let canonical = std::fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+    if let Some(pos) = ctx.include_stack.iter().position(|p| *p == canonical) {
+        let cycle: Vec<_> = ctx.include_stack[pos..]
+            .iter()
+            .chain(std::iter::once(&canonical))
+            .map(|p| p.display().to_string())
+            .collect();
+        return Err(include_error(format!("Include cycle: {}", cycle.join(" -> "))));
+    }
+    
+    let text = std::fs::read_to_string(&path)
+        .map_err(|e| include_error(format!("Cannot read '{}': {}", path.display(), e)))?;
+    let file = ctx.sources.add(&path, text.clone(), Some(span));
+    
+    ctx.include_stack.push(canonical);
+    let first_error = ctx.errors.len();
+    let items = parse_items(&text, file, ctx, depth);
+    ctx.include_stack.pop();
+    
+    // Errors keep their span in the included file
+    for error in ctx.take_errors(first_error) {
+        let included = ParseError::Included {
+            line,
+            column,
+            file,
+            path: path.display().to_string(),
+            error: Box::new(error.node),
+        };
+        ctx.error(included, error.span);
+    }
+    Ok(items)
+}
+
+/// Collect a `.macro` definition (header already consumed up to `.macro`)
+fn parse_macro_definition(lexer: &mut Lexer, macros: &mut MacroTable) -> Result<(), ParseError> {
+    let (line, column) = (lexer.current_line(), lexer.current_column());
+    let macro_error = |message| ParseError::Macro { line, column, message };
+    
+    let mut mac = Macro::from_header(lexer.rest_of_line()).map_err(macro_error)?;
+    lexer.next_raw_line(); // newline ending the header
+    
+    // Body runs up to the matching .endm (nested definitions are kept verbatim)
+    let mut nesting = 0;
+    loop {
+        let Some(raw) = lexer.next_raw_line() else {
+            return Err(macro_error(format!("Missing .endm for macro '{}'", mac.name)));
+        };
+        let text = raw.trim_start();
+        if starts_with_directive(text, ".macro") {
+            nesting += 1;
+        } else if starts_with_directive(text, ".endm") {
+            if nesting == 0 {
+                break;
             }
-            Token::Comment(_) => {
-                // Skip comments
-                continue;
+            nesting -= 1;
+        }
+        mac.body.push(raw.to_string());
+    }
+    
+    macros.define(mac).map_err(macro_error)
+}
+
+/// Expand a `.rept`/`.irp`/`.irpc` block (header already consumed up to the directive)
+/// Items keep spans on the body lines they came from, so every iteration of a
+/// body line is reported and listed at that line
+fn parse_repeat(
+    name: &str,
+    file: FileId,
+    lexer: &mut Lexer,
+    ctx: &mut ParseContext,
+    depth: usize,
+) -> Result<Vec<Spanned<Item>>, ParseError> {
+    let (line, column) = (lexer.current_line(), lexer.current_column());
+    let repeat_error = |message| ParseError::Repeat { line, column, message };
+    
+    let header = if name == "rept" {
+        parse_expr(lexer).and_then(|(expr, _)| {
+            let count = expr
+                .eval(&|symbol| ctx.constants.get(symbol).copied())
+                .map_err(|e| invalid_expression(lexer, e.to_string()))?;
+            usize::try_from(count).map_err(|_| repeat_error(format!("Negative .rept count {}", count)))
+        })
+        .map(Repeat::count)
+    } else {
+        Repeat::from_header(lexer.rest_of_line(), name == "irpc").map_err(repeat_error)
+    };
+    if lexer.current_line() == line {
+        lexer.rest_of_line(); // trailing comment
+        lexer.next_raw_line(); // newline ending the header
+    }
+    // With a bad header the body is still skipped as a whole
+    let (mut repeat, header_error) = match header {
+        Ok(repeat) => (repeat, None),
+        Err(error) => (Repeat::count(0), Some(error)),
+    };
+    
+    // Body runs up to the matching .endr; remember where each line starts
+    let body_line = lexer.current_line();
+    let mut body_starts = Vec::new();
+    let mut nesting = 0;
+    loop {
+        let start = lexer.offset();
+        let Some(raw) = lexer.next_raw_line() else {
+            return Err(repeat_error(format!("Missing .endr for .{}", name)));
+        };
+        let text = raw.trim_start();
+        if [".rept", ".irp", ".irpc"].iter().any(|d| starts_with_directive(text, d)) {
+            nesting += 1;
+        } else if starts_with_directive(text, ".endr") {
+            if nesting == 0 {
+                break;
             }
-            _ => {
-                return Err(ParseError::UnexpectedToken {
-                    line: 0,
-                    column: 0,
-                    message: format!("Unexpected token: {:?}", token),
-                });
+            nesting -= 1;
+        }
+        body_starts.push(start);
+        repeat.body.push(raw.to_string());
+    }
+    if let Some(error) = header_error {
+        return Err(error);
+    }
+    
+    let mut items = Vec::new();
+    for text in repeat.iterations() {
+        let first_error = ctx.errors.len();
+        let mut expanded = parse_items(&text, file, ctx, depth);
+        
+        // Map spans from the iteration text back onto the body lines
+        let iteration_starts: Vec<usize> = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        let map_span = |span: Span| {
+            if span.file != file {
+                return span;
+            }
+            let k = iteration_starts.partition_point(|&s| s <= span.start) - 1;
+            let body = &repeat.body[k];
+            let iteration_line = text[iteration_starts[k]..].split('\n').next().unwrap_or("");
+            if iteration_line == body && span.end <= iteration_starts[k] + body.len() {
+                let shift = body_starts[k] as isize - iteration_starts[k] as isize;
+                Span::new((span.start as isize + shift) as usize, (span.end as isize + shift) as usize).with_file(file)
+            } else {
+                // Substituted line: point at the whole body line
+                let indent = body.len() - body.trim_start().len();
+                Span::new(body_starts[k] + indent, body_starts[k] + body.trim_end().len()).with_file(file)
+            }
+        };
+        for item in &mut expanded {
+            item.span = map_span(item.span);
+            if let Item::Instruction(instr) = &mut item.node {
+                for operand in &mut instr.operands {
+                    operand.span = map_span(operand.span);
+                }
+            }
+        }
+        items.extend(expanded);
+        
+        // Report errors at the body line they came from
+        for error in ctx.take_errors(first_error) {
+            let relocated = match error.node.location() {
+                Some((l, c)) => error.node.relocate(body_line + l - 1, c),
+                None => error.node,
+            };
+            ctx.error(relocated, map_span(error.span));
+        }
+        if ctx.exiting_macro {
+            break;
+        }
+    }
+    
+    Ok(items)
+}
+
+fn starts_with_directive(text: &str, directive: &str) -> bool {
+    text.strip_prefix(directive)
+        .is_some_and(|rest| !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_' || c == '.'))
+}
+
+/// Expand a macro invocation; all resulting items point back at the invocation
+fn parse_macro_invocation(
+    name: &str,
+    name_span: Span,
+    lexer: &mut Lexer,
+    ctx: &mut ParseContext,
+    depth: usize,
+) -> Result<Vec<Spanned<Item>>, ParseError> {
+    let line = lexer.current_line();
+    let column = lexer.current_column() - name.chars().count();
+    
+    let args_start = lexer.offset();
+    let args = lexer.rest_of_line();
+    let span = name_span.merge(Span::new(name_span.start, args_start + strip_comment(args).trim_end().len()));
+    
+    if depth >= MAX_MACRO_DEPTH {
+        return Err(ParseError::Macro {
+            line,
+            column,
+            message: format!("Macro expansion nested too deeply (is '{}' recursive?)", name),
+        });
+    }
+    
+    let text = ctx
+        .macros
+        .expand(name, args)
+        .map_err(|message| ParseError::Macro { line, column, message })?;
+    let first_error = ctx.errors.len();
+    let mut items = parse_items(&text, span.file, ctx, depth + 1);
+    ctx.exiting_macro = false;
+    for error in ctx.take_errors(first_error) {
+        ctx.error(error.node.relocate(line, column), span);
+    }
+    
+    for item in &mut items {
+        item.span = span;
+        if let Item::Instruction(instr) = &mut item.node {
+            for operand in &mut instr.operands {
+                operand.span = span;
             }
         }
     }
     
-    Ok(Program { items })
+    Ok(items)
 }
 
 fn parse_directive(name: &str, lexer: &mut Lexer) -> Result<Directive, ParseError> {
@@ -86,18 +676,12 @@ fn parse_directive(name: &str, lexer: &mut Lexer) -> Result<Directive, ParseErro
             Ok(Directive::Section(section_name))
         }
         "align" => {
-            if let Some((_, Token::Integer(val), _)) = lexer.next_token() {
-                Ok(Directive::Align(val as u32))
-            } else {
-                Ok(Directive::Align(1))
-            }
+            let val = parse_const_arg(lexer, 1)?;
+            Ok(Directive::Align(val as u32))
         }
         "p2align" => {
-            if let Some((_, Token::Integer(val), _)) = lexer.next_token() {
-                Ok(Directive::P2align(val as u32))
-            } else {
-                Ok(Directive::P2align(0))
-            }
+            let val = parse_const_arg(lexer, 0)?;
+            Ok(Directive::P2align(val as u32))
         }
         "byte" => {
             let values = parse_data_values(lexer)?;
@@ -130,27 +714,16 @@ fn parse_directive(name: &str, lexer: &mut Lexer) -> Result<Directive, ParseErro
             Ok(Directive::Asciz(s))
         }
         "zero" => {
-            if let Some((_, Token::Integer(val), _)) = lexer.next_token() {
-                Ok(Directive::Zero(val))
-            } else {
-                Ok(Directive::Zero(0))
-            }
+            let val = parse_const_arg(lexer, 0)?;
+            Ok(Directive::Zero(val))
         }
         "skip" => {
-            if let Some((_, Token::Integer(val), _)) = lexer.next_token() {
-                Ok(Directive::Skip(val))
-            } else {
-                Ok(Directive::Skip(0))
-            }
+            let val = parse_const_arg(lexer, 0)?;
+            Ok(Directive::Skip(val))
         }
         "org" => {
-            if let Some((_, Token::Integer(val), _)) = lexer.next_token() {
-                Ok(Directive::Org(val))
-            } else if let Some((_, Token::HexInteger(val), _)) = lexer.next_token() {
-                Ok(Directive::Org(val as i64))
-            } else {
-                Ok(Directive::Org(0))
-            }
+            let val = parse_const_arg(lexer, 0)?;
+            Ok(Directive::Org(val))
         }
         "file" => {
             let content = consume_rest_of_line_as_string(lexer);
@@ -193,38 +766,49 @@ fn parse_directive(name: &str, lexer: &mut Lexer) -> Result<Directive, ParseErro
             Ok(Directive::Type(sym_name, sym_type))
         }
         "size" => {
-            // Parse "name, expr" - consume everything until newline
-            let mut sym_name = String::new();
-            let mut size_expr = String::new();
-            loop {
-                match lexer.peek_token() {
-                    None | Some((_, Token::Newline, _)) | Some((_, Token::Comment(_), _)) => break,
-                    Some((_, Token::Identifier(ref s), _)) => {
-                        lexer.next_token();
-                        if sym_name.is_empty() {
-                            sym_name = s.clone();
-                        } else if size_expr.is_empty() {
-                            size_expr = s.clone();
-                        }
-                    }
-                    Some((_, Token::Directive(ref s), _)) => {
-                        lexer.next_token();
-                        if sym_name.is_empty() {
-                            sym_name = format!(".{}", s);
-                        }
-                    }
-                    Some((_, Token::Integer(n), _)) => {
-                        lexer.next_token();
-                        if size_expr.is_empty() {
-                            size_expr = n.to_string();
-                        }
-                    }
-                    _ => {
-                        lexer.next_token(); // consume commas, minus signs, etc.
-                    }
+            // Parse "name, expr" (e.g. ".size foo, .Lfunc_end0-foo")
+            let sym_name = match lexer.peek_token() {
+                Some((_, Token::Identifier(name), _)) => {
+                    lexer.next_token();
+                    name
+                }
+                Some((_, Token::Directive(name), _)) => {
+                    lexer.next_token();
+                    format!(".{}", name)
                 }
+                _ => String::new(),
+            };
+            if let Some((_, Token::Comma, _)) = lexer.peek_token() {
+                lexer.next_token();
             }
-            Ok(Directive::Size(sym_name, size_expr))
+            let (size, _) = parse_expr(lexer)?;
+            Ok(Directive::Size(sym_name, size))
+        }
+        "equ" | "set" => {
+            // Parse "name, expr"; unlike .equ, .set may assign the name again
+            let sym_name = match lexer.next_token() {
+                Some((_, Token::Identifier(name), _)) => name,
+                Some((_, Token::Directive(name), _)) => format!(".{}", name),
+                _ => {
+                    return Err(ParseError::UnexpectedToken {
+                        line: lexer.current_line(),
+                        column: lexer.current_column(),
+                        message: format!("Expected symbol name after .{}", name),
+                    });
+                }
+            };
+            match lexer.next_token() {
+                Some((_, Token::Comma, _)) => {}
+                _ => {
+                    return Err(ParseError::UnexpectedToken {
+                        line: lexer.current_line(),
+                        column: lexer.current_column(),
+                        message: format!("Expected ',' after symbol name in .{}", name),
+                    });
+                }
+            }
+            let (expr, _) = parse_expr(lexer)?;
+            Ok(Directive::Equ(sym_name, fold_expr(expr, lexer)?, name == "set"))
         }
         "addrsig" => {
             // .addrsig has no arguments, just consume rest of line
@@ -248,56 +832,35 @@ fn parse_directive(name: &str, lexer: &mut Lexer) -> Result<Directive, ParseErro
                 }
             }
         }
-        _ => {
-            // Unknown directive - check if it looks like a label
-            // (starts with uppercase like .LBB or .L.str.1)
-            if name.chars().next().map_or(false, |c| c.is_uppercase()) {
-                // It's actually a local label, not a directive
-                // Return a placeholder, the caller should handle this
-                consume_rest_of_line(lexer);
-                Ok(Directive::Text) // This case should be handled specially by caller
-            } else {
-                // Unknown directive - consume rest of line
-                consume_rest_of_line(lexer);
-                Ok(Directive::Text)
-            }
-        }
+        _ => Ok(parse_unknown_directive(name, lexer)),
     }
 }
 
-/// Parse data values (integers or label references)
+/// Keep a directive the assembler does not know, with its arguments as written
+fn parse_unknown_directive(name: &str, lexer: &mut Lexer) -> Directive {
+    let args = strip_comment(lexer.rest_of_line()).trim().to_string();
+    Directive::Unknown { name: name.to_string(), args }
+}
+
+/// Parse data values (integers, label references or expressions)
 fn parse_data_values(lexer: &mut Lexer) -> Result<Vec<DataValue>, ParseError> {
     let mut values = Vec::new();
     
     loop {
         match lexer.peek_token() {
             None | Some((_, Token::Newline, _)) | Some((_, Token::Comment(_), _)) => break,
-            Some((_, Token::Integer(n), _)) => {
-                lexer.next_token();
-                values.push(DataValue::Immediate(n));
-            }
-            Some((_, Token::HexInteger(n), _)) => {
-                lexer.next_token();
-                values.push(DataValue::Immediate(n as i64));
-            }
-            Some((_, Token::Identifier(ref label), _)) => {
-                let label = label.clone();
-                lexer.next_token();
-                values.push(DataValue::Label(label));
-            }
-            Some((_, Token::Directive(ref label), _)) => {
-                // Handle labels like .L.str.1
-                let label = format!(".{}", label);
-                lexer.next_token();
-                values.push(DataValue::Label(label));
-            }
             Some((_, Token::Comma, _)) => {
                 lexer.next_token();
                 continue;
             }
             _ => {
-                // Skip unknown tokens
-                lexer.next_token();
+                let (expr, _) = parse_expr(lexer)?;
+                let value = match fold_expr(expr, lexer)? {
+                    Expr::Number(n) => DataValue::Immediate(n),
+                    Expr::Symbol(label) => DataValue::Label(label),
+                    expr => DataValue::Expr(expr),
+                };
+                values.push(value);
             }
         }
     }
@@ -305,6 +868,163 @@ fn parse_data_values(lexer: &mut Lexer) -> Result<Vec<DataValue>, ParseError> {
     Ok(values)
 }
 
+/// Parse a directive argument that must be a constant, or `default` if absent
+fn parse_const_arg(lexer: &mut Lexer, default: i64) -> Result<i64, ParseError> {
+    match lexer.peek_token() {
+        None | Some((_, Token::Newline, _)) | Some((_, Token::Comment(_), _)) => Ok(default),
+        _ => {
+            let (expr, _) = parse_expr(lexer)?;
+            expr.eval_const().map_err(|e| invalid_expression(lexer, e.to_string()))
+        }
+    }
+}
+
+/// Parse a constant expression, returning it together with its source span
+fn parse_expr(lexer: &mut Lexer) -> Result<(Expr, Span), ParseError> {
+    parse_binary_expr(lexer, 0)
+}
+
+/// Precedence climbing over binary operators
+fn parse_binary_expr(lexer: &mut Lexer, min_precedence: u8) -> Result<(Expr, Span), ParseError> {
+    let (mut lhs, mut span) = parse_unary_expr(lexer)?;
+    
+    while let Some(op) = peek_binary_op(lexer) {
+        if op.precedence() < min_precedence {
+            break;
+        }
+        lexer.next_token();
+        let (rhs, rhs_span) = parse_binary_expr(lexer, op.precedence() + 1)?;
+        lhs = Expr::binary(op, lhs, rhs);
+        span = span.merge(rhs_span);
+    }
+    
+    Ok((lhs, span))
+}
+
+fn parse_unary_expr(lexer: &mut Lexer) -> Result<(Expr, Span), ParseError> {
+    // Leave the newline unconsumed so the error stays on this line
+    if let None | Some((_, Token::Newline | Token::Comment(_), _)) = lexer.peek_token() {
+        return Err(invalid_expression(lexer, "Expected expression, found end of line".to_string()));
+    }
+    match lexer.next_token() {
+        Some((start, Token::Minus, _)) => {
+            let (operand, span) = parse_unary_expr(lexer)?;
+            Ok((Expr::unary(UnaryOp::Neg, operand), Span::new(start, span.end)))
+        }
+        Some((start, Token::Tilde, _)) => {
+            let (operand, span) = parse_unary_expr(lexer)?;
+            Ok((Expr::unary(UnaryOp::Not, operand), Span::new(start, span.end)))
+        }
+        Some((start, Token::Bang, _)) => {
+            let (operand, span) = parse_unary_expr(lexer)?;
+            Ok((Expr::unary(UnaryOp::LogicalNot, operand), Span::new(start, span.end)))
+        }
+        Some((start, Token::Plus, _)) => {
+            let (operand, span) = parse_unary_expr(lexer)?;
+            Ok((operand, Span::new(start, span.end)))
+        }
+        Some((start, Token::LParen, _)) => {
+            let (inner, _) = parse_expr(lexer)?;
+            match lexer.peek_token() {
+                Some((_, Token::RParen, end)) => {
+                    lexer.next_token();
+                    Ok((inner, Span::new(start, end)))
+                }
+                _ => Err(invalid_expression(lexer, "Expected ')' in expression".to_string())),
+            }
+        }
+        Some((start, Token::Integer(n), end)) => Ok((Expr::Number(n), Span::new(start, end))),
+        Some((start, Token::HexInteger(n), end)) => Ok((Expr::Number(n as i64), Span::new(start, end))),
+        // Inside an expression every name is a symbol, even if it spells a
+        // register (e.g. the C function `sys_key_count`)
+        Some((start, Token::Identifier(name), end)) => Ok((Expr::Symbol(name), Span::new(start, end))),
+        Some((start, Token::Directive(name), end)) => {
+            // Labels like .L.str.1 are lexed as directives
+            Ok((Expr::Symbol(format!(".{}", name)), Span::new(start, end)))
+        }
+        Some((_, token, _)) => Err(ParseError::UnexpectedToken {
+            line: lexer.current_line(),
+            column: lexer.current_column(),
+            message: format!("Expected expression, found {}", token),
+        }),
+        None => Err(ParseError::UnexpectedEof),
+    }
+}
+
+fn peek_binary_op(lexer: &mut Lexer) -> Option<BinaryOp> {
+    match lexer.peek_token()?.1 {
+        Token::Star => Some(BinaryOp::Mul),
+        Token::Slash => Some(BinaryOp::Div),
+        Token::Percent => Some(BinaryOp::Mod),
+        Token::Plus => Some(BinaryOp::Add),
+        Token::Minus => Some(BinaryOp::Sub),
+        Token::Shl => Some(BinaryOp::Shl),
+        Token::Shr => Some(BinaryOp::Shr),
+        Token::Amp => Some(BinaryOp::And),
+        Token::Caret => Some(BinaryOp::Xor),
+        Token::Pipe => Some(BinaryOp::Or),
+        Token::Lt => Some(BinaryOp::Lt),
+        Token::Le => Some(BinaryOp::Le),
+        Token::Gt => Some(BinaryOp::Gt),
+        Token::Ge => Some(BinaryOp::Ge),
+        Token::EqEq => Some(BinaryOp::Eq),
+        Token::NotEq => Some(BinaryOp::Ne),
+        Token::AndAnd => Some(BinaryOp::LogicalAnd),
+        Token::OrOr => Some(BinaryOp::LogicalOr),
+        _ => None,
+    }
+}
+
+/// Whether the next tokens are an identifier followed by `(`
+fn is_call(lexer: &Lexer) -> bool {
+    let mut ahead = lexer.clone();
+    ahead.next_token();
+    matches!(ahead.next_token(), Some((_, Token::LParen, _)))
+}
+
+/// Parse `(r, g, b)` after `rgb`, returning the colour and the end of the closing parenthesis
+/// Each component must be a constant from 0 to 255
+fn parse_rgb(lexer: &mut Lexer) -> Result<(Color, usize), ParseError> {
+    lexer.next_token(); // consume '('
+    let mut components = [0u8; 3];
+    for (i, component) in components.iter_mut().enumerate() {
+        if i > 0 && !matches!(lexer.next_token(), Some((_, Token::Comma, _))) {
+            return Err(invalid_expression(lexer, "rgb() takes three components: rgb(r, g, b)".to_string()));
+        }
+        let (expr, _) = parse_expr(lexer)?;
+        *component = match fold_expr(expr, lexer)? {
+            Expr::Number(n) if (0..=255).contains(&n) => n as u8,
+            Expr::Number(n) => {
+                return Err(invalid_expression(lexer, format!("rgb() component {} is out of range (0-255)", n)));
+            }
+            _ => return Err(invalid_expression(lexer, "rgb() components must be constants".to_string())),
+        };
+    }
+    match lexer.next_token() {
+        Some((_, Token::RParen, end)) => Ok((Color::new(components[0], components[1], components[2]), end)),
+        _ => Err(invalid_expression(lexer, "rgb() takes three components: rgb(r, g, b)".to_string())),
+    }
+}
+
+/// Fold an expression without symbol references into a number.
+/// Expressions over labels are kept for the assembler to evaluate after layout.
+fn fold_expr(expr: Expr, lexer: &Lexer) -> Result<Expr, ParseError> {
+    if !expr.symbols().is_empty() {
+        return Ok(expr);
+    }
+    expr.eval_const()
+        .map(Expr::Number)
+        .map_err(|e| invalid_expression(lexer, e.to_string()))
+}
+
+fn invalid_expression(lexer: &Lexer, message: String) -> ParseError {
+    ParseError::InvalidExpression {
+        line: lexer.current_line(),
+        column: lexer.current_column(),
+        message,
+    }
+}
+
 /// Parse a string value (handles escape sequences)
 fn parse_string_value(lexer: &mut Lexer) -> Result<String, ParseError> {
     match lexer.peek_token() {
@@ -436,32 +1156,40 @@ fn parse_instruction(mnemonic_str: &str, lexer: &mut Lexer) -> Result<Instructio
             None | Some((_, Token::Newline, _)) | Some((_, Token::Comment(_), _)) => {
                 break;
             }
-            Some((_start, _, _)) => {
-                if let Some((s, tok, e)) = lexer.next_token() {
-                    match tok {
-                        Token::Identifier(id) => {
-                            // Try to parse as register first (handles *, R, V, GL_, SYS_, etc.)
-                            let operand = if let Some(reg) = Register::from_str(&id) {
-                                Operand::Register(reg)
-                            } else {
-                                // It's a label (can start with . for local labels)
-                                Operand::Label(id)
-                            };
-                            operands.push(Spanned::new(operand, Span::new(s, e)));
-                        }
-                        Token::Integer(n) => {
-                            operands.push(Spanned::new(Operand::Immediate(n), Span::new(s, e)));
-                        }
-                        Token::HexInteger(n) => {
-                            operands.push(Spanned::new(Operand::Immediate(n as i64), Span::new(s, e)));
-                        }
-                        Token::Comma => {
-                            // Skip commas
-                            continue;
-                        }
-                        _ => break,
-                    }
+            Some((_, Token::Comma, _)) => {
+                // Skip commas
+                lexer.next_token();
+            }
+            Some((s, Token::Identifier(id), e)) if Register::from_str(&id).is_some() => {
+                // Registers (handles *, R, V, GL_, SYS_, etc.)
+                lexer.next_token();
+                if peek_binary_op(lexer).is_some() {
+                    return Err(invalid_expression(
+                        lexer,
+                        format!("Register '{}' cannot be used in an expression", id),
+                    ));
                 }
+                let reg = Register::from_str(&id).unwrap();
+                operands.push(Spanned::new(Operand::Register(reg), Span::new(s, e)));
+            }
+            Some((s, Token::Color(color), e)) => {
+                lexer.next_token();
+                operands.push(Spanned::new(Operand::Color(color), Span::new(s, e)));
+            }
+            Some((s, Token::Identifier(id), _)) if id.eq_ignore_ascii_case("rgb") && is_call(lexer) => {
+                lexer.next_token();
+                let (color, e) = parse_rgb(lexer)?;
+                operands.push(Spanned::new(Operand::Color(color), Span::new(s, e)));
+            }
+            Some(_) => {
+                // Immediate, label (can start with . for local labels) or expression
+                let (expr, span) = parse_expr(lexer)?;
+                let operand = match fold_expr(expr, lexer)? {
+                    Expr::Number(n) => Operand::Immediate(n),
+                    Expr::Symbol(label) => Operand::Label(label),
+                    expr => Operand::Expr(expr),
+                };
+                operands.push(Spanned::new(operand, span));
             }
         }
     }
@@ -0,0 +1,93 @@
+/// Repetition blocks (`.rept`, `.irp`, `.irpc` ... `.endr`)
+///
+/// Like macros, blocks are expanded textually: every iteration substitutes
+/// `\param` (and `\()`) in the raw body lines. Substitution never adds or
+/// removes lines, so line N of an iteration always comes from body line N.
+use crate::macros::{is_symbol_name, split_args, strip_comment, substitute};
+
+/// A repetition block
+#[derive(Debug, Clone, PartialEq)]
+pub struct Repeat {
+    /// Parameter substituted in the body (`None` for `.rept`)
+    pub param: Option<String>,
+    /// Value of the parameter for each iteration
+    pub values: Vec<String>,
+    pub body: Vec<String>,
+}
+
+impl Repeat {
+    /// `.rept count`
+    pub fn count(count: usize) -> Self {
+        Self {
+            param: None,
+            values: vec![String::new(); count],
+            body: Vec::new(),
+        }
+    }
+
+    /// Parse the header of `.irp param, values...` (or `.irpc param, chars`
+    /// when `per_char` is set), i.e. the text after the directive
+    pub fn from_header(header: &str, per_char: bool) -> Result<Self, String> {
+        let directive = if per_char { ".irpc" } else { ".irp" };
+        let header = strip_comment(header).trim();
+        let (param, values) = header.split_once(',').unwrap_or((header, ""));
+        let param = param.trim();
+        if !is_symbol_name(param) {
+            return Err(format!("Invalid parameter '{}' in {}", param, directive));
+        }
+
+        let values: Vec<String> = if per_char {
+            values.trim().chars().map(String::from).collect()
+        } else {
+            split_args(values).into_iter().map(String::from).collect()
+        };
+
+        Ok(Self {
+            param: Some(param.to_string()),
+            // Without values the body is assembled once with an empty parameter (as in GNU as)
+            values: if values.is_empty() { vec![String::new()] } else { values },
+            body: Vec::new(),
+        })
+    }
+
+    /// Source text of each iteration
+    pub fn iterations(&self) -> impl Iterator<Item = String> + '_ {
+        self.values.iter().map(move |value| {
+            let value_of = |name: &str| (self.param.as_deref() == Some(name)).then_some(value.as_str());
+            let mut text = String::new();
+            for line in &self.body {
+                text.push_str(&substitute(line, value_of, None));
+                text.push('\n');
+            }
+            text
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_irp_expansion() {
+        let mut irp = Repeat::from_header("key, 4, 5, (2*3) ; keycodes", false).unwrap();
+        irp.body = vec!["    PRESS_GK \\key".to_string()];
+        let text: Vec<String> = irp.iterations().collect();
+        assert_eq!(text, vec!["    PRESS_GK 4\n", "    PRESS_GK 5\n", "    PRESS_GK (2*3)\n"]);
+
+        let mut rept = Repeat::count(2);
+        rept.body = vec!["NOP".to_string(), ".Lx\\@:".to_string()];
+        assert_eq!(rept.iterations().collect::<String>(), "NOP\n.Lx\\@:\nNOP\n.Lx\\@:\n");
+    }
+
+    #[test]
+    fn test_irpc_expansion() {
+        let mut irpc = Repeat::from_header("c, 0x1", true).unwrap();
+        irpc.body = vec![".byte \\c".to_string()];
+        assert_eq!(irpc.iterations().collect::<String>(), ".byte 0\n.byte x\n.byte 1\n");
+
+        let empty = Repeat::from_header("c", true).unwrap();
+        assert_eq!(empty.values, vec![""]);
+        assert!(Repeat::from_header("1bad, a", false).is_err());
+    }
+}
@@ -66,3 +66,412 @@ fn test_parse_press_release() {
     let result = parse(input);
     assert!(result.is_ok(), "Failed to parse: {:?}", result.err());
 }
+
+#[test]
+fn test_parse_expressions() {
+    use sayo_ast::{DataValue, Directive, Item, Operand};
+
+    let input = r#"
+foo:
+    MOV16 R0, table+4
+    LED_COL (0xFF << 16) | 0x80
+    MOV R0, *R1
+    SJMP -50
+.Lfunc_end0:
+    .size foo, .Lfunc_end0-foo
+table:
+    .byte 1+2, table>>8
+"#;
+    let program = parse(input).expect("Failed to parse expressions");
+    let instrs: Vec<_> = program.items.iter().filter_map(|item| match &item.node {
+        Item::Instruction(instr) => Some(instr),
+        _ => None,
+    }).collect();
+
+    assert!(matches!(&instrs[0].operands[1].node, Operand::Expr(e) if e.to_string() == "table+4"));
+    assert!(matches!(instrs[1].operands[0].node, Operand::Immediate(0xFF0080)));
+    assert!(matches!(instrs[2].operands[1].node, Operand::Register(_)));
+    assert!(matches!(instrs[3].operands[0].node, Operand::Immediate(-50)));
+
+    let directives: Vec<_> = program.items.iter().filter_map(|item| match &item.node {
+        Item::Directive(dir) => Some(dir),
+        _ => None,
+    }).collect();
+    assert!(matches!(directives[0], Directive::Size(name, e) if name == "foo" && e.to_string() == ".Lfunc_end0-foo"));
+    match directives[1] {
+        Directive::Byte(values) => {
+            assert!(matches!(values[0], DataValue::Immediate(3)));
+            assert!(matches!(&values[1], DataValue::Expr(e) if e.to_string() == "table>>8"));
+        }
+        other => panic!("Expected .byte, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_constants() {
+    use sayo_ast::{Directive, Expr, Item};
+
+    let input = r#"
+    .equ KEY_A, 4
+    .set DELAY, 10 * 2
+    LED_COUNT = KEY_A + 60
+"#;
+    let program = parse(input).expect("Failed to parse constants");
+    let constants: Vec<_> = program.items.iter().filter_map(|item| match &item.node {
+        Item::Directive(Directive::Equ(name, expr, _)) => Some((name.as_str(), expr)),
+        _ => None,
+    }).collect();
+
+    assert_eq!(constants.len(), 3);
+    assert_eq!(constants[0], ("KEY_A", &Expr::Number(4)));
+    assert_eq!(constants[1], ("DELAY", &Expr::Number(20)));
+    assert_eq!(constants[2].0, "LED_COUNT");
+    assert_eq!(constants[2].1.to_string(), "KEY_A+60");
+}
+
+#[test]
+fn test_parse_macros() {
+    use sayo_ast::{Item, Mnemonic, Operand};
+
+    let input = r#"
+    .macro tap key, delay=10
+    PRESS_GK \key
+.Lwait\@:
+    SLEEP \delay
+    RELEASE_GK \key
+    .endm
+main:
+    tap R1
+    tap R2, 20
+"#;
+    let program = parse(input).expect("Failed to parse macros");
+    let invocation = input.find("tap R2, 20").unwrap();
+
+    let labels: Vec<_> = program.items.iter().filter_map(|item| match &item.node {
+        Item::Label(name) => Some(name.as_str()),
+        _ => None,
+    }).collect();
+    assert_eq!(labels, vec!["main", ".Lwait0", ".Lwait1"]);
+
+    let second: Vec<_> = program.items.iter().filter(|item| item.span.start == invocation).collect();
+    assert_eq!(second.len(), 4);
+    assert!(second.iter().all(|item| item.span.end == invocation + "tap R2, 20".len()));
+    match &second[2].node {
+        Item::Instruction(instr) => {
+            assert_eq!(instr.mnemonic, Mnemonic::SLEEP);
+            assert!(matches!(instr.operands[0].node, Operand::Immediate(20)));
+        }
+        other => panic!("Expected SLEEP, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_macro_exitm_in_conditional() {
+    use sayo_ast::{Item, Mnemonic};
+
+    let input = r#"
+    .macro sleep_n n
+    .if \n == 0
+    .exitm
+    .endif
+    SLEEP \n
+    .endm
+main:
+    sleep_n 0
+    sleep_n 5
+    EXIT
+"#;
+    let program = parse(input).expect("Failed to parse .exitm inside .if");
+    let mnemonics: Vec<_> = program.items.iter().filter_map(|item| match &item.node {
+        Item::Instruction(instr) => Some(instr.mnemonic),
+        _ => None,
+    }).collect();
+    assert_eq!(mnemonics, vec![Mnemonic::SLEEP, Mnemonic::EXIT]);
+
+    // Outside of a macro it is still an error
+    assert!(parse("main:\n    .exitm\n").is_err());
+}
+
+#[test]
+fn test_macro_errors() {
+    let unterminated = ".macro foo\n    NOP\n";
+    assert!(parse(unterminated).is_err());
+
+    let recursive = ".macro loop\n    loop\n.endm\n    loop\n";
+    assert!(parse(recursive).is_err());
+
+    // Errors inside an expansion are reported at the invocation line
+    let bad_body = ".macro bad\n    BOGUS R0\n.endm\n\n    bad\n";
+    match parse(bad_body) {
+        Err(sayo_parser::ParseError::InvalidToken { line, .. }) => assert_eq!(line, 5),
+        other => panic!("Expected invalid token error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_includes() {
+    use sayo_ast::{FileId, Item, SourceMap};
+    use sayo_parser::ParseOptions;
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("sayo_include_test_{}", std::process::id()));
+    let lib_dir = dir.join("lib");
+    fs::create_dir_all(&lib_dir).unwrap();
+    fs::write(lib_dir.join("keys.s"), "KEY_A = 4\n.include \"delays.s\"\n").unwrap();
+    fs::write(lib_dir.join("delays.s"), "DELAY = 10\n").unwrap();
+    fs::write(dir.join("loop_a.s"), ".include \"loop_b.s\"\n").unwrap();
+    fs::write(dir.join("loop_b.s"), ".include \"loop_a.s\"\n").unwrap();
+
+    let main = dir.join("main.s");
+    let source = ".include \"keys.s\"\nmain:\n    SLEEP DELAY\n";
+    let options = ParseOptions { include_paths: vec![lib_dir.clone()], ..Default::default() };
+    let mut sources = SourceMap::with_root(&main, source);
+    let program = sayo_parser::parse_sources(&mut sources, &options).into_result().expect("Failed to parse includes");
+
+    // keys.s is found through the search path, delays.s next to keys.s
+    assert_eq!(sources.len(), 3);
+    let delay = program.items.iter().find(|item| matches!(&item.node, Item::Directive(sayo_ast::Directive::Equ(name, ..)) if name == "DELAY")).unwrap();
+    assert_eq!(sources.path(delay.span.file), lib_dir.join("delays.s"));
+    assert_eq!(sources.root_span(delay.span).file, FileId::ROOT);
+    assert!(program.items.iter().any(|item| item.span.file == FileId::ROOT && matches!(item.node, Item::Instruction(_))));
+
+    // Missing search path
+    let mut sources = SourceMap::with_root(&main, source);
+    assert!(!sayo_parser::parse_sources(&mut sources, &ParseOptions::default()).is_ok());
+
+    // Include cycle
+    let mut sources = SourceMap::with_root(dir.join("loop_a.s"), ".include \"loop_b.s\"\n");
+    match sayo_parser::parse_sources(&mut sources, &ParseOptions::default()).into_result() {
+        Err(err) => assert!(err.to_string().contains("Include cycle")),
+        Ok(_) => panic!("Expected include cycle error"),
+    }
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_conditional_assembly() {
+    use sayo_ast::{Directive, Expr, Item, SourceMap};
+    use sayo_parser::ParseOptions;
+
+    let source = r#"
+.ifndef KEY_COUNT
+KEY_COUNT = 4
+.endif
+.if KEY_COUNT > 8
+    .byte 1
+.elseif KEY_COUNT == 4
+    .byte 2
+    .if 0
+        BOGUS R0
+    .endif
+.else
+    .byte 3
+.endif
+"#;
+    let bytes = |program: &sayo_ast::Program| -> Vec<i64> {
+        program.items.iter().filter_map(|item| match &item.node {
+            Item::Directive(Directive::Byte(values)) => match values[0] {
+                sayo_ast::DataValue::Immediate(n) => Some(n),
+                _ => None,
+            },
+            _ => None,
+        }).collect()
+    };
+
+    let program = parse(source).expect("Failed to parse conditionals");
+    assert_eq!(bytes(&program), vec![2]);
+
+    // -D defines are parsed first and become ordinary constants
+    let options = ParseOptions { defines: vec![("KEY_COUNT".to_string(), 16)], ..Default::default() };
+    let mut sources = SourceMap::with_root("main.s", source);
+    let program = sayo_parser::parse_sources(&mut sources, &options).into_result().expect("Failed to parse with defines");
+    assert_eq!(bytes(&program), vec![1]);
+    assert!(matches!(&program.items[0].node, Item::Directive(Directive::Equ(name, Expr::Number(16), _)) if name == "KEY_COUNT"));
+    assert_eq!(sources.len(), 2);
+
+    // Structure errors
+    assert!(parse(".if 1\n    NOP\n").is_err());
+    assert!(parse(".endif\n").is_err());
+    assert!(parse(".if 1\n.else\n.else\n.endif\n").is_err());
+    assert!(parse(".if UNDEFINED\n.endif\n").is_err());
+}
+
+#[test]
+fn test_parse_repetition() {
+    use sayo_ast::{DataValue, Directive, Item};
+
+    let source = "COUNT = 2\n.rept COUNT\n    NOP\n.endr\n.irp key, 4, 5\n    .byte \\key\n.endr\n.irpc c, 12\n    .rept 2\n    .byte \\c\n    .endr\n.endr\n";
+    let program = parse(source).expect("Failed to parse repetition blocks");
+
+    let nops: Vec<_> = program.items.iter().filter(|item| matches!(item.node, Item::Instruction(_))).collect();
+    assert_eq!(nops.len(), 2);
+    // Unchanged body lines keep their exact span
+    assert!(nops.iter().all(|item| &source[item.span.start..item.span.end] == "NOP"));
+
+    let bytes: Vec<(i64, &str)> = program.items.iter().filter_map(|item| match &item.node {
+        Item::Directive(Directive::Byte(values)) => match values[0] {
+            DataValue::Immediate(n) => Some((n, &source[item.span.start..item.span.end])),
+            _ => None,
+        },
+        _ => None,
+    }).collect();
+    // Substituted lines point at the whole body line
+    assert_eq!(bytes, vec![(4, ".byte \\key"), (5, ".byte \\key"), (1, ".byte \\c"), (1, ".byte \\c"), (2, ".byte \\c"), (2, ".byte \\c")]);
+
+    assert!(parse(".rept 2\n    NOP\n").is_err());
+    assert!(parse(".endr\n").is_err());
+    assert!(parse(".rept -1\n.endr\n").is_err());
+
+    // Errors are reported at the body line
+    match parse("\n.rept 2\n    BOGUS\n.endr\n") {
+        Err(sayo_parser::ParseError::InvalidToken { line, .. }) => assert_eq!(line, 3),
+        other => panic!("Expected invalid token error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_error_recovery() {
+    use sayo_ast::{Item, SourceMap};
+    use sayo_parser::{ParseError, ParseOptions};
+
+    let source = "main:\n    BOGUS R0\n    NOP\n    .byte 1 +\n    , ; stray comma\n.endif\n.macro m\n    FOO\n.endm\n    m\n    RET\n";
+    let mut sources = SourceMap::with_root("main.s", source);
+    let output = sayo_parser::parse_sources(&mut sources, &ParseOptions::default());
+
+    // Every bad line is reported, with its own line and span
+    let lines: Vec<usize> = output.errors.iter().map(|e| e.node.location().unwrap().0).collect();
+    assert_eq!(lines, vec![2, 4, 5, 6, 10]);
+    let texts: Vec<&str> = output.errors.iter().map(|e| &source[e.span.start..e.span.end]).collect();
+    assert_eq!(texts, vec!["BOGUS R0", ".byte 1 +", ",", ".endif", "m"]);
+    assert!(matches!(output.errors[2].node, ParseError::UnexpectedToken { .. }));
+
+    // The statements around them still parse
+    let instructions = output.program.items.iter().filter(|item| matches!(item.node, Item::Instruction(_))).count();
+    assert_eq!(instructions, 2);
+    assert!(parse(source).is_err());
+}
+
+#[test]
+fn test_numeric_literals() {
+    use sayo_ast::{DataValue, Directive, Item, SourceMap};
+    use sayo_parser::{ParseError, ParseOptions};
+
+    let program = parse(".byte 0b1010, 0o17, 'A', '\\n'\n.long 1_000_000\n").expect("Failed to parse literals");
+    let values: Vec<&DataValue> = program.items.iter().flat_map(|item| match &item.node {
+        Item::Directive(Directive::Byte(values)) | Item::Directive(Directive::Long(values)) => values.iter(),
+        _ => [].iter(),
+    }).collect();
+    let expected = [10, 15, 65, 10, 1_000_000].map(DataValue::Immediate);
+    assert_eq!(values, expected.iter().collect::<Vec<_>>());
+
+    // Malformed literals are errors at the literal, not silent zeros
+    let source = "main:\n    SLEEP 0xZZ\n    .byte 1, 99999999999999999999\n";
+    let mut sources = SourceMap::with_root("main.s", source);
+    let output = sayo_parser::parse_sources(&mut sources, &ParseOptions::default());
+    let errors: Vec<(&str, usize)> = output.errors.iter().map(|e| match &e.node {
+        ParseError::InvalidLiteral { line, .. } => (&source[e.span.start..e.span.end], *line),
+        other => panic!("Expected invalid literal error, got {:?}", other),
+    }).collect();
+    assert_eq!(errors, vec![("0xZZ", 2), ("99999999999999999999", 3)]);
+}
+
+#[test]
+fn test_unknown_directives() {
+    use sayo_ast::{Directive, Item};
+
+    let program = parse(".text\n.cfi_startproc\n.weak foo ; comment\nmain:\n    .rodata\n    RET\n")
+        .expect("Unknown directives should parse");
+    let unknown: Vec<(&str, &str)> = program.items.iter().filter_map(|item| match &item.node {
+        Item::Directive(Directive::Unknown { name, args }) => Some((name.as_str(), args.as_str())),
+        _ => None,
+    }).collect();
+    assert_eq!(unknown, vec![("cfi_startproc", ""), ("weak", "foo"), ("rodata", "")]);
+
+    // Only the explicit .text switches sections
+    let sections = program.items.iter()
+        .filter(|item| matches!(item.node, Item::Directive(Directive::Text | Directive::Data)))
+        .count();
+    assert_eq!(sections, 1);
+}
+
+#[test]
+fn test_cst_round_trip() {
+    use sayo_parser::{ParseOptions, SyntaxTree};
+
+    let source = include_str!("../../../vs_test/main.s");
+    let tree = SyntaxTree::parse(source);
+    assert_eq!(tree.to_string(), source);
+
+    let lowered = tree.lower(&ParseOptions::default()).into_result().expect("Failed to lower CST");
+    assert_eq!(lowered, parse(source).expect("Failed to parse"));
+}
+
+#[test]
+fn test_numeric_local_labels() {
+    use sayo_ast::{Item, Operand};
+
+    let program = parse("main:\n1:  SJMP 1b\n    JZ R0, 2f\n2:\n    RET\n").expect("Failed to parse numeric labels");
+    let labels: Vec<&str> = program.items.iter().filter_map(|item| match &item.node {
+        Item::Label(name) => Some(name.as_str()),
+        _ => None,
+    }).collect();
+    assert_eq!(labels, vec!["main", "1", "2"]);
+
+    let targets: Vec<&str> = program.items.iter().filter_map(|item| match &item.node {
+        Item::Instruction(instr) => instr.operands.iter().find_map(|op| match &op.node {
+            Operand::Label(name) => Some(name.as_str()),
+            _ => None,
+        }),
+        _ => None,
+    }).collect();
+    assert_eq!(targets, vec!["1b", "2f"]);
+
+    // 0b1010 is still a binary literal
+    assert!(parse("SLEEP 0b1010\n").is_ok());
+}
+
+#[test]
+fn test_hid_code_names() {
+    use sayo_ast::{HidCode, HidNamespace, Item, Operand};
+
+    let program = parse("KEY_B = 7\nmain:\n    PRESS_GK KEY_A\n    PRESS_SK mod_lshift\n    PRESS_GK KEY_B\n    RET\n")
+        .expect("Failed to parse HID names");
+    let operands: Vec<&Operand> = program.items.iter().filter_map(|item| match &item.node {
+        Item::Instruction(instr) => instr.operands.first().map(|op| &op.node),
+        _ => None,
+    }).collect();
+    assert_eq!(operands, vec![
+        &Operand::Hid(HidCode { namespace: HidNamespace::Key, code: 0x04 }),
+        &Operand::Hid(HidCode { namespace: HidNamespace::Modifier, code: 0xE1 }),
+        // A symbol defined by the program takes precedence over the built-in name
+        &Operand::Label("KEY_B".to_string()),
+    ]);
+
+    // Also when it is defined after its use
+    let program = parse("main:\n    CALL key_a\n    PRESS_GK KEY_C\n    EXIT\nkey_a:\n    RET\nKEY_C = 9\n")
+        .expect("Failed to parse forward references");
+    let operands: Vec<&Operand> = program.items.iter().filter_map(|item| match &item.node {
+        Item::Instruction(instr) => instr.operands.first().map(|op| &op.node),
+        _ => None,
+    }).collect();
+    assert_eq!(operands, vec![&Operand::Label("key_a".to_string()), &Operand::Label("KEY_C".to_string())]);
+}
+
+#[test]
+fn test_color_literals() {
+    use sayo_ast::{Color, Item, Operand};
+
+    let program = parse("main:\n    LED_COL #FF8000\n    LED_COL rgb(255, 0x80, 0)\n    LED_COL orange\n    MOV32 ALL_LED_COL, #00ff00\n    RET\n")
+        .expect("Failed to parse colour literals");
+    let operands: Vec<&Operand> = program.items.iter().filter_map(|item| match &item.node {
+        Item::Instruction(instr) => instr.operands.last().map(|op| &op.node),
+        _ => None,
+    }).collect();
+    let orange = Operand::Color(Color::new(0xFF, 0x80, 0x00));
+    assert_eq!(operands, vec![&orange, &orange, &orange, &Operand::Color(Color::new(0, 0xFF, 0))]);
+
+    // Only 6-digit literals and 8-bit components are colours
+    assert!(parse("LED_COL #FFF\n").is_err());
+    assert!(parse("LED_COL rgb(256, 0, 0)\n").is_err());
+}
@@ -0,0 +1,436 @@
+/// Call graph
+///
+/// `CallGraph::build` connects the routines of a program: the functions of its `Cfg` and
+/// the code entered through `NEW_THREAD` and register jumps. Edges come from
+/// - `CALL label`
+/// - `NEW_THREAD id, Rn, Rm` and register jumps (`JFZ R0, R1`) whose target register was
+///   loaded with a label earlier in the same block (`MOV16 R1, worker`)
+/// - jumps to another routine's entry; after `MOV_PC2REG` in the same block the jump is
+///   a call, the callee returning through the saved address
+///
+/// A routine owns the blocks reachable from its entry without entering another routine,
+/// so a tail jump into a helper is an edge rather than a copy of the helper's calls.
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::Write;
+use sayo_ast::{Item, Mnemonic, Operand, Program, Register, SourceMap, Span};
+use serde::Serialize;
+use crate::cfg::{flow, BasicBlock, BlockId, Cfg, Flow};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CallKind {
+    /// `CALL`, or a jump after `MOV_PC2REG`
+    Call,
+    /// `NEW_THREAD`
+    Thread,
+    /// Jump to another routine, directly or through a register
+    Jump,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Routine {
+    pub name: String,
+    pub entry: BlockId,
+    /// Blocks reachable from the entry without entering another routine, in program order
+    pub blocks: Vec<BlockId>,
+    /// Started by `NEW_THREAD`
+    pub thread: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallEdge {
+    /// Index of the calling routine in `CallGraph::routines`
+    pub caller: usize,
+    pub callee: usize,
+    pub kind: CallKind,
+    /// The instruction making the call
+    pub span: Span,
+}
+
+/// Calls, thread starts and jumps between the routines of a program
+#[derive(Debug, Clone, Default)]
+pub struct CallGraph {
+    routines: Vec<Routine>,
+    edges: Vec<CallEdge>,
+    /// Calls, thread starts and register jumps whose target is only known at run time:
+    /// (caller, kind, span)
+    unresolved: Vec<(usize, CallKind, Span)>,
+    /// Whether each routine can run, starting from the program entry
+    reachable: Vec<bool>,
+}
+
+/// A call, thread start or jump found in a routine
+struct Site {
+    caller: usize,
+    kind: CallKind,
+    target: Option<BlockId>,
+    span: Span,
+}
+
+impl CallGraph {
+    pub fn build(cfg: &Cfg, program: &Program) -> Self {
+        let mut entries: Vec<BlockId> = cfg.functions().iter().map(|function| function.entry).collect();
+        // Thread and register jump targets are routines too, and may start others
+        loop {
+            let routines = routines(cfg, &entries);
+            let sites = sites(cfg, program, &routines);
+            let before = entries.len();
+            for target in sites.iter().filter_map(|site| site.target) {
+                if !entries.contains(&target) {
+                    entries.push(target);
+                }
+            }
+            if entries.len() == before {
+                return Self::link(routines, sites);
+            }
+        }
+    }
+
+    fn link(mut routines: Vec<Routine>, sites: Vec<Site>) -> Self {
+        let mut edges = Vec::new();
+        let mut unresolved = Vec::new();
+        for site in sites {
+            match site.target.and_then(|target| routines.iter().position(|routine| routine.entry == target)) {
+                Some(callee) => {
+                    routines[callee].thread |= site.kind == CallKind::Thread;
+                    edges.push(CallEdge { caller: site.caller, callee, kind: site.kind, span: site.span });
+                }
+                None => unresolved.push((site.caller, site.kind, site.span)),
+            }
+        }
+
+        let mut reachable = vec![false; routines.len()];
+        let mut stack = Vec::new();
+        if !routines.is_empty() {
+            reachable[0] = true;
+            stack.push(0);
+        }
+        while let Some(routine) = stack.pop() {
+            for edge in edges.iter().filter(|edge| edge.caller == routine) {
+                if !reachable[edge.callee] {
+                    reachable[edge.callee] = true;
+                    stack.push(edge.callee);
+                }
+            }
+        }
+        Self { routines, edges, unresolved, reachable }
+    }
+
+    /// Functions of the `Cfg` in its order, then thread and jump targets; the first is
+    /// the program entry
+    pub fn routines(&self) -> &[Routine] {
+        &self.routines
+    }
+
+    pub fn routine(&self, name: &str) -> Option<usize> {
+        self.routines.iter().position(|routine| routine.name == name)
+    }
+
+    /// Every call site, in routine order
+    pub fn edges(&self) -> &[CallEdge] {
+        &self.edges
+    }
+
+    /// Sites whose target is only known at run time: (caller, kind, span)
+    pub fn unresolved(&self) -> &[(usize, CallKind, Span)] {
+        &self.unresolved
+    }
+
+    /// Routines `routine` calls, starts or jumps to, without duplicates
+    pub fn callees(&self, routine: usize) -> Vec<usize> {
+        let callees: BTreeSet<usize> = self.edges.iter()
+            .filter(|edge| edge.caller == routine)
+            .map(|edge| edge.callee)
+            .collect();
+        callees.into_iter().collect()
+    }
+
+    /// Routines that call, start or jump to `routine`, without duplicates
+    pub fn callers(&self, routine: usize) -> Vec<usize> {
+        let callers: BTreeSet<usize> = self.edges.iter()
+            .filter(|edge| edge.callee == routine)
+            .map(|edge| edge.caller)
+            .collect();
+        callers.into_iter().collect()
+    }
+
+    /// Routines started with `NEW_THREAD`
+    pub fn threads(&self) -> Vec<usize> {
+        (0..self.routines.len()).filter(|&routine| self.routines[routine].thread).collect()
+    }
+
+    /// Whether `routine` can run: the entry and everything it calls, starts or jumps to
+    pub fn is_reachable(&self, routine: usize) -> bool {
+        self.reachable[routine]
+    }
+
+    /// Routines that nothing reachable from the entry calls, starts or jumps to
+    pub fn dead(&self) -> Vec<usize> {
+        (0..self.routines.len()).filter(|&routine| !self.reachable[routine]).collect()
+    }
+
+    /// Graphviz rendering: one box per routine, threads double-bordered and dead routines
+    /// dashed; sites with unknown targets lead to a `?` node
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        dot.push_str("digraph calls {\n");
+        dot.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+        for (index, routine) in self.routines.iter().enumerate() {
+            let mut attributes = format!("label=\"{}\"", routine.name.replace('\\', "\\\\").replace('"', "\\\""));
+            if routine.thread {
+                attributes.push_str(", peripheries=2");
+            }
+            if !self.reachable[index] {
+                attributes.push_str(", style=dashed");
+            }
+            let _ = writeln!(dot, "    r{} [{}];", index, attributes);
+        }
+        if !self.unresolved.is_empty() {
+            dot.push_str("    unknown [label=\"?\", shape=plaintext];\n");
+        }
+
+        let edges: BTreeSet<(usize, usize, CallKind)> = self.edges.iter()
+            .map(|edge| (edge.caller, edge.callee, edge.kind))
+            .collect();
+        for (caller, callee, kind) in edges {
+            let _ = writeln!(dot, "    r{} -> r{}{};", caller, callee, edge_style(kind));
+        }
+        let unresolved: BTreeSet<(usize, CallKind)> = self.unresolved.iter()
+            .map(|&(caller, kind, _)| (caller, kind))
+            .collect();
+        for (caller, kind) in unresolved {
+            let _ = writeln!(dot, "    r{} -> unknown{};", caller, edge_style(kind));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// JSON report: the routines and every call site with its file and line
+    pub fn to_json(&self, sources: &SourceMap) -> String {
+        #[derive(Serialize)]
+        struct JsonRoutine<'a> {
+            name: &'a str,
+            thread: bool,
+            reachable: bool,
+            callees: Vec<&'a str>,
+            callers: Vec<&'a str>,
+        }
+        #[derive(Serialize)]
+        struct JsonSite<'a> {
+            from: &'a str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            to: Option<&'a str>,
+            kind: CallKind,
+            file: String,
+            line: usize,
+        }
+        #[derive(Serialize)]
+        struct JsonGraph<'a> {
+            entry: Option<&'a str>,
+            routines: Vec<JsonRoutine<'a>>,
+            calls: Vec<JsonSite<'a>>,
+            unresolved: Vec<JsonSite<'a>>,
+        }
+
+        let name = |routine: usize| self.routines[routine].name.as_str();
+        let site = |caller: usize, callee: Option<usize>, kind: CallKind, span: Span| JsonSite {
+            from: name(caller),
+            to: callee.map(name),
+            kind,
+            file: sources.path(span.file).display().to_string(),
+            line: sources.line_col(span.file, span.start).0,
+        };
+        let graph = JsonGraph {
+            entry: self.routines.first().map(|routine| routine.name.as_str()),
+            routines: (0..self.routines.len())
+                .map(|routine| JsonRoutine {
+                    name: name(routine),
+                    thread: self.routines[routine].thread,
+                    reachable: self.reachable[routine],
+                    callees: self.callees(routine).into_iter().map(name).collect(),
+                    callers: self.callers(routine).into_iter().map(name).collect(),
+                })
+                .collect(),
+            calls: self.edges.iter()
+                .map(|edge| site(edge.caller, Some(edge.callee), edge.kind, edge.span))
+                .collect(),
+            unresolved: self.unresolved.iter()
+                .map(|&(caller, kind, span)| site(caller, None, kind, span))
+                .collect(),
+        };
+        serde_json::to_string_pretty(&graph).expect("call graph serializes")
+    }
+}
+
+fn edge_style(kind: CallKind) -> &'static str {
+    match kind {
+        CallKind::Call => "",
+        CallKind::Thread => " [style=bold, label=\"thread\"]",
+        CallKind::Jump => " [style=dashed, label=\"jump\"]",
+    }
+}
+
+/// Routines entered at `entries`, each owning the blocks it reaches before another entry
+fn routines(cfg: &Cfg, entries: &[BlockId]) -> Vec<Routine> {
+    let starts: HashSet<BlockId> = entries.iter().copied().collect();
+    entries.iter()
+        .map(|&entry| {
+            let mut seen = BTreeSet::from([entry]);
+            let mut stack = vec![entry];
+            while let Some(id) = stack.pop() {
+                for successor in cfg.successors(id) {
+                    if !starts.contains(&successor) && seen.insert(successor) {
+                        stack.push(successor);
+                    }
+                }
+            }
+            let name = cfg.functions().iter()
+                .find(|function| function.entry == entry)
+                .map_or_else(|| cfg.block_name(entry), |function| function.name.clone());
+            Routine { name, entry, blocks: seen.into_iter().collect(), thread: false }
+        })
+        .collect()
+}
+
+/// Calls, thread starts and jumps out of each routine
+fn sites(cfg: &Cfg, program: &Program, routines: &[Routine]) -> Vec<Site> {
+    let mut sites = Vec::new();
+    for (caller, routine) in routines.iter().enumerate() {
+        // Jumps back into the routine are its own control flow
+        let internal = |target: BlockId| target == routine.entry
+            || (routine.blocks.binary_search(&target).is_ok() && !routines.iter().any(|r| r.entry == target));
+        for &id in &routine.blocks {
+            let block = cfg.block(id);
+            let mut saved_pc = false;
+            for (at, &index) in block.instructions.iter().enumerate() {
+                let Item::Instruction(instr) = &program.items[index].node else {
+                    continue;
+                };
+                let span = program.items[index].span;
+                let jump = if saved_pc { CallKind::Call } else { CallKind::Jump };
+                let (kind, register) = match (instr.mnemonic, flow(instr)) {
+                    (Mnemonic::MOV_PC2REG, _) => {
+                        saved_pc = true;
+                        continue;
+                    }
+                    (Mnemonic::NEW_THREAD, _) => (CallKind::Thread, instr.operands.get(1)),
+                    (_, Flow::Call(_)) => {
+                        let target = block.calls.iter()
+                            .find(|&&(call, _)| call == index)
+                            .and_then(|&(_, callee)| callee);
+                        sites.push(Site { caller, kind: CallKind::Call, target, span });
+                        continue;
+                    }
+                    (_, Flow::Jump(Operand::Label(name)) | Flow::Branch(Operand::Label(name))) => {
+                        // A plain jump only leaves the routine when it enters another
+                        let target = cfg.resolve(name, index).filter(|&target| {
+                            jump == CallKind::Call || (target != routine.entry && routines.iter().any(|r| r.entry == target))
+                        });
+                        if target.is_some() {
+                            sites.push(Site { caller, kind: jump, target, span });
+                        }
+                        continue;
+                    }
+                    (_, Flow::Branch(Operand::Register(_)) | Flow::IndirectBranch) => (jump, instr.operands.last()),
+                    _ => continue,
+                };
+
+                let target = match register.map(|op| &op.node) {
+                    Some(Operand::Register(reg)) => loaded_label(program, block, at, reg)
+                        .and_then(|(name, load)| cfg.resolve(name, load)),
+                    _ => None,
+                };
+                if kind == CallKind::Thread || !target.is_some_and(internal) {
+                    sites.push(Site { caller, kind, target, span });
+                }
+            }
+        }
+    }
+    sites
+}
+
+/// Label loaded into `reg` by the last write to it before position `at` of `block`
+/// (`MOV16 R1, worker`), with the item index of the load
+fn loaded_label<'a>(program: &'a Program, block: &BasicBlock, at: usize, reg: &Register) -> Option<(&'a str, usize)> {
+    for &index in block.instructions[..at].iter().rev() {
+        let Item::Instruction(instr) = &program.items[index].node else {
+            continue;
+        };
+        let writes = instr.mnemonic.metadata().operands.first().is_some_and(|def| def.is_write);
+        if let Some(Operand::Register(written)) = instr.operands.first().map(|op| &op.node) {
+            if writes && written == reg {
+                return match instr.operands.get(1).map(|op| &op.node) {
+                    Some(Operand::Label(name)) => Some((name.as_str(), index)),
+                    _ => None,
+                };
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(text: &str) -> CallGraph {
+        let program = sayo_parser::parse(text).unwrap();
+        CallGraph::build(&Cfg::build(&program), &program)
+    }
+
+    fn names(graph: &CallGraph, routines: Vec<usize>) -> Vec<&str> {
+        routines.into_iter().map(|routine| graph.routines()[routine].name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_calls_and_threads() {
+        let graph = build("main:\n    CALL setup\n    MOV16 R1, worker\n    NEW_THREAD 1, R1, R2\n    CALL setup\n    EXIT\n\
+                           setup:\n    RET\nworker:\n    CALL setup\n    EXIT\n");
+        let main = graph.routine("main").unwrap();
+        let worker = graph.routine("worker").unwrap();
+        assert_eq!(names(&graph, graph.callees(main)), vec!["setup", "worker"]);
+        assert_eq!(names(&graph, graph.callers(graph.routine("setup").unwrap())), vec!["main", "worker"]);
+        assert_eq!(graph.threads(), vec![worker]);
+        assert!(graph.edges().iter().any(|edge| edge.callee == worker && edge.kind == CallKind::Thread));
+        assert!(graph.dead().is_empty());
+    }
+
+    #[test]
+    fn test_jumps() {
+        let graph = build("main:\n    MOV16 R1, tail\n    JFZ R2, R1\n    JFZ R2, R3\n    MOV_PC2REG R0\n    JMP helper\n\
+                           helper:\n    JFNZ R4, R0\n    EXIT\ntail:\n    EXIT\n\
+                           .type unused, @function\nunused:\n    RET\n");
+        let kinds: Vec<_> = graph.edges().iter()
+            .map(|edge| (graph.routines()[edge.callee].name.as_str(), edge.kind))
+            .collect();
+        assert_eq!(kinds, vec![("tail", CallKind::Jump), ("helper", CallKind::Call)]);
+        // JFZ R2, R3 in main and the return through R0 in helper
+        assert_eq!(graph.unresolved().len(), 2);
+        assert_eq!(names(&graph, graph.dead()), vec!["unused"]);
+    }
+
+    #[test]
+    fn test_tail_jump_owns_blocks() {
+        let graph = build("main:\n    CALL f\n    EXIT\nf:\n    JMP g\n.type g, @function\ng:\n    CALL h\n    RET\nh:\n    RET\n");
+        let f = graph.routine("f").unwrap();
+        assert_eq!(names(&graph, graph.callees(f)), vec!["g"]);
+        assert_eq!(graph.edges().iter().filter(|edge| graph.routines()[edge.callee].name == "h").count(), 1);
+    }
+
+    #[test]
+    fn test_dot_and_json() {
+        let text = "main:\n    MOV16 R1, worker\n    NEW_THREAD 0, R1, R2\n    EXIT\nworker:\n    EXIT\n";
+        let graph = build(text);
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph calls {"));
+        assert!(dot.contains("r1 [label=\"worker\", peripheries=2];"));
+        assert!(dot.contains("r0 -> r1 [style=bold, label=\"thread\"];"));
+
+        let json: serde_json::Value = serde_json::from_str(&graph.to_json(&SourceMap::with_root("main.s", text))).unwrap();
+        assert_eq!(json["entry"], "main");
+        assert_eq!(json["calls"][0]["to"], "worker");
+        assert_eq!(json["calls"][0]["kind"], "thread");
+        assert_eq!(json["calls"][0]["line"], 3);
+        assert_eq!(json["routines"][1]["callers"][0], "main");
+    }
+}
@@ -0,0 +1,654 @@
+/// Control-flow graph
+///
+/// `Cfg::build` splits the instructions of a program into basic blocks: a block starts at
+/// the first instruction, at every label and after every jump, return or exit, and ends
+/// with the instruction that leaves it. `CALL` does not end a block; the call is recorded
+/// on the block and execution continues with the next instruction.
+///
+/// Functions are entered at `main` (where the binary header points; the first instruction
+/// if there is no `main`), at `CALL` targets and at labels declared with
+/// `.type name, @function`. A function's blocks are those reachable from
+/// its entry without following calls, so a block may belong to several functions.
+/// Labels used as values (`MOV16 R0, handler`, `.word handler`) may also be entered,
+/// through register jumps or `NEW_THREAD`.
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+use sayo_ast::{numeric_label, ControlFlow, DataValue, Directive, Instruction, Item, NumericLabels, Operand, Program, Span, Spanned};
+
+/// Index of a block in `Cfg::blocks`
+pub type BlockId = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Execution continues with the next instruction
+    FallThrough,
+    /// Unconditional jump (`JMP`/`SJMP`/`AJMP`, `RES`)
+    Jump,
+    /// Taken side of a conditional jump
+    Branch,
+}
+
+/// Ways a block leaves the graph besides its successors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    /// `RET` to the caller
+    Return,
+    /// `END`, `EXIT` and `JMP_TO_SCRIPT` stop the script; `EXIT_IF_*` may
+    Exit,
+    /// Jump to an address only known at run time: a register target (`JFZ R0, R1`),
+    /// a constant address (`JMP 0x100`) or an expression
+    Indirect,
+    /// Execution runs past the last instruction into data or off the end of the program
+    FallOff,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    pub id: BlockId,
+    /// Labels naming the first instruction
+    pub labels: Vec<String>,
+    /// Item indices of the instructions, in program order
+    pub instructions: Vec<usize>,
+    /// From the first instruction to the last
+    pub span: Span,
+    pub successors: Vec<(BlockId, EdgeKind)>,
+    pub predecessors: Vec<BlockId>,
+    pub exits: Vec<Exit>,
+    /// `CALL`s made by the block: item index and the callee's entry block, if the
+    /// target is a label
+    pub calls: Vec<(usize, Option<BlockId>)>,
+}
+
+impl BasicBlock {
+    /// Item index of the instruction that ends the block
+    pub fn last(&self) -> usize {
+        *self.instructions.last().expect("blocks are never empty")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub entry: BlockId,
+    /// Blocks reachable from the entry without following calls, in program order
+    pub blocks: Vec<BlockId>,
+}
+
+/// Control-flow graph of a program
+#[derive(Debug, Clone, Default)]
+pub struct Cfg {
+    blocks: Vec<BasicBlock>,
+    functions: Vec<Function>,
+    /// Block of each instruction, by item index
+    block_of: HashMap<usize, BlockId>,
+    /// Blocks whose label is used as a value
+    address_taken: Vec<BlockId>,
+    targets: Targets,
+}
+
+/// What an instruction does to control flow
+#[derive(Clone, Copy)]
+pub(crate) enum Flow<'a> {
+    /// Continues with the next instruction
+    Next,
+    Call(&'a Operand),
+    Jump(&'a Operand),
+    /// Conditional jump to a label (or a register, for the `JZ R0, R1` spelling)
+    Branch(&'a Operand),
+    /// Conditional jump to a register target (`JF*` forms)
+    IndirectBranch,
+    Return,
+    Exit,
+    /// `EXIT_IF_*`: exits or continues
+    ExitIf,
+    /// `RES`: restarts at the program entry
+    Restart,
+}
+
+pub(crate) fn flow(instr: &Instruction) -> Flow<'_> {
+    let first = instr.operands.first().map(|op| &op.node);
+    let last = instr.operands.last().map(|op| &op.node);
+    match (instr.mnemonic.control_flow(), first, last) {
+        (ControlFlow::Jump, Some(target), _) => Flow::Jump(target),
+        (ControlFlow::Call, Some(target), _) => Flow::Call(target),
+        (ControlFlow::Branch, _, Some(target)) => Flow::Branch(target),
+        (ControlFlow::RegisterBranch, ..) => Flow::IndirectBranch,
+        (ControlFlow::Return, ..) => Flow::Return,
+        (ControlFlow::Exit, ..) => Flow::Exit,
+        (ControlFlow::ExitIf, ..) => Flow::ExitIf,
+        (ControlFlow::Restart, ..) => Flow::Restart,
+        _ => Flow::Next,
+    }
+}
+
+/// Directives that place bytes in the current section
+pub(crate) fn emits_data(directive: &Directive) -> bool {
+    matches!(
+        directive,
+        Directive::Byte(_) | Directive::Word(_) | Directive::Short(_) | Directive::Long(_) | Directive::Quad(_)
+            | Directive::Ascii(_) | Directive::Asciz(_) | Directive::Zero(_) | Directive::Skip(_)
+    )
+}
+
+/// Labels an item uses as values rather than as jump or call targets
+fn value_references(item: &Item) -> Vec<&str> {
+    match item {
+        Item::Instruction(instr) => {
+            let target = match flow(instr) {
+                Flow::Jump(target) | Flow::Call(target) | Flow::Branch(target) => Some(target),
+                _ => None,
+            };
+            instr.operands.iter()
+                .filter(|op| !target.is_some_and(|target| std::ptr::eq(target, &op.node)))
+                .flat_map(|op| match &op.node {
+                    Operand::Label(name) => vec![name.as_str()],
+                    Operand::Expr(expr) => expr.symbols(),
+                    _ => Vec::new(),
+                })
+                .collect()
+        }
+        Item::Directive(Directive::Equ(_, expr, _)) => expr.symbols(),
+        Item::Directive(
+            Directive::Byte(values) | Directive::Word(values) | Directive::Short(values)
+            | Directive::Long(values) | Directive::Quad(values),
+        ) => values.iter()
+            .flat_map(|value| match value {
+                DataValue::Label(name) => vec![name.as_str()],
+                DataValue::Expr(expr) => expr.symbols(),
+                DataValue::Immediate(_) => Vec::new(),
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Code labels of a program, for resolving jump and call targets
+#[derive(Debug, Clone, Default)]
+struct Targets {
+    /// Global labels and `global.local` keys -> first instruction after the label
+    named: HashMap<String, usize>,
+    /// Numeric labels -> first instruction after the label
+    numeric: NumericLabels<usize>,
+    /// Global label in effect at each item
+    scopes: Vec<Option<String>>,
+}
+
+impl Targets {
+    /// Instruction item that the reference `name` made by item `index` jumps to
+    fn resolve(&self, name: &str, index: usize) -> Option<usize> {
+        if let Some(target) = self.numeric.resolve(name, index) {
+            return Some(target);
+        }
+        let key = match (name.starts_with('.'), &self.scopes[index]) {
+            (true, Some(global)) => format!("{}{}", global, name),
+            _ => name.to_string(),
+        };
+        self.named.get(&key).copied()
+    }
+}
+
+impl Cfg {
+    pub fn build(program: &Program) -> Self {
+        let mut cfg = Cfg::default();
+        let mut targets = Targets::default();
+        // Labels waiting for the instruction they name: (item index, name, scoped key)
+        let mut pending: Vec<(usize, String, Option<String>)> = Vec::new();
+        let mut global: Option<String> = None;
+        let mut in_text = true;
+        let mut split = true;
+        // Blocks followed by data in the text section, which they would run into
+        let mut into_data = BTreeSet::new();
+        // Whether an instruction came after the last data directive
+        let mut after_code = false;
+        let mut functions: Vec<String> = Vec::new();
+        let mut references: Vec<(&str, usize)> = Vec::new();
+
+        for (index, item) in program.items.iter().enumerate() {
+            references.extend(value_references(&item.node).into_iter().map(|name| (name, index)));
+            match &item.node {
+                Item::Label(name) => {
+                    let key = if numeric_label(name).is_some() {
+                        None
+                    } else if name.starts_with('.') {
+                        global.as_ref().map(|global| format!("{}{}", global, name))
+                    } else {
+                        global = Some(name.clone());
+                        Some(name.clone())
+                    };
+                    if in_text {
+                        pending.push((index, name.clone(), key));
+                        split = true;
+                    }
+                }
+                Item::Directive(Directive::Text) => in_text = true,
+                Item::Directive(Directive::Data) => in_text = false,
+                Item::Directive(Directive::Section(name)) if name.contains("rodata") => in_text = false,
+                Item::Directive(Directive::Type(name, kind)) if kind.trim_start_matches('@') == "function" => {
+                    functions.push(name.clone());
+                }
+                Item::Directive(directive) if in_text && emits_data(directive) => {
+                    // Labels before data name the data, not code
+                    pending.clear();
+                    if after_code {
+                        into_data.insert(cfg.blocks.len() - 1);
+                    }
+                    after_code = false;
+                    split = true;
+                }
+                Item::Instruction(instr) => {
+                    if split || cfg.blocks.is_empty() {
+                        let id = cfg.blocks.len();
+                        cfg.blocks.push(BasicBlock {
+                            id,
+                            labels: Vec::new(),
+                            instructions: Vec::new(),
+                            span: item.span,
+                            successors: Vec::new(),
+                            predecessors: Vec::new(),
+                            exits: Vec::new(),
+                            calls: Vec::new(),
+                        });
+                        for (label, name, key) in pending.drain(..) {
+                            match (numeric_label(&name), key) {
+                                (Some(number), _) => targets.numeric.define(number, label, index),
+                                (None, Some(key)) => {
+                                    targets.named.insert(key, index);
+                                }
+                                (None, None) => {}
+                            }
+                            cfg.blocks[id].labels.push(name);
+                        }
+                    }
+                    let id = cfg.blocks.len() - 1;
+                    let block = &mut cfg.blocks[id];
+                    block.instructions.push(index);
+                    block.span = block.span.merge(item.span);
+                    cfg.block_of.insert(index, id);
+                    after_code = true;
+                    split = !matches!(flow(instr), Flow::Next | Flow::Call(_));
+                }
+                Item::Directive(_) => {}
+            }
+            targets.scopes.push(global.clone());
+        }
+
+        let address_taken: BTreeSet<BlockId> = references.into_iter()
+            .filter_map(|(name, index)| targets.resolve(name, index))
+            .map(|item| cfg.block_of[&item])
+            .collect();
+        cfg.address_taken = address_taken.into_iter().collect();
+        cfg.link(program, &targets, &into_data);
+        cfg.find_functions(&targets, &functions);
+        cfg.targets = targets;
+        cfg
+    }
+
+    /// Add the edges and exits of every block
+    fn link(&mut self, program: &Program, targets: &Targets, into_data: &BTreeSet<BlockId>) {
+        let instruction = |index: usize| match &program.items[index].node {
+            Item::Instruction(instr) => instr,
+            _ => unreachable!("blocks hold instructions only"),
+        };
+        let resolve = |target: &Operand, index: usize| match target {
+            Operand::Label(name) => targets.resolve(name, index),
+            _ => None,
+        };
+        let program_entry = self.program_entry(targets);
+
+        for id in 0..self.blocks.len() {
+            let mut successors = Vec::new();
+            let mut exits = Vec::new();
+            let mut calls = Vec::new();
+            for &index in &self.blocks[id].instructions {
+                if let Flow::Call(target) = flow(instruction(index)) {
+                    calls.push((index, resolve(target, index).map(|item| self.block_of[&item])));
+                }
+            }
+
+            let last = self.blocks[id].last();
+            let last_flow = flow(instruction(last));
+            let falls_through = match last_flow {
+                Flow::Next | Flow::Call(_) => true,
+                Flow::Jump(target) | Flow::Branch(target) => {
+                    let conditional = matches!(last_flow, Flow::Branch(_));
+                    match resolve(target, last) {
+                        Some(item) => {
+                            let kind = if conditional { EdgeKind::Branch } else { EdgeKind::Jump };
+                            successors.push((self.block_of[&item], kind));
+                        }
+                        None => exits.push(Exit::Indirect),
+                    }
+                    conditional
+                }
+                Flow::IndirectBranch => {
+                    exits.push(Exit::Indirect);
+                    true
+                }
+                Flow::Return => {
+                    exits.push(Exit::Return);
+                    false
+                }
+                Flow::Exit => {
+                    exits.push(Exit::Exit);
+                    false
+                }
+                Flow::ExitIf => {
+                    exits.push(Exit::Exit);
+                    true
+                }
+                Flow::Restart => {
+                    successors.extend(program_entry.map(|entry| (entry, EdgeKind::Jump)));
+                    false
+                }
+            };
+            if falls_through {
+                if id + 1 < self.blocks.len() && !into_data.contains(&id) {
+                    successors.push((id + 1, EdgeKind::FallThrough));
+                } else {
+                    exits.push(Exit::FallOff);
+                }
+            }
+
+            for &(successor, _) in &successors {
+                let predecessors = &mut self.blocks[successor].predecessors;
+                if !predecessors.contains(&id) {
+                    predecessors.push(id);
+                }
+            }
+            let block = &mut self.blocks[id];
+            block.successors = successors;
+            block.exits = exits;
+            block.calls = calls;
+        }
+        for block in &mut self.blocks {
+            block.predecessors.sort_unstable();
+        }
+    }
+
+    /// Block of the `main` label, or the first block when there is none
+    fn program_entry(&self, targets: &Targets) -> Option<BlockId> {
+        match targets.named.get("main") {
+            Some(&item) => Some(self.block_of[&item]),
+            None => (!self.blocks.is_empty()).then_some(0),
+        }
+    }
+
+    /// Functions entered at the program entry, at call targets and at `.type name, @function`
+    fn find_functions(&mut self, targets: &Targets, declared: &[String]) {
+        let mut entries: HashMap<BlockId, String> = HashMap::new();
+        let program_entry = self.program_entry(targets);
+        if let Some(entry) = program_entry {
+            let name = self.blocks[entry].labels.iter().find(|label| is_global(label)).cloned();
+            entries.insert(entry, name.unwrap_or_else(|| "<entry>".to_string()));
+        }
+        for block in &self.blocks {
+            for &(_, callee) in &block.calls {
+                if let Some(callee) = callee {
+                    let name = self.block_name(callee);
+                    entries.entry(callee).or_insert(name);
+                }
+            }
+        }
+        for name in declared {
+            if let Some(&item) = targets.named.get(name) {
+                entries.entry(self.block_of[&item]).or_insert_with(|| name.clone());
+            }
+        }
+
+        let mut entries: Vec<_> = entries.into_iter().collect();
+        entries.sort_unstable_by_key(|&(entry, _)| (Some(entry) != program_entry, entry));
+        self.functions = entries.into_iter()
+            .map(|(entry, name)| Function { name, entry, blocks: self.reachable(entry) })
+            .collect();
+    }
+
+    /// Blocks reachable from `entry` along edges (not calls), in program order
+    pub fn reachable(&self, entry: BlockId) -> Vec<BlockId> {
+        self.reachable_from(&[entry])
+    }
+
+    fn reachable_from(&self, roots: &[BlockId]) -> Vec<BlockId> {
+        let mut seen: BTreeSet<BlockId> = roots.iter().copied().collect();
+        let mut stack = roots.to_vec();
+        while let Some(id) = stack.pop() {
+            for &(successor, _) in &self.blocks[id].successors {
+                if seen.insert(successor) {
+                    stack.push(successor);
+                }
+            }
+        }
+        seen.into_iter().collect()
+    }
+
+    /// Name of the code starting at a block: its first global label, else its first label
+    pub(crate) fn block_name(&self, id: BlockId) -> String {
+        let labels = &self.blocks[id].labels;
+        labels.iter().find(|label| is_global(label))
+            .or_else(|| labels.first())
+            .cloned()
+            .unwrap_or_else(|| format!("<block {}>", id))
+    }
+
+    pub fn blocks(&self) -> &[BasicBlock] {
+        &self.blocks
+    }
+
+    pub fn block(&self, id: BlockId) -> &BasicBlock {
+        &self.blocks[id]
+    }
+
+    /// Block holding the instruction at item `index`
+    pub fn block_of(&self, index: usize) -> Option<BlockId> {
+        self.block_of.get(&index).copied()
+    }
+
+    /// Block a label reference made by item `index` leads to; local and numeric labels
+    /// resolve relative to that item
+    pub fn resolve(&self, name: &str, index: usize) -> Option<BlockId> {
+        self.targets.resolve(name, index).map(|item| self.block_of[&item])
+    }
+
+    pub fn successors(&self, id: BlockId) -> impl Iterator<Item = BlockId> + '_ {
+        self.blocks[id].successors.iter().map(|&(successor, _)| successor)
+    }
+
+    pub fn predecessors(&self, id: BlockId) -> &[BlockId] {
+        &self.blocks[id].predecessors
+    }
+
+    /// The program entry, then the other functions in program order
+    pub fn functions(&self) -> &[Function] {
+        &self.functions
+    }
+
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.iter().find(|function| function.name == name)
+    }
+
+    /// Blocks whose label is used as a value (`MOV16 R0, handler`, `.word handler`)
+    pub fn address_taken(&self) -> &[BlockId] {
+        &self.address_taken
+    }
+
+    /// Blocks that no function entry or address-taken block leads to, in program order
+    pub fn unreachable(&self) -> Vec<BlockId> {
+        let roots: Vec<BlockId> = self.functions.iter().map(|function| function.entry)
+            .chain(self.address_taken.iter().copied())
+            .collect();
+        let reachable = self.reachable_from(&roots);
+        (0..self.blocks.len()).filter(|id| reachable.binary_search(id).is_err()).collect()
+    }
+
+    /// Graphviz rendering: one box per block, one cluster per function (a block shared by
+    /// several functions is drawn in the first); calls are dotted edges
+    pub fn to_dot(&self, program: &Program) -> String {
+        let mut owner = vec![None; self.blocks.len()];
+        for (index, function) in self.functions.iter().enumerate() {
+            for &id in &function.blocks {
+                owner[id].get_or_insert(index);
+            }
+        }
+
+        let mut dot = String::new();
+        dot.push_str("digraph cfg {\n");
+        dot.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+        for (index, function) in self.functions.iter().enumerate() {
+            let _ = writeln!(dot, "    subgraph cluster_{} {{", index);
+            let _ = writeln!(dot, "        label=\"{}\";", escape(&function.name));
+            for block in self.blocks.iter().filter(|block| owner[block.id] == Some(index)) {
+                let _ = writeln!(dot, "        {}", self.dot_node(block, program));
+            }
+            dot.push_str("    }\n");
+        }
+        for block in self.blocks.iter().filter(|block| owner[block.id].is_none()) {
+            let _ = writeln!(dot, "    {}", self.dot_node(block, program));
+        }
+        for block in &self.blocks {
+            for &(successor, kind) in &block.successors {
+                let style = match kind {
+                    EdgeKind::FallThrough => " [style=dashed]",
+                    EdgeKind::Jump => "",
+                    EdgeKind::Branch => " [label=\"taken\"]",
+                };
+                let _ = writeln!(dot, "    b{} -> b{}{};", block.id, successor, style);
+            }
+            for &(_, callee) in &block.calls {
+                if let Some(callee) = callee {
+                    let _ = writeln!(dot, "    b{} -> b{} [style=dotted, label=\"call\"];", block.id, callee);
+                }
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn dot_node(&self, block: &BasicBlock, program: &Program) -> String {
+        let mut label = String::new();
+        for name in &block.labels {
+            let _ = write!(label, "{}:\\l", escape(name));
+        }
+        for &index in &block.instructions {
+            if let Item::Instruction(instr) = &program.items[index].node {
+                let _ = write!(label, "    {}\\l", escape(&instruction_text(instr)));
+            }
+        }
+        for exit in &block.exits {
+            match exit {
+                Exit::Indirect => label.push_str("(indirect jump)\\l"),
+                Exit::FallOff => label.push_str("(falls off)\\l"),
+                Exit::Return | Exit::Exit => {}
+            }
+        }
+        format!("b{} [label=\"{}\"];", block.id, label)
+    }
+}
+
+fn is_global(label: &str) -> bool {
+    !label.starts_with('.') && numeric_label(label).is_none()
+}
+
+fn instruction_text(instr: &Instruction) -> String {
+    let operands: Vec<String> = instr.operands.iter().map(|Spanned { node, .. }| match node {
+        Operand::Register(reg) => reg.to_string(),
+        Operand::Immediate(value) => value.to_string(),
+        Operand::Label(name) => name.clone(),
+        Operand::Expr(expr) => expr.to_string(),
+        Operand::Hid(code) => code.to_string(),
+        Operand::Color(color) => color.to_string(),
+    }).collect();
+    if operands.is_empty() {
+        instr.mnemonic.to_string()
+    } else {
+        format!("{} {}", instr.mnemonic, operands.join(", "))
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(text: &str) -> Cfg {
+        Cfg::build(&sayo_parser::parse(text).unwrap())
+    }
+
+    #[test]
+    fn test_blocks_and_edges() {
+        let cfg = build("main:\n    MOV8 R0, 3\n.loop:\n    DJNZ R0, .loop\n    CALL helper\n    JMP .done\n    NOP\n\
+                         .done:\n    EXIT\nhelper:\n    JZ R1, R2\n    RET\n");
+        let blocks = cfg.blocks();
+        assert_eq!(blocks.len(), 7);
+        assert_eq!(blocks[1].labels, vec![".loop"]);
+        assert_eq!(blocks[1].successors, vec![(1, EdgeKind::Branch), (2, EdgeKind::FallThrough)]);
+        assert_eq!(cfg.predecessors(1), &[0, 1]);
+        // The CALL stays in its block; JMP ends it
+        assert_eq!(blocks[2].instructions.len(), 2);
+        assert_eq!(blocks[2].successors, vec![(4, EdgeKind::Jump)]);
+        // NOP after the JMP has no predecessors
+        assert!(blocks[3].predecessors.is_empty());
+        assert_eq!(blocks[4].exits, vec![Exit::Exit]);
+        // A register target is not known statically
+        assert_eq!(blocks[5].exits, vec![Exit::Indirect]);
+        assert_eq!(blocks[5].successors, vec![(6, EdgeKind::FallThrough)]);
+        assert_eq!(blocks[6].exits, vec![Exit::Return]);
+        assert_eq!(blocks[2].calls[0].1, Some(5));
+    }
+
+    #[test]
+    fn test_functions() {
+        let cfg = build("main:\n    CALL helper\n    JMP main\nhelper:\n    JNZ R0, 1f\n    INC R0\n1:\n    RET\n\
+                         .type isr, @function\nisr:\n    RET\n");
+        let names: Vec<_> = cfg.functions().iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["main", "helper", "isr"]);
+        assert_eq!(cfg.function("main").unwrap().blocks, vec![0]);
+        assert_eq!(cfg.function("helper").unwrap().blocks, vec![1, 2, 3]);
+        assert_eq!(cfg.block(1).successors, vec![(3, EdgeKind::Branch), (2, EdgeKind::FallThrough)]);
+    }
+
+    #[test]
+    fn test_main_is_entry() {
+        let cfg = build(".type helper, @function\nhelper:\n    RET\nmain:\n    CALL helper\n    EXIT\n");
+        let names: Vec<_> = cfg.functions().iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["main", "helper"]);
+        assert_eq!(cfg.functions()[0].entry, 1);
+        // RES restarts at main, not at the first block
+        let cfg = build("helper:\n    RET\nmain:\n    CALL helper\n    RES\n");
+        assert_eq!(cfg.block(1).successors, vec![(1, EdgeKind::Jump)]);
+        let cfg = build("start:\n    NOP\nagain:\n    RES\n");
+        assert_eq!(cfg.block(1).successors, vec![(0, EdgeKind::Jump)]);
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let cfg = build("main:\n    MOV16 R1, handler\n    JFZ R0, R1\n    RET\n    INC R0\nhandler:\n    RET\n\
+                         orphan:\n    NOP\n    JMP main\n");
+        assert_eq!(cfg.address_taken(), &[3]);
+        assert_eq!(cfg.unreachable(), vec![2, 4]);
+    }
+
+    #[test]
+    fn test_fall_off_into_data() {
+        let cfg = build("main:\n    EXIT_IF_PRESS\n    NOP\ntable:\n    .byte 1, 2\nnext:\n    RES\n");
+        assert_eq!(cfg.blocks().len(), 3);
+        assert_eq!(cfg.block(0).exits, vec![Exit::Exit]);
+        assert_eq!(cfg.block(1).exits, vec![Exit::FallOff]);
+        assert_eq!(cfg.block(2).labels, vec!["next"]);
+        assert_eq!(cfg.block(2).successors, vec![(0, EdgeKind::Jump)]);
+        // The data label does not name a block
+        assert!(cfg.blocks().iter().all(|block| !block.labels.contains(&"table".to_string())));
+    }
+
+    #[test]
+    fn test_dot() {
+        let program = sayo_parser::parse("main:\n    JZ R0, .end\n    MOV8 R0, 1\n.end:\n    RET\n").unwrap();
+        let dot = Cfg::build(&program).to_dot(&program);
+        assert!(dot.starts_with("digraph cfg {"));
+        assert!(dot.contains("label=\"main\""));
+        assert!(dot.contains("b0 -> b2 [label=\"taken\"];"));
+        assert!(dot.contains("b1 -> b2 [style=dashed];"));
+        assert!(dot.contains("MOV8 R0, 1\\l"));
+    }
+}
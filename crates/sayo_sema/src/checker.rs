@@ -1,24 +1,41 @@
-use sayo_ast::{Program, Item, Instruction, Operand, OperandType, Register, Spanned};
+use sayo_ast::{Diagnostic, Program, Item, Instruction, Operand, OperandType, Register, RegWidth, Span, Spanned};
 
-use crate::{SemanticError, check_immediate_range, LabelAnalyzer};
+use crate::{SemanticError, SemanticWarning, check_immediate_range, LabelAnalyzer, LintConfig, Dataflow, KeyAnalysis, StackAnalysis};
+use crate::cfg::{self, Cfg, EdgeKind, Exit};
+use crate::lint::Level;
 
 /// Semantic checker for Sayo assembly
 pub struct SemanticChecker {
     errors: Vec<SemanticError>,
+    warnings: Vec<SemanticWarning>,
     label_analyzer: LabelAnalyzer,
+    lints: LintConfig,
 }
 
 impl SemanticChecker {
     pub fn new() -> Self {
         Self {
             errors: Vec::new(),
+            warnings: Vec::new(),
             label_analyzer: LabelAnalyzer::new(),
+            lints: LintConfig::new(),
         }
     }
     
+    /// Use the given lint levels instead of the defaults
+    pub fn with_lints(mut self, lints: LintConfig) -> Self {
+        self.lints = lints;
+        self
+    }
+    
     /// Check an entire program
     pub fn check(&mut self, program: &Program) -> Result<(), Vec<SemanticError>> {
         self.errors.clear();
+        self.warnings.clear();
+        
+        for (name, span) in self.lints.unknown_lints().to_vec() {
+            self.warn(SemanticWarning::UnknownLint { name, span });
+        }
         
         // First, perform label analysis (two-pass)
         if let Err(label_errors) = self.label_analyzer.analyze(program) {
@@ -33,6 +50,8 @@ impl SemanticChecker {
             self.check_item(item);
         }
         
+        self.check_control_flow(program);
+        
         if self.errors.is_empty() {
             Ok(())
         } else {
@@ -40,13 +59,34 @@ impl SemanticChecker {
         }
     }
     
+    /// Warnings from the last `check`, reported whether or not it succeeded
+    pub fn warnings(&self) -> &[SemanticWarning] {
+        &self.warnings
+    }
+    
+    /// Errors and warnings from the last `check`, in that order
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.errors.iter().map(SemanticError::diagnostic)
+            .chain(self.warnings.iter().map(SemanticWarning::diagnostic))
+            .collect()
+    }
+    
+    /// Report a lint at the level configured for it
+    fn warn(&mut self, warning: SemanticWarning) {
+        match self.lints.level(warning.lint(), warning.span()) {
+            Level::Allow => {}
+            Level::Warn => self.warnings.push(warning),
+            Level::Deny => self.errors.push(SemanticError::DeniedLint(warning)),
+        }
+    }
+    
     fn check_item(&mut self, item: &Spanned<Item>) {
         match &item.node {
             Item::Instruction(instr) => {
-                self.check_instruction(instr, item.span.start, item.span.end);
+                self.check_instruction(instr, item.span);
             }
             Item::Directive(dir) => {
-                self.check_directive(dir, item.span.start, item.span.end);
+                self.check_directive(dir, item.span);
             }
             Item::Label(_) => {
                 // Labels don't need additional semantic checking here
@@ -54,7 +94,7 @@ impl SemanticChecker {
         }
     }
     
-    fn check_directive(&mut self, dir: &sayo_ast::Directive, start: usize, end: usize) {
+    fn check_directive(&mut self, dir: &sayo_ast::Directive, span: Span) {
         use sayo_ast::Directive;
         match dir {
             Directive::Align(value) => {
@@ -62,18 +102,23 @@ impl SemanticChecker {
                 if *value != 1 {
                     self.errors.push(SemanticError::UnsupportedAlignment {
                         value: *value as i64,
-                        line: start,
-                        col: end,
+                        span,
                     });
                 }
             }
+            Directive::Unknown { name, .. } => {
+                self.warn(SemanticWarning::UnknownDirective {
+                    name: name.clone(),
+                    span,
+                });
+            }
             _ => {
                 // Other directives don't need semantic checking
             }
         }
     }
     
-    fn check_instruction(&mut self, instr: &Instruction, start: usize, _end: usize) {
+    fn check_instruction(&mut self, instr: &Instruction, span: Span) {
         let metadata = instr.mnemonic.metadata();
         
         // Check operand count
@@ -84,8 +129,7 @@ impl SemanticChecker {
                 instruction: format!("{:?}", instr.mnemonic),
                 expected: expected_count,
                 actual: actual_count,
-                line: start,
-                col: _end,
+                span,
             });
             // Continue checking the operands we have
         }
@@ -99,23 +143,62 @@ impl SemanticChecker {
             let expected = &metadata.operands[idx];
             
             // Check operand type matches
-            self.check_operand_type(&operand.node, &expected.op_type, &format!("{:?}", instr.mnemonic), operand.span.start, operand.span.end);
+            // Colours are also accepted as immediates written to 24-bit registers (MOV32 ALL_LED_COL, #FF0000)
+            let color_for_register = matches!(operand.node, Operand::Color(_)) && instr.accepts_color(idx);
+            // Conditional jumps also take their target in a register (JZ R0, R1 is assembled as JFZ)
+            let register_target = operand.node.is_register() && expected.op_type == OperandType::Label
+                && instr.mnemonic.register_target_form().is_some();
+            if !color_for_register && !register_target {
+                self.check_operand_type(&operand.node, &expected.op_type, &format!("{:?}", instr.mnemonic), operand.span);
+            }
             
             // Check immediate value ranges
-            if let Operand::Immediate(value) = operand.node {
-                self.check_immediate_operand(value, &expected.op_type, operand.span.start, operand.span.end);
+            let value = match operand.node {
+                Operand::Immediate(value) => Some(value),
+                Operand::Hid(code) => Some(code.code as i64),
+                Operand::Color(color) if color_for_register => Some(color.rgb888() as i64),
+                _ => None,
+            };
+            if let Some(value) = value {
+                if instr.mnemonic.is_pseudo() {
+                    // Any value one of the real forms can take (e.g. LI accepts -1 through MOV8SX)
+                    if instr.mnemonic.lower(Register::R0, value, 0).is_none() {
+                        self.errors.push(SemanticError::ImmediateOutOfRange {
+                            value,
+                            expected_type: format!("{} immediate", instr.mnemonic),
+                            span: operand.span,
+                        });
+                    }
+                } else {
+                    self.check_immediate_operand(value, &expected.op_type, operand.span);
+                }
+            }
+            
+            // Check that a HID name belongs to the instruction's table (key vs modifier vs media ...)
+            if let (Operand::Hid(code), Some(namespace)) = (&operand.node, instr.mnemonic.hid_namespace()) {
+                if code.namespace != namespace {
+                    self.errors.push(SemanticError::HidNamespaceMismatch {
+                        instruction: format!("{:?}", instr.mnemonic),
+                        name: code.name().to_string(),
+                        expected: namespace.to_string(),
+                        actual: code.namespace.to_string(),
+                        span: operand.span,
+                    });
+                }
             }
             
             // Check register write permissions
             if let Operand::Register(reg) = operand.node {
                 if expected.is_write {
-                    self.check_register_write(reg, operand.span.start, operand.span.end);
+                    self.check_register_write(reg, operand.span);
                 }
             }
         }
+        
+        self.check_widths(instr);
     }
     
-    fn check_operand_type(&mut self, operand: &Operand, expected_type: &OperandType, instruction: &str, start: usize, end: usize) {
+    fn check_operand_type(&mut self, operand: &Operand, expected_type: &OperandType, instruction: &str, span: Span) {
         let matches = match (operand, expected_type) {
             (Operand::Register(_), OperandType::Register) => true,
             (Operand::Immediate(_), OperandType::U8 | OperandType::I8 | OperandType::U16 | OperandType::I16 | OperandType::U32 | OperandType::I32) => true,
@@ -126,6 +209,11 @@ impl SemanticChecker {
             // Labels can be used in MOV8, MOV16, MOV32, ADD8, etc.
             (Operand::Label(_), OperandType::U8 | OperandType::I8 | OperandType::U16 | OperandType::I16 | OperandType::U32 | OperandType::I32) => true,
             (Operand::Immediate(_), OperandType::Rgb888) => true, // RGB888 is also an immediate
+            (Operand::Color(_), OperandType::Rgb888) => true,
+            // HID names are u8 codes
+            (Operand::Hid(_), OperandType::U8 | OperandType::I8 | OperandType::U16 | OperandType::I16 | OperandType::U32 | OperandType::I32) => true,
+            // Expressions over labels are evaluated after layout, like labels
+            (Operand::Expr(_), OperandType::Label | OperandType::U8 | OperandType::I8 | OperandType::U16 | OperandType::I16 | OperandType::U32 | OperandType::I32 | OperandType::Rgb888) => true,
             _ => false,
         };
         
@@ -139,8 +227,7 @@ impl SemanticChecker {
                             self.errors.push(SemanticError::ImmediateOutOfRange {
                                 value: *val,
                                 expected_type: "u16 (address)".to_string(),
-                                line: start,
-                                col: end,
+                                span,
                             });
                             return;
                         }
@@ -148,6 +235,9 @@ impl SemanticChecker {
                     "immediate"
                 }
                 Operand::Label(_) => "label",
+                Operand::Expr(_) => "expression",
+                Operand::Hid(_) => "HID code",
+                Operand::Color(_) => "colour",
             };
             
             let expected = match expected_type {
@@ -167,13 +257,12 @@ impl SemanticChecker {
                 instruction: instruction.to_string(),
                 expected: expected.to_string(),
                 actual: actual.to_string(),
-                line: start,
-                col: end,
+                span,
             });
         }
     }
     
-    fn check_immediate_operand(&mut self, value: i64, expected_type: &OperandType, start: usize, end: usize) {
+    fn check_immediate_operand(&mut self, value: i64, expected_type: &OperandType, span: Span) {
         let type_name = match expected_type {
             OperandType::U8 => "u8",
             OperandType::I8 => "i8",
@@ -188,24 +277,115 @@ impl SemanticChecker {
             self.errors.push(SemanticError::ImmediateOutOfRange {
                 value,
                 expected_type: type_name.to_string(),
-                line: start,
-                col: end,
+                span,
             });
         }
     }
     
-    fn check_register_write(&mut self, register: Register, start: usize, end: usize) {
+    fn check_register_write(&mut self, register: Register, span: Span) {
         let metadata = register.metadata();
         
         if metadata.is_read_only() {
             self.errors.push(SemanticError::WriteToReadOnlyRegister {
                 register: register.to_string(),
-                line: start,
-                col: end,
+                span,
             });
         }
     }
     
+    /// Sized operations on registers of another width (`MOV32 V0, ...`, `MOV8 R0, ...`)
+    fn check_widths(&mut self, instr: &Instruction) {
+        let Some(width) = instr.mnemonic.width() else {
+            return;
+        };
+        let bits = width.bits();
+        for (idx, operand) in instr.operands.iter().enumerate() {
+            let Operand::Register(reg) = operand.node else {
+                continue;
+            };
+            let register_bits = reg.metadata().width.bits();
+            let destination = idx == 0;
+            // 24-bit colour registers are written with the 32-bit forms (MOV32 ALL_LED_COL, #FF0000)
+            let colour = destination && reg.metadata().width == RegWidth::W24 && width == RegWidth::W32;
+            let truncated = bits > register_bits && !colour;
+            let partial = destination && bits < register_bits && instr.mnemonic.is_sized_move();
+            if truncated || partial {
+                self.warn(SemanticWarning::WidthMismatch {
+                    instruction: format!("{:?}", instr.mnemonic),
+                    register: reg.to_string(),
+                    instruction_bits: bits,
+                    register_bits,
+                    destination,
+                    span: operand.span,
+                });
+            }
+        }
+    }
+    
+    /// Unreachable instructions, functions that run past their end and PUSH/POP balance
+    fn check_control_flow(&mut self, program: &Program) {
+        let cfg = Cfg::build(program);
+        let instruction_span = |index: usize| program.items[index].span;
+        
+        let unreachable = cfg.unreachable();
+        for (i, &id) in unreachable.iter().enumerate() {
+            // One warning per run of consecutive unreachable blocks
+            if i > 0 && unreachable[i - 1] + 1 == id {
+                continue;
+            }
+            let count = unreachable[i..].iter().enumerate()
+                .take_while(|&(offset, &next)| next == id + offset)
+                .map(|(_, &next)| cfg.block(next).instructions.len())
+                .sum();
+            let block = cfg.block(id);
+            let after = id.checked_sub(1)
+                .map(|previous| cfg.block(previous))
+                .filter(|previous| !previous.successors.iter().any(|&(_, kind)| kind == EdgeKind::FallThrough))
+                .map(|previous| instruction_span(previous.last()));
+            self.warn(SemanticWarning::UnreachableCode {
+                span: instruction_span(block.instructions[0]),
+                count,
+                after,
+            });
+        }
+        
+        // A function running off its last instruction lands in the code of the next
+        // function, or in data when the program has any (data follows the text section)
+        let has_data = program.items.iter().any(|item| matches!(&item.node, Item::Directive(d) if cfg::emits_data(d)));
+        let mut reported = std::collections::HashSet::new();
+        for function in cfg.functions() {
+            for &id in &function.blocks {
+                if !reported.insert(id) {
+                    continue;
+                }
+                let block = cfg.block(id);
+                let into_function = block.successors.iter()
+                    .filter(|&&(_, kind)| kind == EdgeKind::FallThrough)
+                    .find_map(|&(next, _)| cfg.functions().iter().find(|f| f.entry == next && f.entry != function.entry));
+                let into = match into_function {
+                    Some(next) => Some(next.name.clone()),
+                    None if has_data && block.exits.contains(&Exit::FallOff) => None,
+                    None => continue,
+                };
+                self.warn(SemanticWarning::FallThrough {
+                    function: function.name.clone(),
+                    into,
+                    span: instruction_span(block.last()),
+                });
+            }
+        }
+        
+        for warning in StackAnalysis::analyze(&cfg, program).warnings() {
+            self.warn(warning.clone());
+        }
+        for warning in Dataflow::analyze(&cfg, program).warnings() {
+            self.warn(warning.clone());
+        }
+        for warning in KeyAnalysis::analyze(&cfg, program).warnings() {
+            self.warn(warning.clone());
+        }
+    }
+    
     fn check_mixed_directives_and_instructions(&mut self, program: &Program) {
         // Split program into sections (delimited by labels)
         let mut sections = Vec::new();
@@ -233,20 +413,23 @@ impl SemanticChecker {
         for section in sections {
             let mut has_directive = false;
             let mut has_instruction = false;
-            let mut error_span = (0, 0);
+            let mut error_span = Span::new(0, 0);
             
             for item in section {
                 match &item.node {
+                    // Constant definitions and ignored directives emit nothing,
+                    // so they may sit among instructions
+                    Item::Directive(sayo_ast::Directive::Equ(..) | sayo_ast::Directive::Unknown { .. }) => {}
                     Item::Directive(_) => {
                         has_directive = true;
                         if !has_instruction {
-                            error_span = (item.span.start, item.span.end);
+                            error_span = item.span;
                         }
                     }
                     Item::Instruction(_) => {
                         has_instruction = true;
                         if !has_directive {
-                            error_span = (item.span.start, item.span.end);
+                            error_span = item.span;
                         }
                     }
                     _ => {}
@@ -255,8 +438,7 @@ impl SemanticChecker {
                 // If both are present, report error
                 if has_directive && has_instruction {
                     self.errors.push(SemanticError::MixedDirectivesAndInstructions {
-                        line: error_span.0,
-                        col: error_span.1,
+                        span: error_span,
                     });
                     break; // Only report once per section
                 }
@@ -274,7 +456,8 @@ impl Default for SemanticChecker {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use sayo_ast::{Program, Item, Instruction, Operand, Mnemonic, Register, Spanned, Span};
+    use sayo_ast::{Program, Item, Instruction, Directive, Color, HidCode, Operand, Mnemonic, Register, Spanned, Span};
+    use crate::Level;
     
     #[test]
     fn test_immediate_in_range() {
@@ -478,5 +661,159 @@ mod tests {
             assert!(errors.iter().any(|e| matches!(e, SemanticError::WriteToReadOnlyRegister { .. })));
         }
     }
-}
 
+    #[test]
+    fn test_unknown_directive_warning() {
+        let mut checker = SemanticChecker::new();
+        let program = Program {
+            items: vec![
+                Spanned::new(
+                    Item::Directive(Directive::Unknown {
+                        name: "cfi_startproc".to_string(),
+                        args: String::new(),
+                    }),
+                    Span::new(3, 17),
+                )
+            ],
+        };
+
+        assert!(checker.check(&program).is_ok());
+        assert_eq!(checker.warnings().len(), 1);
+        assert!(matches!(
+            &checker.warnings()[0],
+            SemanticWarning::UnknownDirective { name, span, .. } if *span == Span::new(3, 17) && name == "cfi_startproc"
+        ));
+    }
+    
+    #[test]
+    fn test_hid_namespace_mismatch() {
+        let press = |mnemonic: Mnemonic, name: &str, at: usize| Spanned::new(
+            Item::Instruction(Instruction {
+                mnemonic,
+                operands: vec![Spanned::new(Operand::Hid(HidCode::from_name(name).unwrap()), Span::new(at + 9, at + 15))],
+                encoding: None,
+            }),
+            Span::new(at, at + 15),
+        );
+        let mut checker = SemanticChecker::new();
+        let program = Program {
+            items: vec![
+                press(Mnemonic::PRESS_GK, "KEY_A", 0),
+                press(Mnemonic::PRESS_SK, "MOD_LSHIFT", 20),
+                press(Mnemonic::PRESS_GK, "MEDIA_VOL_UP", 40),
+            ],
+        };
+        
+        let errors = checker.check(&program).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            &errors[0],
+            SemanticError::HidNamespaceMismatch { name, expected, span, .. } if span.start == 49 && name == "MEDIA_VOL_UP" && expected == "keyboard key"
+        ));
+    }
+
+    #[test]
+    fn test_color_operand_placement() {
+        let with_color = |mnemonic: Mnemonic, at: usize| Spanned::new(
+            Item::Instruction(Instruction {
+                mnemonic,
+                operands: vec![Spanned::new(Operand::Color(Color::new(255, 128, 0)), Span::new(at + 8, at + 15))],
+                encoding: None,
+            }),
+            Span::new(at, at + 15),
+        );
+        let mut checker = SemanticChecker::new();
+        let program = Program {
+            items: vec![with_color(Mnemonic::LED_COL, 0), with_color(Mnemonic::SLEEP, 20)],
+        };
+        
+        let errors = checker.check(&program).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(&errors[0], SemanticError::InvalidOperandType { span, .. } if span.start == 28));
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let mut checker = SemanticChecker::new();
+        let program = Program {
+            items: vec![
+                Spanned::new(Item::Label("main".to_string()), Span::new(0, 5)),
+                Spanned::new(Item::Directive(Directive::Unknown { name: "cfi_endproc".to_string(), args: String::new() }), Span::new(6, 18)),
+                Spanned::new(Item::Label("main".to_string()), Span::new(19, 24)),
+            ],
+        };
+        
+        assert!(checker.check(&program).is_err());
+        let diagnostics = checker.diagnostics();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!((diagnostics[0].code, diagnostics[0].span), ("E0107", Some(Span::new(19, 24))));
+        assert_eq!(diagnostics[0].labels[0].span, Span::new(0, 5));
+        assert_eq!((diagnostics[1].code, diagnostics[1].severity), ("W0101", sayo_ast::Severity::Warning));
+    }
+
+    #[test]
+    fn test_lint_levels() {
+        let text = "main:\n    .weak a\n    .weak b ; sayo: deny(unknown-directive)\n    .weak c ; sayo: allow(unknown-directive)\n";
+        let program = sayo_parser::parse(text).unwrap();
+        let mut lints = LintConfig::new();
+        lints.read_inline(&sayo_ast::SourceMap::with_root("main.s", text));
+        let mut checker = SemanticChecker::new().with_lints(lints);
+        
+        let errors = checker.check(&program).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(&errors[0], SemanticError::DeniedLint(SemanticWarning::UnknownDirective { span, .. }) if span.start == text.find(".weak b").unwrap()));
+        assert_eq!(checker.warnings().len(), 1);
+        let diagnostics = checker.diagnostics();
+        assert_eq!((diagnostics[0].code, diagnostics[0].severity), ("W0101", sayo_ast::Severity::Error));
+        assert_eq!(diagnostics[1].severity, sayo_ast::Severity::Warning);
+    }
+
+    #[test]
+    fn test_width_mismatch() {
+        let text = "main:\n    MOV32 V0, 0x12345678\n    MOV8 R0, 1\n    MOV16 ALL_LED_COL, 0xFF\n    MOV32 ALL_LED_COL, #00FF00\n\
+                    MOV8 *R0_16B, 1\n    MOVSX16b R0, V1\n    ADD8 R1, 1\n    MOV16SX R2, -1\n    MOV V0, R3\n";
+        let program = sayo_parser::parse(text).unwrap();
+        // R1 and R3 are read without being set
+        let mut lints = LintConfig::new();
+        lints.set("read-before-write", Level::Allow).unwrap();
+        let mut checker = SemanticChecker::new().with_lints(lints);
+        
+        assert!(checker.check(&program).is_ok());
+        let found: Vec<_> = checker.warnings().iter()
+            .map(|w| match w {
+                SemanticWarning::WidthMismatch { register, instruction_bits, register_bits, destination, .. } => {
+                    (register.as_str(), *instruction_bits, *register_bits, *destination)
+                }
+                _ => panic!("unexpected warning {:?}", w),
+            })
+            .collect();
+        assert_eq!(found, vec![
+            ("V0", 32, 8, true),
+            ("R0", 8, 32, true),
+            ("ALL_LED_COL", 16, 24, true),
+            ("*R0_16b", 8, 16, true),
+            ("V1", 16, 8, false),
+        ]);
+        assert_eq!(checker.warnings()[0].span().start, text.find("V0").unwrap());
+        assert_eq!(checker.diagnostics()[0].notes[0], "the value is truncated to 8 bits");
+    }
+
+    #[test]
+    fn test_control_flow_lints() {
+        let text = "main:\n    CLR R0\n    CLR R1\n    CALL helper\n    CALL next\n    EXIT\n    NOP\n    NOP\nhelper:\n    JZ R0, .done\n    INC R0\n\
+                    .done:\n    DEC R1\nnext:\n    CALL last\n    RET\nlast:\n    NOP\ntable:\n    .byte 1\n";
+        let program = sayo_parser::parse(text).unwrap();
+        let mut checker = SemanticChecker::new();
+        
+        assert!(checker.check(&program).is_ok());
+        let warnings = checker.warnings();
+        assert_eq!(warnings.len(), 3);
+        assert!(matches!(&warnings[0], SemanticWarning::UnreachableCode { span, count: 2, after: Some(after) }
+            if span.start == text.find("NOP").unwrap() && after.start == text.find("EXIT").unwrap()));
+        assert!(matches!(&warnings[1], SemanticWarning::FallThrough { function, into: Some(into), span }
+            if function == "helper" && into == "next" && span.start == text.find("DEC").unwrap()));
+        assert!(matches!(&warnings[2], SemanticWarning::FallThrough { function, into: None, .. } if function == "last"));
+        assert_eq!(warnings[1].to_string(), "helper falls through into next");
+        assert_eq!(warnings[2].to_string(), "last falls through into data");
+    }
+}
@@ -0,0 +1,357 @@
+/// Register dataflow
+///
+/// Liveness and reaching definitions of the general purpose registers `R0`-`R15` over a
+/// `Cfg`. `DPTR`, `A` and `B` share storage with `R4`, `R6` and `R7`, and an indirect
+/// register (`*R2_16b`) reads the register holding its address. `V0`-`V3` carry key
+/// parameters and the system and `GL_n` registers are set outside the script, so they are
+/// not tracked. Which registers an instruction reads and writes, named or implicit
+/// (`MUL_A` reads `A` and `B`), comes from its metadata.
+///
+/// Each function is summarised by the registers it reads before writing (its inputs) and
+/// the registers it may write; a `CALL` reads the callee's inputs and may write the rest.
+/// Two checks use the summaries:
+/// - registers the program entry reads before writing them on some path; calls count as
+///   writes only, since callees also read the registers they save and restore
+/// - writes whose value is overwritten on every path before it is read; every register
+///   counts as read where a function returns or exits, so only overwrites are reported
+use std::collections::HashMap;
+use std::ops::{BitAnd, BitOr, Sub};
+use sayo_ast::{Instruction, Item, Mnemonic, Operand, Program, Register, Span};
+use crate::cfg::{BasicBlock, BlockId, Cfg, Function};
+use crate::SemanticWarning;
+
+pub(crate) const GENERAL: [Register; 16] = [
+    Register::R0, Register::R1, Register::R2, Register::R3, Register::R4, Register::R5, Register::R6, Register::R7,
+    Register::R8, Register::R9, Register::R10, Register::R11, Register::R12, Register::R13, Register::R14, Register::R15,
+];
+
+/// Set of general purpose registers, bit n for Rn
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Registers(u16);
+
+impl Registers {
+    const NONE: Self = Self(0);
+    const ALL: Self = Self(u16::MAX);
+
+    /// The storage of `reg`, or nothing if it is not a general purpose register
+    fn of(reg: &Register) -> Self {
+        reg.storage()
+            .and_then(|storage| GENERAL.iter().position(|&general| general == storage))
+            .map_or(Self::NONE, |index| Self(1 << index))
+    }
+
+    fn iter(self) -> impl Iterator<Item = Register> {
+        GENERAL.into_iter().enumerate().filter(move |&(index, _)| self.0 & (1 << index) != 0).map(|(_, reg)| reg)
+    }
+}
+
+impl BitOr for Registers {
+    type Output = Self;
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl BitAnd for Registers {
+    type Output = Self;
+    fn bitand(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl Sub for Registers {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+
+/// What an instruction does to the registers
+#[derive(Debug, Clone, Copy, Default)]
+struct Effect {
+    reads: Registers,
+    /// Written wholly or in part
+    writes: Registers,
+    /// Written wholly: the previous value is gone
+    kills: Registers,
+}
+
+fn operand_effect(instr: &Instruction) -> Effect {
+    let metadata = instr.mnemonic.metadata();
+    // Only the low bits are replaced; LI is lowered to a form that sets the whole register
+    let partial = matches!(instr.mnemonic, Mnemonic::MOV8 | Mnemonic::MOV16);
+    let mut effect = Effect::default();
+    for (position, operand) in instr.operands.iter().enumerate() {
+        let Operand::Register(reg) = &operand.node else {
+            continue;
+        };
+        if let Some(address) = reg.address_register() {
+            effect.reads = effect.reads | Registers::of(&address);
+            continue;
+        }
+        let registers = Registers::of(reg);
+        let Some(def) = metadata.operands.get(position) else {
+            effect.reads = effect.reads | registers;
+            continue;
+        };
+        if def.is_read {
+            effect.reads = effect.reads | registers;
+        }
+        if def.is_write {
+            effect.writes = effect.writes | registers;
+            if !def.is_read && !partial {
+                effect.kills = effect.kills | registers;
+            }
+        }
+    }
+    for reg in &metadata.implicit_reads {
+        effect.reads = effect.reads | Registers::of(reg);
+    }
+    for reg in &metadata.implicit_writes {
+        effect.writes = effect.writes | Registers::of(reg);
+        effect.kills = effect.kills | Registers::of(reg);
+    }
+    effect
+}
+
+/// Register summaries of the functions of a program and the problems found
+#[derive(Debug, Clone, Default)]
+pub struct Dataflow {
+    /// Registers each function reads before writing them, in `Cfg::functions` order
+    inputs: Vec<Registers>,
+    /// Registers each function may write, including through its callees
+    clobbers: Vec<Registers>,
+    /// Function entered at each entry block
+    functions: HashMap<BlockId, usize>,
+    warnings: Vec<SemanticWarning>,
+}
+
+impl Dataflow {
+    pub fn analyze(cfg: &Cfg, program: &Program) -> Self {
+        let count = cfg.functions().len();
+        let mut analysis = Self {
+            inputs: vec![Registers::NONE; count],
+            clobbers: vec![Registers::NONE; count],
+            functions: cfg.functions().iter().enumerate().map(|(index, function)| (function.entry, index)).collect(),
+            warnings: Vec::new(),
+        };
+
+        // Summaries only grow, so repeating until nothing changes settles recursive calls
+        loop {
+            let mut changed = false;
+            for (index, function) in cfg.functions().iter().enumerate() {
+                let inputs = analysis.liveness(cfg, program, function, Registers::NONE, true)[&function.entry];
+                let clobbers = function.blocks.iter()
+                    .flat_map(|&id| cfg.block(id).instructions.iter().map(move |&at| (id, at)))
+                    .fold(Registers::NONE, |clobbers, (id, at)| clobbers | analysis.effect(cfg.block(id), program, at).writes);
+                if (inputs, clobbers) != (analysis.inputs[index], analysis.clobbers[index]) {
+                    analysis.inputs[index] = inputs;
+                    analysis.clobbers[index] = clobbers;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        if let Some(entry) = cfg.functions().first() {
+            analysis.check_reads(cfg, program, entry);
+        }
+        for function in cfg.functions() {
+            analysis.check_stores(cfg, program, function);
+        }
+        analysis
+    }
+
+    /// Registers a function reads before writing them: its register parameters
+    pub fn inputs(&self, function: usize) -> Vec<Register> {
+        self.inputs[function].iter().collect()
+    }
+
+    /// Registers a function or its callees may write
+    pub fn clobbers(&self, function: usize) -> Vec<Register> {
+        self.clobbers[function].iter().collect()
+    }
+
+    pub fn warnings(&self) -> &[SemanticWarning] {
+        &self.warnings
+    }
+
+    fn warn(&mut self, warning: SemanticWarning) {
+        if !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
+        }
+    }
+
+    fn effect(&self, block: &BasicBlock, program: &Program, index: usize) -> Effect {
+        let Item::Instruction(instr) = &program.items[index].node else {
+            return Effect::default();
+        };
+        if instr.mnemonic != Mnemonic::CALL {
+            return operand_effect(instr);
+        }
+        let callee = block.calls.iter()
+            .find(|&&(at, _)| at == index)
+            .and_then(|&(_, callee)| callee)
+            .and_then(|callee| self.functions.get(&callee).copied());
+        match callee {
+            Some(callee) => Effect {
+                reads: self.inputs[callee],
+                writes: self.clobbers[callee],
+                kills: Registers::NONE,
+            },
+            // A constant address: anything may be read or written
+            None => Effect { reads: Registers::ALL, writes: Registers::ALL, kills: Registers::NONE },
+        }
+    }
+
+    /// Registers live on entry to each block of `function`, with `exit` live wherever
+    /// the function leaves the graph; `partial` counts `MOV8`/`MOV16` as setting the
+    /// register rather than keeping its upper bits alive
+    fn liveness(&self, cfg: &Cfg, program: &Program, function: &Function, exit: Registers, partial: bool) -> HashMap<BlockId, Registers> {
+        let mut live_in = HashMap::new();
+        loop {
+            let mut changed = false;
+            for &id in function.blocks.iter().rev() {
+                let block = cfg.block(id);
+                let live = block.instructions.iter().rev()
+                    .fold(self.live_out(cfg, id, &live_in, exit), |live, &index| {
+                        let effect = self.effect(block, program, index);
+                        effect.reads | (live - if partial { effect.writes } else { effect.kills })
+                    });
+                changed |= live_in.insert(id, live) != Some(live);
+            }
+            if !changed {
+                return live_in;
+            }
+        }
+    }
+
+    fn live_out(&self, cfg: &Cfg, id: BlockId, live_in: &HashMap<BlockId, Registers>, exit: Registers) -> Registers {
+        let block = cfg.block(id);
+        let leaves = if block.exits.is_empty() { Registers::NONE } else { exit };
+        cfg.successors(id).fold(leaves, |live, successor| live | live_in.get(&successor).copied().unwrap_or_default())
+    }
+
+    /// Report registers the program entry reads before writing them on some path
+    fn check_reads(&mut self, cfg: &Cfg, program: &Program, entry: &Function) {
+        // Registers written on every path to the end of each block
+        let mut written: HashMap<BlockId, Registers> = HashMap::new();
+        let written_on_entry = |id: BlockId, written: &HashMap<BlockId, Registers>| {
+            if id == entry.entry {
+                return Registers::NONE;
+            }
+            cfg.predecessors(id).iter()
+                .filter(|predecessor| entry.blocks.binary_search(predecessor).is_ok())
+                .fold(Registers::ALL, |common, predecessor| common & written.get(predecessor).copied().unwrap_or(Registers::ALL))
+        };
+        loop {
+            let mut changed = false;
+            for &id in &entry.blocks {
+                let block = cfg.block(id);
+                let out = block.instructions.iter()
+                    .fold(written_on_entry(id, &written), |set, &index| set | self.effect(block, program, index).writes);
+                changed |= written.insert(id, out) != Some(out);
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        for &id in &entry.blocks {
+            let block = cfg.block(id);
+            let mut set = written_on_entry(id, &written);
+            for &index in &block.instructions {
+                let effect = self.effect(block, program, index);
+                let call = matches!(&program.items[index].node, Item::Instruction(instr) if instr.mnemonic == Mnemonic::CALL);
+                if !call {
+                    for register in (effect.reads - set).iter() {
+                        self.warn(SemanticWarning::ReadBeforeWrite {
+                            register: register.to_string(),
+                            span: program.items[index].span,
+                        });
+                    }
+                }
+                set = set | effect.writes;
+            }
+        }
+    }
+
+    /// Report writes overwritten on every path before they are read
+    fn check_stores(&mut self, cfg: &Cfg, program: &Program, function: &Function) {
+        let live_in = self.liveness(cfg, program, function, Registers::ALL, false);
+        for &id in &function.blocks {
+            let block = cfg.block(id);
+            let mut live = self.live_out(cfg, id, &live_in, Registers::ALL);
+            for (position, &index) in block.instructions.iter().enumerate().rev() {
+                let effect = self.effect(block, program, index);
+                let stores = match &program.items[index].node {
+                    // POP also restores the stack; CALL clobbers without storing anything useful
+                    Item::Instruction(instr) if matches!(instr.mnemonic, Mnemonic::POP | Mnemonic::CALL) => Registers::NONE,
+                    _ => effect.writes,
+                };
+                for register in (stores - live).iter() {
+                    let overwritten = self.overwritten_at(block, program, position, register);
+                    self.warn(SemanticWarning::DeadStore {
+                        register: register.to_string(),
+                        span: program.items[index].span,
+                        overwritten,
+                    });
+                }
+                live = effect.reads | (live - effect.kills);
+            }
+        }
+    }
+
+    /// The instruction after `position` in `block` that overwrites `register`, if any
+    fn overwritten_at(&self, block: &BasicBlock, program: &Program, position: usize, register: Register) -> Option<Span> {
+        let registers = Registers::of(&register);
+        block.instructions[position + 1..].iter()
+            .find(|&&index| self.effect(block, program, index).kills & registers != Registers::NONE)
+            .map(|&index| program.items[index].span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyze(text: &str) -> Dataflow {
+        let program = sayo_parser::parse(text).unwrap();
+        Dataflow::analyze(&Cfg::build(&program), &program)
+    }
+
+    #[test]
+    fn test_summaries() {
+        let analysis = analyze("main:\n    MOV8 R0, 1\n    CALL add\n    EXIT\n\
+                                add:\n    ADD_R R2, R0, R1\n    INC A\n    MOV R1, *DPTR\n    RET\n");
+        // A is R6; *DPTR reads the address in R4
+        assert_eq!(analysis.inputs(1), vec![Register::R0, Register::R1, Register::R4, Register::R6]);
+        assert_eq!(analysis.clobbers(1), vec![Register::R1, Register::R2, Register::R6]);
+        assert_eq!(analysis.clobbers(0), vec![Register::R0, Register::R1, Register::R2, Register::R6]);
+    }
+
+    #[test]
+    fn test_read_before_write() {
+        let analysis = analyze("main:\n    JZ V0, .skip_init\n    MOV8 R1, 2\n.skip_init:\n    ADD_R R0, R1, R1\n    CALL f\n    PRINT_REG R2\n    EXIT\n\
+                                f:\n    PUSH B\n    MOV8 R2, 1\n    POP B\n    RET\n");
+        let warnings = analysis.warnings();
+        // R1 is only set on one path into .skip_init; V0 is a parameter, f saves B itself and sets R2
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(&warnings[0], SemanticWarning::ReadBeforeWrite { register, .. } if register == "R1"));
+        assert_eq!(analysis.inputs(1), vec![Register::R7]);
+    }
+
+    #[test]
+    fn test_dead_store() {
+        let text = "main:\n    MOV32 R0, 1\n    MOV32 R0, 2\n    MOV8 R1, 1\n    JZ R0, .out\n    MOV R1, R0\n.out:\n    RET\n\
+                    loop:\n    MOV32 A, 0\n    MOV32 R6, 1\n    DJNZ R6, loop\n    RET\n";
+        let analysis = analyze(&text.replace("loop:", ".type loop, @function\nloop:"));
+        let warnings = analysis.warnings();
+        assert_eq!(warnings.len(), 2);
+        assert!(matches!(&warnings[0], SemanticWarning::DeadStore { register, overwritten: Some(_), .. } if register == "R0"));
+        // A is R6, overwritten before the loop reads it
+        assert!(matches!(&warnings[1], SemanticWarning::DeadStore { register, .. } if register == "R6"));
+    }
+}
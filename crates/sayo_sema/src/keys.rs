@@ -0,0 +1,295 @@
+/// Press/release pairing
+///
+/// Follows the keys a script holds down on the host through the `Cfg`, from the program
+/// entry and into every call with the keys held at the call. `PRESS_*` holds a key and
+/// `RELEASE_*` lets it go. The `_VAL` forms are followed when the register holds a
+/// constant loaded earlier in the same function (`MOV8 R0, 0x04`), and key codes may be
+/// named by `.equ` constants; a press of an unknown code is ignored and a release of one
+/// lets go of every key of its kind.
+///
+/// A key still held where the script ends (`END`, `EXIT`, `EXIT_IF_*`, `JMP_TO_SCRIPT`,
+/// a `RET` from the program entry or running off the end) stays down on the host. It is
+/// reported at that exit together with the press that left it down.
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use sayo_ast::{Constants, HidNamespace, Item, KeyAction, Mnemonic, Operand, Program, Register};
+use crate::cfg::{BasicBlock, BlockId, Cfg, Exit};
+use crate::dataflow::GENERAL;
+use crate::SemanticWarning;
+
+/// A key by kind and code
+type Key = (HidNamespace, u8);
+
+/// Keys that may be held, each with the presses (item indices) that may have left it down
+type Held = BTreeMap<Key, BTreeSet<usize>>;
+
+/// What is known at a point of a function
+#[derive(Debug, Clone, PartialEq)]
+struct State {
+    held: Held,
+    /// Low byte of each general purpose register, where constant
+    constants: [Option<u8>; 16],
+}
+
+impl State {
+    /// Merge in the state of another path; returns whether anything changed
+    fn join(&mut self, other: &State) -> bool {
+        let before = self.clone();
+        for (key, presses) in &other.held {
+            self.held.entry(*key).or_default().extend(presses);
+        }
+        for (constant, other) in self.constants.iter_mut().zip(other.constants) {
+            if *constant != other {
+                *constant = None;
+            }
+        }
+        *self != before
+    }
+
+    /// Value of an operand of item `index`, where constant; key codes are a byte, so only
+    /// the low byte
+    fn value(&self, operand: &Operand, constants: &Constants, index: usize) -> Option<u8> {
+        match operand {
+            Operand::Immediate(value) => Some(*value as u8),
+            Operand::Hid(code) => Some(code.code),
+            Operand::Label(name) => constants.value_at(name, index).map(|value| value as u8),
+            Operand::Expr(expr) => expr.eval(&|name| constants.value_at(name, index)).ok().map(|value| value as u8),
+            Operand::Register(reg) => self.constants[general(reg)?],
+            _ => None,
+        }
+    }
+}
+
+/// Index of the general purpose register holding `reg`
+fn general(reg: &Register) -> Option<usize> {
+    let storage = reg.storage()?;
+    GENERAL.iter().position(|&general| general == storage)
+}
+
+fn key_name((namespace, code): Key) -> String {
+    namespace.name_of(code).map_or_else(|| format!("{} {:#04x}", namespace, code), str::to_string)
+}
+
+/// Keys left pressed where the script ends
+#[derive(Debug, Clone, Default)]
+pub struct KeyAnalysis {
+    /// Keys held when a function returns, by function and the keys held at the call;
+    /// None while the function is being walked
+    returns: HashMap<(usize, Held), Option<Held>>,
+    /// Values of the constants, for key codes given by name
+    constants: Constants,
+    warnings: Vec<SemanticWarning>,
+}
+
+impl KeyAnalysis {
+    pub fn analyze(cfg: &Cfg, program: &Program) -> Self {
+        let mut analysis = Self { constants: Constants::evaluate_known(program), ..Self::default() };
+        if !cfg.functions().is_empty() {
+            analysis.walk(cfg, program, 0, Held::new());
+        }
+        analysis
+    }
+
+    pub fn warnings(&self) -> &[SemanticWarning] {
+        &self.warnings
+    }
+
+    /// Report once: a function called with the same keys held is walked again
+    fn warn(&mut self, warning: SemanticWarning) {
+        if !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
+        }
+    }
+
+    /// Keys held when `function` returns, called with `held`
+    fn call(&mut self, cfg: &Cfg, program: &Program, function: usize, held: Held) -> Held {
+        match self.returns.get(&(function, held.clone())) {
+            Some(Some(returned)) => return returned.clone(),
+            // A recursive call: take it to leave the keys as they are
+            Some(None) => return held,
+            None => {}
+        }
+        self.returns.insert((function, held.clone()), None);
+        let returned = self.walk(cfg, program, function, held.clone());
+        self.returns.insert((function, held), Some(returned.clone()));
+        returned
+    }
+
+    /// Walk the blocks of a function from its entry until the states settle, reporting
+    /// the exits reached with keys held; returns the keys held where it returns
+    fn walk(&mut self, cfg: &Cfg, program: &Program, function: usize, held: Held) -> Held {
+        let entry = cfg.functions()[function].entry;
+        let mut states: HashMap<BlockId, State> = HashMap::from([(entry, State { held, constants: [None; 16] })]);
+        let mut worklist = vec![entry];
+        let mut returned = Held::new();
+
+        while let Some(id) = worklist.pop() {
+            let block = cfg.block(id);
+            let mut state = states[&id].clone();
+            for &index in &block.instructions {
+                self.step(cfg, program, block, index, &mut state);
+            }
+
+            // The program entry returning ends the script
+            let ends = block.exits.iter().any(|exit| match exit {
+                Exit::Exit | Exit::FallOff => true,
+                Exit::Return => function == 0,
+                Exit::Indirect => false,
+            });
+            if ends {
+                let span = program.items[block.last()].span;
+                for (&key, presses) in &state.held {
+                    for &press in presses {
+                        self.warn(SemanticWarning::UnreleasedKey {
+                            key: key_name(key),
+                            span,
+                            pressed: program.items[press].span,
+                        });
+                    }
+                }
+            } else if block.exits.contains(&Exit::Return) {
+                for (key, presses) in &state.held {
+                    returned.entry(*key).or_default().extend(presses);
+                }
+            }
+
+            for successor in cfg.successors(id) {
+                match states.get_mut(&successor) {
+                    None => {
+                        states.insert(successor, state.clone());
+                        worklist.push(successor);
+                    }
+                    Some(existing) => {
+                        if existing.join(&state) {
+                            worklist.push(successor);
+                        }
+                    }
+                }
+            }
+        }
+        returned
+    }
+
+    fn step(&mut self, cfg: &Cfg, program: &Program, block: &BasicBlock, index: usize, state: &mut State) {
+        let Item::Instruction(instr) = &program.items[index].node else {
+            return;
+        };
+
+        if let Some((namespace, action)) = instr.mnemonic.key_action() {
+            let code = instr.operands.first().and_then(|op| state.value(&op.node, &self.constants, index));
+            match (action, code) {
+                (KeyAction::Press, Some(code)) => {
+                    state.held.insert((namespace, code), BTreeSet::from([index]));
+                }
+                (KeyAction::Press, None) => {}
+                (KeyAction::Release, Some(code)) => {
+                    state.held.remove(&(namespace, code));
+                }
+                // Could be any key of its kind
+                (KeyAction::Release, None) => state.held.retain(|&(held, _), _| held != namespace),
+            }
+            return;
+        }
+
+        if instr.mnemonic == Mnemonic::CALL {
+            let callee = block.calls.iter()
+                .find(|&&(at, _)| at == index)
+                .and_then(|&(_, callee)| callee)
+                .and_then(|callee| cfg.functions().iter().position(|f| f.entry == callee));
+            if let Some(callee) = callee {
+                state.held = self.call(cfg, program, callee, state.held.clone());
+            }
+            // The callee may change any register
+            state.constants = [None; 16];
+            return;
+        }
+
+        // Track the registers holding constants: loads set them, any other write forgets them
+        let loaded = match instr.mnemonic {
+            Mnemonic::CLR => Some(0),
+            Mnemonic::MOV | Mnemonic::MOV8 | Mnemonic::MOV16 | Mnemonic::MOV32
+            | Mnemonic::MOV8SX | Mnemonic::MOV16SX | Mnemonic::LI => {
+                instr.operands.get(1).and_then(|op| state.value(&op.node, &self.constants, index))
+            }
+            _ => None,
+        };
+        let metadata = instr.mnemonic.metadata();
+        let written = instr.operands.iter()
+            .zip(&metadata.operands)
+            .filter(|(_, def)| def.is_write)
+            .filter_map(|(op, _)| match &op.node {
+                Operand::Register(reg) if reg.address_register().is_none() => Some(reg),
+                _ => None,
+            })
+            .chain(&metadata.implicit_writes);
+        for reg in written.collect::<Vec<_>>() {
+            if let Some(general) = general(reg) {
+                state.constants[general] = loaded;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyze(text: &str) -> KeyAnalysis {
+        let program = sayo_parser::parse(text).unwrap();
+        KeyAnalysis::analyze(&Cfg::build(&program), &program)
+    }
+
+    #[test]
+    fn test_unreleased_key() {
+        let text = "main:\n    PRESS_GK KEY_A\n    PRESS_MK MOUSE_LEFT\n    SLEEP 10\n    RELEASE_MK MOUSE_LEFT\n    EXIT\n";
+        let analysis = analyze(text);
+        assert_eq!(analysis.warnings().len(), 1);
+        let SemanticWarning::UnreleasedKey { key, span, pressed } = &analysis.warnings()[0] else {
+            panic!("expected an unreleased key");
+        };
+        assert_eq!(key, "KEY_A");
+        assert!(text[span.start..].starts_with("EXIT"));
+        assert!(text[pressed.start..].starts_with("PRESS_GK KEY_A"));
+
+        assert!(analyze("main:\n    PRESS_GK KEY_A\n    RELEASE_GK KEY_A\n    EXIT\n").warnings().is_empty());
+    }
+
+    #[test]
+    fn test_paths_and_calls() {
+        // Released on one path only, then held across a call that returns
+        let analysis = analyze("main:\n    PRESS_GK KEY_A\n    JZ R0, .out\n    RELEASE_GK KEY_A\n.out:\n    RET\n");
+        assert_eq!(analysis.warnings().len(), 1);
+        let analysis = analyze("main:\n    CALL down\n    SLEEP 10\n    CALL up\n    EXIT\n\
+                                down:\n    PRESS_GK KEY_B\n    RET\n\
+                                up:\n    RELEASE_GK KEY_B\n    RET\n");
+        assert!(analysis.warnings().is_empty());
+        let analysis = analyze("main:\n    CALL down\n    EXIT\ndown:\n    PRESS_GK KEY_B\n    RET\n");
+        assert!(matches!(analysis.warnings(), [SemanticWarning::UnreleasedKey { key, .. }] if key == "KEY_B"));
+    }
+
+    #[test]
+    fn test_register_forms() {
+        // KEY_C is 0x06; a press of an unknown code is ignored
+        let analysis = analyze("main:\n    MOV8 R1, 0x06\n    PRESS_GK_VAL R1\n    PRESS_GK_VAL R2\n    EXIT\n");
+        assert!(matches!(analysis.warnings(), [SemanticWarning::UnreleasedKey { key, .. }] if key == "KEY_C"));
+        // A release of an unknown code may release anything of its kind
+        let analysis = analyze("main:\n    PRESS_GK KEY_A\n    PRESS_MK MOUSE_LEFT\n    RELEASE_GK_VAL R2\n    EXIT\n");
+        assert!(matches!(analysis.warnings(), [SemanticWarning::UnreleasedKey { key, .. }] if key == "MOUSE_LEFT"));
+        // The constant is lost once the register is changed
+        let analysis = analyze("main:\n    MOV8 R1, 0x06\n    PRESS_GK_VAL R1\n    INC R1\n    RELEASE_GK_VAL R1\n    EXIT\n");
+        assert!(analysis.warnings().is_empty());
+    }
+
+    #[test]
+    fn test_named_codes() {
+        // KEY_A is 0x04
+        let analysis = analyze(".equ MYKEY, 4\nmain:\n    PRESS_GK MYKEY\n    EXIT\n");
+        assert!(matches!(analysis.warnings(), [SemanticWarning::UnreleasedKey { key, .. }] if key == "KEY_A"));
+        // Through a register and an expression, defined further down
+        let analysis = analyze("main:\n    MOV8 R1, BASE+1\n    PRESS_GK_VAL R1\n    PRESS_GK MYKEY\n    RELEASE_GK_VAL R1\n    EXIT\n\
+                                MYKEY = BASE\nBASE = 4\n");
+        assert!(matches!(analysis.warnings(), [SemanticWarning::UnreleasedKey { key, .. }] if key == "KEY_A"));
+        // A reassigned name holds its value from that point on
+        let analysis = analyze("K = 4\nmain:\n    PRESS_GK K\n    K = 5\n    RELEASE_GK K\n    EXIT\n");
+        assert!(matches!(analysis.warnings(), [SemanticWarning::UnreleasedKey { key, .. }] if key == "KEY_A"));
+    }
+}
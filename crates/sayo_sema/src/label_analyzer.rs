@@ -1,5 +1,5 @@
 use std::collections::HashMap;
-use sayo_ast::{Program, Item, Instruction, Operand};
+use sayo_ast::{numeric_label, numeric_label_ref, NumericLabels, Program, Item, Instruction, Operand, Directive, Expr, Span};
 use crate::SemanticError;
 
 /// Label table for tracking label definitions
@@ -9,6 +9,11 @@ pub struct LabelTable {
     global_labels: HashMap<String, u16>,
     /// Local labels map: global_context -> (local_label_name -> address)
     local_labels: HashMap<String, HashMap<String, u16>>,
+    /// Absolute symbols defined with .equ/.set/= (file-wide, no scoping), and whether
+    /// they may be reassigned (.set/=)
+    constants: HashMap<String, bool>,
+    /// Numeric local labels (`1:`), resolved by item index
+    numeric_labels: NumericLabels<u16>,
     /// Current global context for local label binding
     current_global: Option<String>,
 }
@@ -18,13 +23,15 @@ impl LabelTable {
         Self {
             global_labels: HashMap::new(),
             local_labels: HashMap::new(),
+            constants: HashMap::new(),
+            numeric_labels: NumericLabels::new(),
             current_global: None,
         }
     }
     
     /// Add a global label (no leading '.')
     pub fn add_global_label(&mut self, name: String, address: u16) -> Result<(), ()> {
-        if self.global_labels.contains_key(&name) {
+        if self.global_labels.contains_key(&name) || self.constants.contains_key(&name) {
             return Err(());
         }
         self.global_labels.insert(name.clone(), address);
@@ -49,8 +56,39 @@ impl LabelTable {
         Ok(())
     }
     
+    /// Add a numeric local label (`1:`) defined by item `index`
+    /// Numeric labels may be redefined any number of times
+    pub fn add_numeric_label(&mut self, number: u64, index: usize, address: u16) {
+        self.numeric_labels.define(number, index, address);
+    }
+    
+    /// Add a constant (.equ/.set/=); `.set` and `=` may assign a `.set`/`=` constant again
+    /// Returns false if the name is a global label or a constant that cannot be redefined
+    pub fn add_constant(&mut self, name: String, reassignable: bool) -> bool {
+        if self.global_labels.contains_key(&name) {
+            return false;
+        }
+        match self.constants.get(&name) {
+            Some(&previous) => previous && reassignable,
+            None => {
+                self.constants.insert(name, reassignable);
+                true
+            }
+        }
+    }
+    
+    /// Check if a name is a constant
+    pub fn is_constant(&self, name: &str) -> bool {
+        self.constants.contains_key(name)
+    }
+    
     /// Resolve a label reference
+    /// Constants have no address and resolve to 0
     pub fn resolve(&self, name: &str) -> Result<u16, ResolveError> {
+        if self.constants.contains_key(name) {
+            return Ok(0);
+        }
+        
         if name.starts_with('.') {
             // Local label - must have current global context
             let global_context = match &self.current_global {
@@ -73,6 +111,15 @@ impl LabelTable {
         }
     }
     
+    /// Resolve a label reference made by item `index`
+    /// `1b` / `1f` resolve to the nearest numeric label before / after the item
+    pub fn resolve_at(&self, name: &str, index: usize) -> Result<u16, ResolveError> {
+        if numeric_label_ref(name).is_some() {
+            return self.numeric_labels.resolve(name, index).ok_or(ResolveError::Undefined);
+        }
+        self.resolve(name)
+    }
+    
     /// Check if a label is defined
     pub fn is_defined(&self, name: &str) -> bool {
         self.resolve(name).is_ok()
@@ -119,37 +166,49 @@ impl LabelAnalyzer {
     /// First pass: collect label definitions and their addresses
     fn first_pass(&mut self, program: &Program) {
         let mut address: u16 = 0;
+        // Where each global label, constant and `global.local` label was first defined
+        let mut definitions: HashMap<String, Span> = HashMap::new();
         
-        for item in &program.items {
+        for (index, item) in program.items.iter().enumerate() {
             match &item.node {
                 Item::Label(label_name) => {
                     let is_local = label_name.starts_with('.');
+                    let key = match (&self.table.current_global, is_local) {
+                        (Some(global), true) => format!("{}{}", global, label_name),
+                        _ => label_name.clone(),
+                    };
+                    let previous = definitions.get(&key).copied();
                     
-                    if is_local {
+                    if let Some(number) = numeric_label(label_name) {
+                        self.table.add_numeric_label(number, index, address);
+                    } else if is_local {
                         // Local label
                         if let Err(()) = self.table.add_local_label(label_name.clone(), address) {
                             if self.table.current_global.is_none() {
                                 self.errors.push(SemanticError::LocalLabelWithoutGlobal {
                                     label: label_name.clone(),
-                                    line: item.span.start,
-                                    col: item.span.end,
+                                    span: item.span,
                                 });
                             } else {
                                 self.errors.push(SemanticError::DuplicateLabel {
                                     label: label_name.clone(),
-                                    line: item.span.start,
-                                    col: item.span.end,
+                                    span: item.span,
+                                    previous,
                                 });
                             }
+                        } else {
+                            definitions.insert(key, item.span);
                         }
                     } else {
                         // Global label
                         if let Err(()) = self.table.add_global_label(label_name.clone(), address) {
                             self.errors.push(SemanticError::DuplicateLabel {
                                 label: label_name.clone(),
-                                line: item.span.start,
-                                col: item.span.end,
+                                span: item.span,
+                                previous,
                             });
+                        } else {
+                            definitions.insert(key, item.span);
                         }
                     }
                 }
@@ -158,8 +217,19 @@ impl LabelAnalyzer {
                     let length = instr.mnemonic.metadata().length as u16;
                     address = address.saturating_add(length);
                 }
+                Item::Directive(Directive::Equ(name, _, reassignable)) => {
+                    if !self.table.add_constant(name.clone(), *reassignable) {
+                        self.errors.push(SemanticError::ConstantRedefinition {
+                            name: name.clone(),
+                            span: item.span,
+                            previous: definitions.get(name).copied(),
+                        });
+                    } else {
+                        definitions.entry(name.clone()).or_insert(item.span);
+                    }
+                }
                 _ => {
-                    // Directives don't contribute to address
+                    // Other directives don't contribute to address
                 }
             }
         }
@@ -170,45 +240,70 @@ impl LabelAnalyzer {
         // Reset current global for second pass
         self.table.current_global = None;
         
-        for item in &program.items {
+        for (index, item) in program.items.iter().enumerate() {
             match &item.node {
                 Item::Label(label_name) => {
                     // Track current global context for local label resolution
-                    if !label_name.starts_with('.') {
+                    if !label_name.starts_with('.') && numeric_label(label_name).is_none() {
                         self.table.current_global = Some(label_name.clone());
                     }
                 }
                 Item::Instruction(instr) => {
-                    self.check_instruction_labels(instr);
+                    self.check_instruction_labels(instr, index);
+                }
+                Item::Directive(Directive::Equ(_, expr, _)) => {
+                    self.check_constant_symbols(expr, item.span, index);
                 }
                 _ => {}
             }
         }
     }
     
-    /// Check all label references in an instruction
-    fn check_instruction_labels(&mut self, instr: &Instruction) {
+    /// Check that every symbol used in a constant definition is defined
+    fn check_constant_symbols(&mut self, expr: &Expr, span: Span, index: usize) {
+        for name in expr.symbols() {
+            if self.table.resolve_at(name, index).is_err() {
+                self.errors.push(SemanticError::UndefinedConstant {
+                    name: name.to_string(),
+                    span,
+                });
+            }
+        }
+    }
+    
+    /// Check all label references in instruction `index`
+    fn check_instruction_labels(&mut self, instr: &Instruction, index: usize) {
         for operand in &instr.operands {
-            if let Operand::Label(label_name) = &operand.node {
-                match self.table.resolve(label_name) {
-                    Ok(_address) => {
-                        // Label is defined, valid
-                    }
-                    Err(ResolveError::Undefined) => {
-                        self.errors.push(SemanticError::UndefinedLabel {
-                            label: label_name.clone(),
-                            line: operand.span.start,
-                            col: operand.span.end,
-                        });
-                    }
-                    Err(ResolveError::NoGlobalContext) => {
-                        self.errors.push(SemanticError::LocalLabelWithoutGlobal {
-                            label: label_name.clone(),
-                            line: operand.span.start,
-                            col: operand.span.end,
-                        });
+            match &operand.node {
+                Operand::Label(label_name) => {
+                    self.check_label_reference(label_name, operand.span, index);
+                }
+                Operand::Expr(expr) => {
+                    for label_name in expr.symbols() {
+                        self.check_label_reference(label_name, operand.span, index);
                     }
                 }
+                _ => {}
+            }
+        }
+    }
+    
+    fn check_label_reference(&mut self, label_name: &str, span: Span, index: usize) {
+        match self.table.resolve_at(label_name, index) {
+            Ok(_address) => {
+                // Label is defined, valid
+            }
+            Err(ResolveError::Undefined) => {
+                self.errors.push(SemanticError::UndefinedLabel {
+                    label: label_name.to_string(),
+                    span,
+                });
+            }
+            Err(ResolveError::NoGlobalContext) => {
+                self.errors.push(SemanticError::LocalLabelWithoutGlobal {
+                    label: label_name.to_string(),
+                    span,
+                });
             }
         }
     }
@@ -223,7 +318,7 @@ impl Default for LabelAnalyzer {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use sayo_ast::{Instruction, Operand, Mnemonic, Span, Spanned};
+    use sayo_ast::{Instruction, Operand, Mnemonic, Span, Spanned, Expr, BinaryOp, Directive};
     
     #[test]
     fn test_global_label() {
@@ -303,7 +398,7 @@ mod tests {
         assert!(result.is_err());
         let errors = result.unwrap_err();
         assert_eq!(errors.len(), 1);
-        assert!(matches!(errors[0], SemanticError::DuplicateLabel { .. }));
+        assert!(matches!(errors[0], SemanticError::DuplicateLabel { previous: Some(previous), .. } if previous == Span::new(0, 5)));
     }
     
     #[test]
@@ -355,4 +450,101 @@ mod tests {
         // Both functions can use the same local label name without conflict
         assert!(analyzer.analyze(&program).is_ok());
     }
+    
+    #[test]
+    fn test_expression_label_references() {
+        let mut analyzer = LabelAnalyzer::new();
+        let program = Program {
+            items: vec![
+                Spanned::new(Item::Label("table".to_string()), Span::new(0, 6)),
+                Spanned::new(
+                    Item::Instruction(Instruction {
+                        mnemonic: Mnemonic::MOV16,
+                        operands: vec![
+                            Spanned::new(Operand::Register(sayo_ast::Register::R0), Span::new(13, 15)),
+                            Spanned::new(
+                                Operand::Expr(Expr::binary(
+                                    BinaryOp::Sub,
+                                    Expr::Symbol("missing".to_string()),
+                                    Expr::Symbol("table".to_string()),
+                                )),
+                                Span::new(17, 30),
+                            ),
+                        ],
+                        encoding: None,
+                    }),
+                    Span::new(7, 30),
+                ),
+            ],
+        };
+        
+        let result = analyzer.analyze(&program);
+        let errors = result.unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(&errors[0], SemanticError::UndefinedLabel { label, .. } if label == "missing"));
+    }
+    
+    #[test]
+    fn test_constants() {
+        let mut analyzer = LabelAnalyzer::new();
+        let program = Program {
+            items: vec![
+                Spanned::new(Item::Directive(Directive::Equ("KEY_A".to_string(), Expr::Number(4), false)), Span::new(0, 4)),
+                Spanned::new(Item::Directive(Directive::Equ("KEY_A".to_string(), Expr::Number(5), true)), Span::new(10, 14)),
+                Spanned::new(
+                    Item::Directive(Directive::Equ(
+                        "KEY_B".to_string(),
+                        Expr::binary(BinaryOp::Add, Expr::Symbol("KEY_A".to_string()), Expr::Symbol("KEY_Z".to_string())),
+                        false,
+                    )),
+                    Span::new(20, 24),
+                ),
+                Spanned::new(Item::Label("main".to_string()), Span::new(30, 35)),
+                Spanned::new(
+                    Item::Instruction(Instruction {
+                        mnemonic: Mnemonic::SLEEP,
+                        operands: vec![Spanned::new(Operand::Label("KEY_B".to_string()), Span::new(42, 47))],
+                        encoding: None,
+                    }),
+                    Span::new(36, 47),
+                ),
+            ],
+        };
+        
+        let errors = analyzer.analyze(&program).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(&errors[0], SemanticError::ConstantRedefinition { name, .. } if name == "KEY_A"));
+        assert!(matches!(&errors[1], SemanticError::UndefinedConstant { name, .. } if name == "KEY_Z"));
+    }
+    
+    #[test]
+    fn test_numeric_labels() {
+        let jump = |target: &str, at: usize| Spanned::new(
+            Item::Instruction(Instruction {
+                mnemonic: Mnemonic::SJMP,
+                operands: vec![Spanned::new(Operand::Label(target.to_string()), Span::new(at + 5, at + 7))],
+                encoding: None,
+            }),
+            Span::new(at, at + 7),
+        );
+        let mut analyzer = LabelAnalyzer::new();
+        let program = Program {
+            items: vec![
+                Spanned::new(Item::Label("main".to_string()), Span::new(0, 5)),
+                jump("1f", 10),
+                Spanned::new(Item::Label("1".to_string()), Span::new(20, 22)),
+                jump("1b", 30),
+                // Redefining a numeric label is allowed
+                Spanned::new(Item::Label("1".to_string()), Span::new(40, 42)),
+                jump("1b", 50),
+                jump("1f", 60),
+                jump("2b", 70),
+            ],
+        };
+        
+        let errors = analyzer.analyze(&program).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(&errors[0], SemanticError::UndefinedLabel { label, span, .. } if span.start == 65 && label == "1f"));
+        assert!(matches!(&errors[1], SemanticError::UndefinedLabel { label, span, .. } if span.start == 75 && label == "2b"));
+    }
 }
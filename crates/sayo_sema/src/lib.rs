@@ -1,81 +1,377 @@
 
+use sayo_ast::{Diagnostic, Severity, Span};
 use thiserror::Error;
 
+pub mod call_graph;
+pub mod cfg;
 pub mod checker;
+pub mod dataflow;
+pub mod keys;
 pub mod label_analyzer;
+pub mod lint;
+pub mod stack;
 
+pub use call_graph::{CallEdge, CallGraph, CallKind, Routine};
+pub use cfg::{BasicBlock, BlockId, Cfg, EdgeKind, Exit, Function};
 pub use checker::SemanticChecker;
+pub use dataflow::Dataflow;
+pub use keys::KeyAnalysis;
 pub use label_analyzer::{LabelAnalyzer, LabelTable};
+pub use lint::{Level, Lint, LintConfig};
+pub use stack::StackAnalysis;
 
 /// Semantic error types
 #[derive(Debug, Error, Clone, PartialEq)]
 pub enum SemanticError {
-    #[error("Immediate value {value} out of range for type {expected_type} at position {line}:{col}")]
+    #[error("Immediate value {value} out of range for type {expected_type}")]
     ImmediateOutOfRange {
         value: i64,
         expected_type: String,
-        line: usize,
-        col: usize,
+        span: Span,
     },
     
-    #[error("Cannot write to read-only register {register} at position {line}:{col}")]
+    #[error("Cannot write to read-only register {register}")]
     WriteToReadOnlyRegister {
         register: String,
-        line: usize,
-        col: usize,
+        span: Span,
     },
     
-    #[error("{instruction} requires {expected} operand(s), but {actual} provided at position {line}:{col}")]
+    #[error("{instruction} requires {expected} operand(s), but {actual} provided")]
     OperandCountMismatch {
         instruction: String,
         expected: usize,
         actual: usize,
-        line: usize,
-        col: usize,
+        span: Span,
     },
     
-    #[error("Invalid operand type for {instruction}: expected {expected}, got {actual} at position {line}:{col}")]
+    #[error("Invalid operand type for {instruction}: expected {expected}, got {actual}")]
     InvalidOperandType {
         instruction: String,
         expected: String,
         actual: String,
-        line: usize,
-        col: usize,
+        span: Span,
     },
     
-    #[error("Undefined label '{label}' at position {line}:{col}")]
+    #[error("Undefined label '{label}'")]
     UndefinedLabel {
         label: String,
-        line: usize,
-        col: usize,
+        span: Span,
     },
     
-    #[error("Directives and instructions cannot be mixed in the same section (between labels) at position {line}:{col}")]
+    #[error("Directives and instructions cannot be mixed in the same section (between labels)")]
     MixedDirectivesAndInstructions {
-        line: usize,
-        col: usize,
+        span: Span,
     },
     
-    #[error("Duplicate label definition '{label}' at position {line}:{col}")]
+    #[error("Duplicate label definition '{label}'")]
     DuplicateLabel {
         label: String,
-        line: usize,
-        col: usize,
+        span: Span,
+        /// The first definition, if it was a label or constant of this program
+        previous: Option<Span>,
     },
     
-    #[error("Local label '{label}' used without a preceding global label at position {line}:{col}")]
+    #[error("Redefinition of constant '{name}'")]
+    ConstantRedefinition {
+        name: String,
+        span: Span,
+        previous: Option<Span>,
+    },
+    
+    #[error("Undefined symbol '{name}' in constant definition")]
+    UndefinedConstant {
+        name: String,
+        span: Span,
+    },
+    
+    #[error("Local label '{label}' used without a preceding global label")]
     LocalLabelWithoutGlobal {
         label: String,
-        line: usize,
-        col: usize,
+        span: Span,
     },
     
-    #[error("Unsupported alignment .align {value} at position {line}:{col}. Only .align 1 or no alignment is supported.")]
+    #[error("Unsupported alignment .align {value}")]
     UnsupportedAlignment {
         value: i64,
-        line: usize,
-        col: usize,
+        span: Span,
+    },
+
+    #[error("{instruction} expects a {expected}, but {name} is a {actual}")]
+    HidNamespaceMismatch {
+        instruction: String,
+        name: String,
+        expected: String,
+        actual: String,
+        span: Span,
+    },
+
+    /// A lint set to `deny`
+    #[error("{0}")]
+    DeniedLint(SemanticWarning),
+}
+
+/// Semantic warnings: the program still assembles, but probably not as intended
+/// Each belongs to a lint (see `lint`), whose level may turn it off or into an error
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum SemanticWarning {
+    #[error("Unknown directive .{name} is ignored")]
+    UnknownDirective {
+        name: String,
+        span: Span,
+    },
+
+    #[error("Unknown lint '{name}'")]
+    UnknownLint {
+        name: String,
+        span: Span,
+    },
+
+    #[error("{instruction} operates on {instruction_bits} bits but {register} is {register_bits}-bit")]
+    WidthMismatch {
+        instruction: String,
+        register: String,
+        instruction_bits: u8,
+        register_bits: u8,
+        /// Whether the register is written (the first operand) rather than read
+        destination: bool,
+        span: Span,
+    },
+
+    #[error("Unreachable code")]
+    UnreachableCode {
+        /// The first instruction never executed
+        span: Span,
+        /// Number of instructions never executed from `span` on
+        count: usize,
+        /// The jump, return or exit before it, if any
+        after: Option<Span>,
+    },
+
+    #[error("{function} falls through into {}", .into.as_deref().unwrap_or("data"))]
+    FallThrough {
+        function: String,
+        /// The function it runs into, None for data
+        into: Option<String>,
+        /// The last instruction of the function
+        span: Span,
+    },
+
+    #[error("{function} returns with {depth} value(s) still pushed")]
+    StackUnbalanced {
+        function: String,
+        depth: u32,
+        span: Span,
+    },
+
+    #[error("POP in {function} pops more than the function pushed")]
+    StackUnderflow {
+        function: String,
+        span: Span,
     },
+
+    #[error("Stack depth in {function} is {} on one path and {} on another", .depths.0, .depths.1)]
+    StackMismatch {
+        function: String,
+        depths: (u32, u32),
+        /// The first instruction of the block the paths meet at
+        span: Span,
+    },
+
+    #[error("Recursive call to {function}; the stack depth has no bound")]
+    Recursion {
+        function: String,
+        /// The CALL that closes the cycle
+        span: Span,
+    },
+
+    #[error("{register} is read before it is written")]
+    ReadBeforeWrite {
+        register: String,
+        span: Span,
+    },
+
+    #[error("Value written to {register} is overwritten before it is read")]
+    DeadStore {
+        register: String,
+        span: Span,
+        /// The instruction overwriting it, when in the same block
+        overwritten: Option<Span>,
+    },
+
+    #[error("{key} may still be pressed when the script exits")]
+    UnreleasedKey {
+        key: String,
+        /// The exit
+        span: Span,
+        /// The press that leaves it down
+        pressed: Span,
+    },
+}
+
+impl SemanticError {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::ImmediateOutOfRange { span, .. }
+            | Self::WriteToReadOnlyRegister { span, .. }
+            | Self::OperandCountMismatch { span, .. }
+            | Self::InvalidOperandType { span, .. }
+            | Self::UndefinedLabel { span, .. }
+            | Self::MixedDirectivesAndInstructions { span }
+            | Self::DuplicateLabel { span, .. }
+            | Self::ConstantRedefinition { span, .. }
+            | Self::UndefinedConstant { span, .. }
+            | Self::LocalLabelWithoutGlobal { span, .. }
+            | Self::UnsupportedAlignment { span, .. }
+            | Self::HidNamespaceMismatch { span, .. } => *span,
+            Self::DeniedLint(warning) => warning.span(),
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::ImmediateOutOfRange { .. } => "E0101",
+            Self::WriteToReadOnlyRegister { .. } => "E0102",
+            Self::OperandCountMismatch { .. } => "E0103",
+            Self::InvalidOperandType { .. } => "E0104",
+            Self::UndefinedLabel { .. } => "E0105",
+            Self::MixedDirectivesAndInstructions { .. } => "E0106",
+            Self::DuplicateLabel { .. } => "E0107",
+            Self::ConstantRedefinition { .. } => "E0108",
+            Self::UndefinedConstant { .. } => "E0109",
+            Self::LocalLabelWithoutGlobal { .. } => "E0110",
+            Self::UnsupportedAlignment { .. } => "E0111",
+            Self::HidNamespaceMismatch { .. } => "E0112",
+            Self::DeniedLint(warning) => warning.code(),
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        if let Self::DeniedLint(warning) = self {
+            let mut diagnostic = warning.diagnostic();
+            diagnostic.severity = Severity::Error;
+            diagnostic.notes = vec![format!("lint `{}` is denied", warning.lint().name)];
+            return diagnostic;
+        }
+        let diagnostic = Diagnostic::error(self.code(), self.to_string()).with_span(self.span());
+        match self {
+            Self::DuplicateLabel { previous: Some(previous), .. }
+            | Self::ConstantRedefinition { previous: Some(previous), .. } => {
+                diagnostic.with_label(*previous, "first defined here")
+            }
+            Self::MixedDirectivesAndInstructions { .. } => {
+                diagnostic.with_help("put a label between the data directives and the instructions")
+            }
+            Self::LocalLabelWithoutGlobal { .. } => {
+                diagnostic.with_help("local labels (.name) belong to the preceding global label; define one first")
+            }
+            Self::UnsupportedAlignment { .. } => {
+                diagnostic.with_note("only .align 1 (no alignment) is supported")
+            }
+            _ => diagnostic,
+        }
+    }
+}
+
+impl SemanticWarning {
+    /// The lint that controls this warning's level
+    pub fn lint(&self) -> &'static Lint {
+        match self {
+            Self::UnknownDirective { .. } => &lint::UNKNOWN_DIRECTIVE,
+            Self::UnknownLint { .. } => &lint::UNKNOWN_LINT,
+            Self::WidthMismatch { .. } => &lint::WIDTH_MISMATCH,
+            Self::UnreachableCode { .. } => &lint::UNREACHABLE_CODE,
+            Self::FallThrough { .. } => &lint::FALL_THROUGH,
+            Self::StackUnbalanced { .. } | Self::StackUnderflow { .. } | Self::StackMismatch { .. } => &lint::STACK_BALANCE,
+            Self::Recursion { .. } => &lint::RECURSION,
+            Self::ReadBeforeWrite { .. } => &lint::READ_BEFORE_WRITE,
+            Self::DeadStore { .. } => &lint::DEAD_STORE,
+            Self::UnreleasedKey { .. } => &lint::UNRELEASED_KEY,
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            Self::UnknownDirective { span, .. }
+            | Self::UnknownLint { span, .. }
+            | Self::WidthMismatch { span, .. }
+            | Self::UnreachableCode { span, .. }
+            | Self::FallThrough { span, .. }
+            | Self::StackUnbalanced { span, .. }
+            | Self::StackUnderflow { span, .. }
+            | Self::StackMismatch { span, .. }
+            | Self::Recursion { span, .. }
+            | Self::ReadBeforeWrite { span, .. }
+            | Self::DeadStore { span, .. }
+            | Self::UnreleasedKey { span, .. } => *span,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::UnknownDirective { .. } => "W0101",
+            Self::UnknownLint { .. } => "W0102",
+            Self::WidthMismatch { .. } => "W0103",
+            Self::UnreachableCode { .. } => "W0104",
+            Self::FallThrough { .. } => "W0105",
+            Self::StackUnbalanced { .. } => "W0106",
+            Self::StackUnderflow { .. } => "W0107",
+            Self::StackMismatch { .. } => "W0108",
+            Self::Recursion { .. } => "W0109",
+            Self::ReadBeforeWrite { .. } => "W0110",
+            Self::DeadStore { .. } => "W0111",
+            Self::UnreleasedKey { .. } => "W0112",
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let name = self.lint().name;
+        let diagnostic = Diagnostic::warning(self.code(), self.to_string()).with_span(self.span());
+        let diagnostic = match self {
+            Self::WidthMismatch { register, instruction_bits, register_bits, destination: true, .. } => {
+                diagnostic.with_note(if instruction_bits > register_bits {
+                    format!("the value is truncated to {} bits", register_bits)
+                } else {
+                    format!("only the low {} bits of {} are written", instruction_bits, register)
+                })
+            }
+            Self::WidthMismatch { register, instruction_bits, register_bits, destination: false, .. } => {
+                diagnostic.with_note(format!("the upper {} bits read from {} are always zero, so the sign is never extended",
+                    instruction_bits.saturating_sub(*register_bits), register))
+            }
+            Self::UnreachableCode { count, after, .. } => {
+                let diagnostic = match after {
+                    Some(after) => diagnostic.with_label(*after, "execution never continues past this"),
+                    None => diagnostic,
+                };
+                diagnostic.with_note(format!("{} instruction(s) can never execute", count))
+            }
+            Self::FallThrough { .. } => {
+                diagnostic.with_help("end the function with RET or EXIT, or jump to the next label explicitly")
+            }
+            Self::StackUnbalanced { .. } => {
+                diagnostic.with_note("RET pops the last pushed value instead of the return address")
+            }
+            Self::StackUnderflow { .. } => {
+                diagnostic.with_note("the POP takes the caller's return address")
+            }
+            Self::StackMismatch { .. } => {
+                diagnostic.with_note("the paths that meet here have pushed a different number of values")
+            }
+            Self::ReadBeforeWrite { .. } => {
+                diagnostic.with_note("on at least one path from the program entry the register has not been set yet")
+            }
+            Self::DeadStore { overwritten: Some(overwritten), .. } => {
+                diagnostic.with_label(*overwritten, "overwritten here")
+            }
+            Self::UnreleasedKey { pressed, .. } => {
+                diagnostic.with_label(*pressed, "pressed here")
+                    .with_note("the key stays down on the host after the script ends")
+                    .with_help("release it on every path to this exit")
+            }
+            _ => diagnostic,
+        };
+        diagnostic.with_note(format!("lint `{}`; silence it with `; sayo: allow({})`", name, name))
+    }
 }
 
 /// Result type for semantic analysis
@@ -0,0 +1,282 @@
+/// Lints: advisory checks that warn by default instead of failing the build
+///
+/// Every lint has a name and a default level. `LintConfig` overrides the level from the
+/// command line (`-W`/`-A`/`-D name`, or `warnings` for every lint) and from comments in
+/// the source: `; sayo: allow(width-mismatch)` applies to the line it is on, or to the
+/// next line when the comment stands on a line of its own. Several lints may be listed
+/// (`; sayo: allow(a, b) deny(c)`).
+use sayo_ast::{FileId, SourceMap, Span};
+use std::collections::HashMap;
+use std::fmt;
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    /// Not reported
+    Allow,
+    /// Reported as a warning
+    Warn,
+    /// Reported as an error, failing the check
+    Deny,
+}
+
+impl Level {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "allow" => Some(Self::Allow),
+            "warn" => Some(Self::Warn),
+            "deny" => Some(Self::Deny),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Allow => write!(f, "allow"),
+            Self::Warn => write!(f, "warn"),
+            Self::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lint {
+    pub name: &'static str,
+    pub default_level: Level,
+    pub description: &'static str,
+}
+
+pub const UNKNOWN_DIRECTIVE: Lint = Lint {
+    name: "unknown-directive",
+    default_level: Level::Warn,
+    description: "directive the assembler does not know; it is ignored",
+};
+
+pub const UNKNOWN_LINT: Lint = Lint {
+    name: "unknown-lint",
+    default_level: Level::Warn,
+    description: "`; sayo: allow(...)` names a lint that does not exist",
+};
+
+pub const WIDTH_MISMATCH: Lint = Lint {
+    name: "width-mismatch",
+    default_level: Level::Warn,
+    description: "sized operation (`MOV8`, `ADD32`, `MOVSX16b`, ...) on a register of another width",
+};
+
+pub const UNREACHABLE_CODE: Lint = Lint {
+    name: "unreachable-code",
+    default_level: Level::Warn,
+    description: "instructions that can never execute (after `RET`, `JMP`, `EXIT`, `END`)",
+};
+
+pub const FALL_THROUGH: Lint = Lint {
+    name: "fall-through",
+    default_level: Level::Warn,
+    description: "function that runs into the next function or into data without `RET`/`EXIT`",
+};
+
+pub const STACK_BALANCE: Lint = Lint {
+    name: "stack-balance",
+    default_level: Level::Warn,
+    description: "`PUSH`/`POP` that do not balance on every path to `RET`",
+};
+
+pub const RECURSION: Lint = Lint {
+    name: "recursion",
+    default_level: Level::Warn,
+    description: "recursive `CALL`, for which no stack depth bound can be computed",
+};
+
+pub const READ_BEFORE_WRITE: Lint = Lint {
+    name: "read-before-write",
+    default_level: Level::Warn,
+    description: "general purpose register read before it is written on some path from the program entry",
+};
+
+pub const DEAD_STORE: Lint = Lint {
+    name: "dead-store",
+    default_level: Level::Warn,
+    description: "register write that is overwritten before it is read",
+};
+
+pub const UNRELEASED_KEY: Lint = Lint {
+    name: "unreleased-key",
+    default_level: Level::Warn,
+    description: "key pressed with PRESS_* and not released on some path to the end of the script",
+};
+
+/// Every lint, in documentation order
+pub const LINTS: &[Lint] = &[
+    UNKNOWN_DIRECTIVE, UNKNOWN_LINT, WIDTH_MISMATCH, UNREACHABLE_CODE, FALL_THROUGH, STACK_BALANCE, RECURSION,
+    READ_BEFORE_WRITE, DEAD_STORE, UNRELEASED_KEY,
+];
+
+/// Name that sets the level of every lint at once (`-D warnings`)
+pub const ALL_LINTS: &str = "warnings";
+
+pub fn find_lint(name: &str) -> Option<&'static Lint> {
+    LINTS.iter().find(|lint| lint.name == name)
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum LintConfigError {
+    #[error("Unknown lint '{0}'")]
+    UnknownLint(String),
+}
+
+/// A `; sayo: level(lint)` comment and the byte range of the lines it covers
+#[derive(Debug, Clone)]
+struct InlineLevel {
+    file: FileId,
+    start: usize,
+    end: usize,
+    lint: &'static str,
+    level: Level,
+}
+
+/// Lint levels in effect: defaults, command-line overrides, then inline comments
+#[derive(Debug, Clone, Default)]
+pub struct LintConfig {
+    levels: HashMap<&'static str, Level>,
+    inline: Vec<InlineLevel>,
+    /// Lint names in comments that do not exist, reported as `unknown-lint`
+    unknown: Vec<(String, Span)>,
+}
+
+impl LintConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the level of a lint, or of every lint for `warnings`
+    pub fn set(&mut self, name: &str, level: Level) -> Result<(), LintConfigError> {
+        if name == ALL_LINTS {
+            self.levels.extend(LINTS.iter().map(|lint| (lint.name, level)));
+            return Ok(());
+        }
+        let lint = find_lint(name).ok_or_else(|| LintConfigError::UnknownLint(name.to_string()))?;
+        self.levels.insert(lint.name, level);
+        Ok(())
+    }
+
+    /// Read the `; sayo: ...` comments of every source file
+    pub fn read_inline(&mut self, sources: &SourceMap) {
+        for (file, source) in sources.files() {
+            self.read_file(file, &source.text);
+        }
+    }
+
+    fn read_file(&mut self, file: FileId, text: &str) {
+        // Comments standing alone apply to the next line that is not blank
+        let mut pending: Vec<(&'static str, Level)> = Vec::new();
+        let mut offset = 0;
+        for line in text.split_inclusive('\n') {
+            let (start, end) = (offset, offset + line.trim_end().len());
+            offset += line.len();
+
+            let (code, comment) = split_comment(line);
+            let has_code = !code.trim().is_empty();
+            if has_code {
+                for (lint, level) in pending.drain(..) {
+                    self.inline.push(InlineLevel { file, start, end, lint, level });
+                }
+            }
+
+            let Some((comment_start, levels)) = comment.and_then(|(at, text)| Some((at, attribute(text)?))) else {
+                continue;
+            };
+            let span = Span::new(start + comment_start, end).with_file(file);
+            for (name, level) in levels {
+                match find_lint(name) {
+                    None => self.unknown.push((name.to_string(), span)),
+                    Some(lint) if has_code => self.inline.push(InlineLevel { file, start, end, lint: lint.name, level }),
+                    Some(lint) => pending.push((lint.name, level)),
+                }
+            }
+        }
+    }
+
+    /// Level of `lint` for a problem at `span`
+    pub fn level(&self, lint: &Lint, span: Span) -> Level {
+        self.inline.iter()
+            .rev()
+            .find(|i| i.lint == lint.name && i.file == span.file && (i.start..=i.end).contains(&span.start))
+            .map(|i| i.level)
+            .or_else(|| self.levels.get(lint.name).copied())
+            .unwrap_or(lint.default_level)
+    }
+
+    /// Lint names used in comments that do not exist, with the comment's span
+    pub fn unknown_lints(&self) -> &[(String, Span)] {
+        &self.unknown
+    }
+}
+
+/// Code and comment of a line, with the offset of the `;`; `;` inside strings does not start a comment
+fn split_comment(line: &str) -> (&str, Option<(usize, &str)>) {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ';' if !in_string => return (&line[..i], Some((i, &line[i + 1..]))),
+            _ => {}
+        }
+    }
+    (line, None)
+}
+
+/// Levels set by a `sayo: allow(a, b) deny(c)` comment, or None for other comments
+fn attribute(comment: &str) -> Option<Vec<(&str, Level)>> {
+    let mut rest = comment.trim().strip_prefix("sayo:")?.trim();
+    let mut levels = Vec::new();
+    while !rest.is_empty() {
+        let (level, after) = rest.split_once('(')?;
+        let (names, after) = after.split_once(')')?;
+        let level = Level::from_name(level.trim())?;
+        levels.extend(names.split(',').map(str::trim).filter(|n| !n.is_empty()).map(|name| (name, level)));
+        rest = after.trim_start_matches([' ', '\t', ',']);
+    }
+    Some(levels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels() {
+        let mut config = LintConfig::new();
+        let span = Span::new(0, 1);
+        assert_eq!(config.level(&UNKNOWN_DIRECTIVE, span), Level::Warn);
+
+        config.set(ALL_LINTS, Level::Deny).unwrap();
+        assert_eq!(config.level(&UNKNOWN_LINT, span), Level::Deny);
+        config.set("unknown-directive", Level::Allow).unwrap();
+        assert_eq!(config.level(&UNKNOWN_DIRECTIVE, span), Level::Allow);
+        assert_eq!(config.set("no-such-lint", Level::Warn), Err(LintConfigError::UnknownLint("no-such-lint".to_string())));
+    }
+
+    #[test]
+    fn test_inline_comments() {
+        let text = "main:\n    .weak foo ; sayo: allow(unknown-directive)\n    .weak bar\n\
+                    ; sayo: deny(unknown-directive, no-such-lint)\n\n    .weak baz\n    .weak qux\n";
+        let mut config = LintConfig::new();
+        config.read_inline(&SourceMap::with_root("main.s", text));
+
+        let at = |needle: &str| Span::new(text.find(needle).unwrap(), text.find(needle).unwrap() + needle.len());
+        assert_eq!(config.level(&UNKNOWN_DIRECTIVE, at(".weak foo")), Level::Allow);
+        assert_eq!(config.level(&UNKNOWN_DIRECTIVE, at(".weak bar")), Level::Warn);
+        // A comment on its own line covers the next line that is not blank
+        assert_eq!(config.level(&UNKNOWN_DIRECTIVE, at(".weak baz")), Level::Deny);
+        assert_eq!(config.level(&UNKNOWN_DIRECTIVE, at(".weak qux")), Level::Warn);
+
+        assert_eq!(config.unknown_lints().len(), 1);
+        assert_eq!(config.unknown_lints()[0].0, "no-such-lint");
+    }
+}
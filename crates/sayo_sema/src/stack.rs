@@ -0,0 +1,216 @@
+/// PUSH/POP stack analysis
+///
+/// Walks every function of a `Cfg` tracking how many entries it has pushed: each
+/// instruction moves the depth by its stack effect (`PUSH` adds one, `POP` removes one)
+/// and `CALL` adds the return address plus whatever the callee uses. A function must
+/// return with nothing left pushed, otherwise `RET` pops a pushed value instead of the
+/// return address. Every path must reach a block with the same depth, which also
+/// catches loops that push on every iteration.
+///
+/// Depths are counted in stack entries. Functions that are part of a recursive call
+/// cycle have no bound.
+use std::collections::HashMap;
+use sayo_ast::{ControlFlow, Item, Program, Span};
+use crate::cfg::{BlockId, Cfg, Exit};
+use crate::SemanticWarning;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Visit {
+    InProgress,
+    Done(Option<u32>),
+}
+
+/// Stack depths and balance problems of a program
+#[derive(Debug, Clone, Default)]
+pub struct StackAnalysis {
+    /// Deepest use of each function including its callees, in `Cfg::functions` order;
+    /// None when recursion makes it unbounded
+    depths: Vec<Option<u32>>,
+    warnings: Vec<SemanticWarning>,
+}
+
+/// Stack use of one function on its own
+struct Frame {
+    /// Deepest point reached without calls
+    local: u32,
+    /// Depth at each call with the return address pushed: (depth, callee function, span of the CALL)
+    calls: Vec<(u32, usize, Span)>,
+}
+
+impl StackAnalysis {
+    pub fn analyze(cfg: &Cfg, program: &Program) -> Self {
+        let mut analysis = Self::default();
+        let frames: Vec<Frame> = cfg.functions().iter()
+            .map(|function| analysis.frame(cfg, program, &function.name, function.entry))
+            .collect();
+
+        let mut visits = vec![None; frames.len()];
+        for function in 0..frames.len() {
+            analysis.depth_of(function, cfg, &frames, &mut visits);
+        }
+        analysis.depths = visits.into_iter()
+            .map(|visit| match visit {
+                Some(Visit::Done(depth)) => depth,
+                _ => None,
+            })
+            .collect();
+        analysis
+    }
+
+    /// Deepest use of the program: its entry function and everything it calls
+    /// None when recursion makes it unbounded
+    pub fn max_depth(&self) -> Option<u32> {
+        self.depths.first().copied().unwrap_or(Some(0))
+    }
+
+    /// Deepest use of each function including its callees, in `Cfg::functions` order
+    pub fn depths(&self) -> &[Option<u32>] {
+        &self.depths
+    }
+
+    pub fn warnings(&self) -> &[SemanticWarning] {
+        &self.warnings
+    }
+
+    /// Report once per location: blocks shared by several functions are walked for each
+    fn warn(&mut self, warning: SemanticWarning) {
+        if !self.warnings.iter().any(|w| w.span() == warning.span()) {
+            self.warnings.push(warning);
+        }
+    }
+
+    /// Walk the blocks of a function from its entry, checking depths agree on every path
+    fn frame(&mut self, cfg: &Cfg, program: &Program, function: &str, entry: BlockId) -> Frame {
+        let mut frame = Frame { local: 0, calls: Vec::new() };
+        let mut entered: HashMap<BlockId, u32> = HashMap::from([(entry, 0)]);
+        let mut worklist = vec![entry];
+
+        while let Some(id) = worklist.pop() {
+            let block = cfg.block(id);
+            let mut depth = entered[&id];
+            for &index in &block.instructions {
+                let Item::Instruction(instr) = &program.items[index].node else {
+                    continue;
+                };
+                let span = program.items[index].span;
+                let effect = instr.mnemonic.stack_effect();
+                match instr.mnemonic.control_flow() {
+                    ControlFlow::Call => {
+                        let callee = block.calls.iter()
+                            .find(|&&(at, _)| at == index)
+                            .and_then(|&(_, callee)| callee)
+                            .and_then(|callee| cfg.functions().iter().position(|f| f.entry == callee));
+                        let called = depth + effect as u32;
+                        match callee {
+                            Some(callee) => frame.calls.push((called, callee, span)),
+                            // Calls to constant addresses: count the return address only
+                            None => frame.local = frame.local.max(called),
+                        }
+                    }
+                    // Pops the return address, which is not part of this frame
+                    ControlFlow::Return => {}
+                    _ => match depth.checked_add_signed(effect.into()) {
+                        Some(after) => {
+                            depth = after;
+                            frame.local = frame.local.max(depth);
+                        }
+                        None => self.warn(SemanticWarning::StackUnderflow { function: function.to_string(), span }),
+                    },
+                }
+            }
+
+            if block.exits.contains(&Exit::Return) && depth != 0 {
+                self.warn(SemanticWarning::StackUnbalanced {
+                    function: function.to_string(),
+                    depth,
+                    span: program.items[block.last()].span,
+                });
+            }
+            for successor in cfg.successors(id) {
+                match entered.get(&successor) {
+                    None => {
+                        entered.insert(successor, depth);
+                        worklist.push(successor);
+                    }
+                    Some(&other) if other != depth => {
+                        let first = cfg.block(successor).instructions[0];
+                        self.warn(SemanticWarning::StackMismatch {
+                            function: function.to_string(),
+                            depths: (other.min(depth), other.max(depth)),
+                            span: program.items[first].span,
+                        });
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        frame
+    }
+
+    /// Depth of `function` including its callees, reporting recursive calls
+    fn depth_of(&mut self, function: usize, cfg: &Cfg, frames: &[Frame], visits: &mut Vec<Option<Visit>>) -> Option<u32> {
+        match visits[function] {
+            Some(Visit::Done(depth)) => return depth,
+            Some(Visit::InProgress) => return None,
+            None => {}
+        }
+        visits[function] = Some(Visit::InProgress);
+
+        let mut depth = Some(frames[function].local);
+        for &(at, callee, span) in &frames[function].calls {
+            if visits[callee] == Some(Visit::InProgress) {
+                self.warn(SemanticWarning::Recursion {
+                    function: cfg.functions()[callee].name.clone(),
+                    span,
+                });
+            }
+            let callee_depth = self.depth_of(callee, cfg, frames, visits);
+            depth = depth.zip(callee_depth).map(|(depth, callee_depth)| depth.max(at + callee_depth));
+        }
+
+        visits[function] = Some(Visit::Done(depth));
+        depth
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyze(text: &str) -> StackAnalysis {
+        let program = sayo_parser::parse(text).unwrap();
+        StackAnalysis::analyze(&Cfg::build(&program), &program)
+    }
+
+    #[test]
+    fn test_nested_depth() {
+        let analysis = analyze("main:\n    PUSH R0\n    CALL outer\n    POP R0\n    EXIT\n\
+                                outer:\n    PUSH R1\n    PUSH R2\n    CALL inner\n    POP R2\n    POP R1\n    RET\n\
+                                inner:\n    PUSH R3\n    POP R3\n    RET\n");
+        assert!(analysis.warnings().is_empty());
+        // main's R0, outer's return address, R1, R2, inner's return address, R3
+        assert_eq!(analysis.max_depth(), Some(6));
+        assert_eq!(analysis.depths(), &[Some(6), Some(4), Some(1)]);
+    }
+
+    #[test]
+    fn test_unbalanced_paths() {
+        let text = "main:\n    CALL f\n    EXIT\nf:\n    PUSH R0\n    JZ R0, .out\n    POP R0\n.out:\n    NOP\n    RET\n\
+                    g:\n    POP R1\n    RET\n";
+        let analysis = analyze(&text.replace("g:", ".type g, @function\ng:"));
+        let warnings = analysis.warnings();
+        assert_eq!(warnings.len(), 3);
+        assert!(matches!(&warnings[0], SemanticWarning::StackMismatch { function, depths: (0, 1), .. } if function == "f"));
+        assert!(matches!(&warnings[1], SemanticWarning::StackUnbalanced { depth: 1, .. }));
+        assert!(matches!(&warnings[2], SemanticWarning::StackUnderflow { function, .. } if function == "g"));
+    }
+
+    #[test]
+    fn test_recursion() {
+        let analysis = analyze("main:\n    CALL ping\n    EXIT\nping:\n    PUSH R0\n    CALL pong\n    POP R0\n    RET\n\
+                                pong:\n    CALL ping\n    RET\n");
+        assert_eq!(analysis.max_depth(), None);
+        assert_eq!(analysis.warnings().len(), 1);
+        assert!(matches!(&analysis.warnings()[0], SemanticWarning::Recursion { function, .. } if function == "ping"));
+    }
+}
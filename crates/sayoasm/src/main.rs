@@ -1,4 +1,6 @@
 use clap::Parser;
+use sayo_sema::lint::{self, Level, LintConfig};
+use sayo_sema::{CallGraph, Cfg, SemanticChecker, SemanticError, StackAnalysis};
 use std::fs;
 use std::path::PathBuf;
 
@@ -54,6 +56,102 @@ struct Cli {
     /// Output listing file path (default: <input>.lst)
     #[arg(long, value_name = "FILE")]
     listing_output: Option<PathBuf>,
+
+    /// Add a directory to the .include search path (may be repeated)
+    #[arg(short = 'I', long = "include", value_name = "DIR")]
+    include_paths: Vec<PathBuf>,
+
+    /// Define a constant for conditional assembly, NAME or NAME=VALUE (may be repeated);
+    /// a lint name (or `warnings`) instead denies that lint, like --deny
+    #[arg(short = 'D', long = "define", value_name = "NAME[=VALUE]", value_parser = parse_define_or_deny)]
+    defines: Vec<DefineOrDeny>,
+
+    /// Report a lint as a warning (`warnings` for every lint; may be repeated)
+    #[arg(short = 'W', long = "warn", value_name = "LINT", value_parser = parse_lint)]
+    warn: Vec<String>,
+
+    /// Do not report a lint (`warnings` for every lint; may be repeated)
+    #[arg(short = 'A', long = "allow", value_name = "LINT", value_parser = parse_lint)]
+    allow: Vec<String>,
+
+    /// Report a lint as an error and fail (`warnings` for every lint; may be repeated)
+    #[arg(long = "deny", value_name = "LINT", value_parser = parse_lint)]
+    deny: Vec<String>,
+
+    /// Keep JMP/SJMP/AJMP as written instead of picking the shortest form that reaches
+    /// (for cycle-exact code)
+    #[arg(long)]
+    no_relax: bool,
+
+    /// Write the control-flow graph in Graphviz DOT format
+    #[arg(long, value_name = "FILE")]
+    cfg: Option<PathBuf>,
+
+    /// Write the call graph: JSON if FILE ends in `.json`, Graphviz DOT otherwise
+    #[arg(long, value_name = "FILE")]
+    call_graph: Option<PathBuf>,
+}
+
+/// A `-D` argument: a constant definition or a denied lint
+#[derive(Clone)]
+enum DefineOrDeny {
+    Define(String, i64),
+    Deny(String),
+}
+
+/// Lint names contain `-`, which symbol names cannot, so only `-D warnings` is ambiguous;
+/// it denies every lint (define a symbol of that name with `-D warnings=1`)
+fn parse_define_or_deny(arg: &str) -> Result<DefineOrDeny, String> {
+    if let Ok(name) = parse_lint(arg) {
+        return Ok(DefineOrDeny::Deny(name));
+    }
+    parse_define(arg).map(|(name, value)| DefineOrDeny::Define(name, value))
+}
+
+fn parse_lint(arg: &str) -> Result<String, String> {
+    if arg == lint::ALL_LINTS || lint::find_lint(arg).is_some() {
+        Ok(arg.to_string())
+    } else {
+        let names: Vec<_> = lint::LINTS.iter().map(|lint| lint.name).collect();
+        Err(format!("unknown lint '{}' (known lints: {}, or {} for all)", arg, names.join(", "), lint::ALL_LINTS))
+    }
+}
+
+/// Lint levels from the command line; `warnings` applies first so that single lints override it
+fn lint_config(cli: &Cli) -> anyhow::Result<LintConfig> {
+    let denied = cli.defines.iter().filter_map(|arg| match arg {
+        DefineOrDeny::Deny(name) => Some(name),
+        DefineOrDeny::Define(..) => None,
+    });
+    let mut levels: Vec<(&String, Level)> = cli.allow.iter().map(|name| (name, Level::Allow))
+        .chain(cli.warn.iter().map(|name| (name, Level::Warn)))
+        .chain(cli.deny.iter().chain(denied).map(|name| (name, Level::Deny)))
+        .collect();
+    levels.sort_by_key(|(name, _)| name.as_str() != lint::ALL_LINTS);
+
+    let mut config = LintConfig::new();
+    for (name, level) in levels {
+        config.set(name, level)?;
+    }
+    Ok(config)
+}
+
+/// Parse a `-D` argument; the value defaults to 1 and may be decimal or 0x hex
+fn parse_define(arg: &str) -> Result<(String, i64), String> {
+    let (name, value) = arg.split_once('=').unwrap_or((arg, "1"));
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return Err(format!("invalid symbol name '{}'", name));
+    }
+    let (digits, negative) = match value.strip_prefix('-') {
+        Some(rest) => (rest, true),
+        None => (value, false),
+    };
+    let magnitude = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => digits.parse(),
+    }
+    .map_err(|_| format!("invalid value '{}' for '{}'", value, name))?;
+    Ok((name.to_string(), if negative { -magnitude } else { magnitude }))
 }
 
 fn main() -> anyhow::Result<()> {
@@ -62,25 +160,74 @@ fn main() -> anyhow::Result<()> {
     // Read input file with encoding detection
     let source = read_file_with_encoding(&cli.input)?;
 
-    // Parse assembly
+    // Parse assembly (including any .include'd files)
     println!("Parsing {}...", cli.input.display());
-    let program = match sayo_parser::parse(&source) {
-        Ok(prog) => prog,
-        Err(e) => {
-            eprintln!("Parse error: {:?}", e);
-            std::process::exit(1);
-        }
+    let mut sources = sayo_ast::SourceMap::with_root(&cli.input, source);
+    let mut lints = lint_config(&cli)?;
+    let options = sayo_parser::ParseOptions {
+        include_paths: cli.include_paths,
+        defines: cli.defines.into_iter()
+            .filter_map(|arg| match arg {
+                DefineOrDeny::Define(name, value) => Some((name, value)),
+                DefineOrDeny::Deny(_) => None,
+            })
+            .collect(),
     };
+    let parsed = sayo_parser::parse_sources(&mut sources, &options);
+    if !parsed.is_ok() {
+        for diagnostic in parsed.diagnostics() {
+            eprint!("{}", diagnostic.render(&sources));
+        }
+        eprintln!("{} parse error(s)", parsed.errors.len());
+        std::process::exit(1);
+    }
+    let program = parsed.program;
+
+    println!("Found {} items in {} file(s)", program.items.len(), sources.len());
 
-    println!("Found {} items", program.items.len());
+    // Semantic errors (e.g. a media key given to PRESS_GK) and lints denied by the user
+    lints.read_inline(&sources);
+    let mut checker = SemanticChecker::new().with_lints(lints);
+    let errors = checker.check(&program).err().unwrap_or_default();
+    for warning in checker.warnings() {
+        eprint!("{}", warning.diagnostic().render(&sources));
+    }
+    for error in &errors {
+        eprint!("{}", error.diagnostic().render(&sources));
+    }
+    if !errors.is_empty() {
+        let denied = errors.iter().filter(|error| matches!(error, SemanticError::DeniedLint(_))).count();
+        eprintln!("{} error(s), {} denied lint(s)", errors.len() - denied, denied);
+        std::process::exit(1);
+    }
+
+    let cfg = Cfg::build(&program);
+    match StackAnalysis::analyze(&cfg, &program).max_depth() {
+        Some(depth) => println!("Maximum stack depth: {} entries", depth),
+        None => println!("Maximum stack depth: unbounded (recursive calls)"),
+    }
+    if let Some(cfg_path) = &cli.cfg {
+        fs::write(cfg_path, cfg.to_dot(&program))?;
+        println!("Wrote control-flow graph to {}", cfg_path.display());
+    }
+    if let Some(graph_path) = &cli.call_graph {
+        let graph = CallGraph::build(&cfg, &program);
+        let text = match graph_path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => graph.to_json(&sources),
+            _ => graph.to_dot(),
+        };
+        fs::write(graph_path, text)?;
+        println!("Wrote call graph to {}", graph_path.display());
+    }
 
     // Assemble
     println!("Assembling...");
-    let assembler = sayo_assembler::Assembler::new(source.clone(), program);
+    let assembler = sayo_assembler::Assembler::with_sources(sources, program)
+        .with_branch_relaxation(!cli.no_relax);
     let output = match assembler.assemble() {
         Ok(out) => out,
-        Err(e) => {
-            eprintln!("Assembly error: {}", e);
+        Err(diagnostic) => {
+            eprint!("{}", diagnostic.render(assembler.sources()));
             std::process::exit(1);
         }
     };
@@ -0,0 +1,36 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Assemble `source` with the sayoasm binary; returns the output and the binary path
+fn assemble(name: &str, source: &str) -> (Output, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("sayoasm_cli_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join(format!("{}.s", name));
+    let binary = dir.join(format!("{}.bin", name));
+    fs::write(&input, source).unwrap();
+    let _ = fs::remove_file(&binary);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_sayoasm"))
+        .arg(&input)
+        .arg("-o")
+        .arg(&binary)
+        .output()
+        .unwrap();
+    (output, binary)
+}
+
+#[test]
+fn test_assembles() {
+    let (output, binary) = assemble("ok", "main:\n    PRESS_GK KEY_A\n    RELEASE_GK KEY_A\n    RET\n");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(binary.exists());
+}
+
+#[test]
+fn test_semantic_errors_fail_the_build() {
+    let (output, binary) = assemble("namespace", "main:\n    PRESS_GK MEDIA_VOL_UP\n    RELEASE_GK MEDIA_VOL_UP\n    RET\n");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("error[E0112]"));
+    assert!(!binary.exists());
+}